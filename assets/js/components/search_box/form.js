@@ -4,7 +4,7 @@ import SVG from 'react-inlinesvg'
 import searchIcon from '../../../svg/icon-search.svg'
 import InnerComponent from './index'
 
-const Component = ({ query }) => {
+const Component = ({ query, sort }) => {
   const [value, setValue] = useState(query)
 
   const onChange = (newValue) => {
@@ -15,11 +15,15 @@ const Component = ({ query }) => {
     (ev) => {
       ev.preventDefault()
 
-      const query = encodeURI(value.trim())
+      const query = encodeURIComponent(value.trim())
 
-      window.location = `/?q=${query}`
+      if (sort && sort !== 'newest') {
+        window.location = `/?q=${query}&sort=${encodeURIComponent(sort)}`
+      } else {
+        window.location = `/?q=${query}`
+      }
     },
-    [value]
+    [value, sort]
   )
 
   return (
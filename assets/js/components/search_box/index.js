@@ -2,7 +2,7 @@ import React, { useState, useRef, useCallback, useEffect } from 'react'
 
 import './index.css'
 
-const TAG_REGEX = /([-~]*)?([a-z\:\/]*)$/i
+const TAG_REGEX = /([-~]*)?([^\s]*)$/i
 
 const parseQuery = (text, caretIdx) => {
   let beforeCaretText = text.substring(0, caretIdx)
@@ -18,7 +18,7 @@ const parseQuery = (text, caretIdx) => {
 }
 
 const fetchTagSuggestions = (query) => {
-  const sanitizedQuery = encodeURI(query.trim())
+  const sanitizedQuery = encodeURIComponent(query.trim())
   const url = `/api/v1/tags/suggestions?q=${sanitizedQuery}`
 
   return fetch(url, {
@@ -165,8 +165,11 @@ const Component = ({
             <div
               key={tag.name}
               className={classNames}
+              style={tag.colour ? { borderLeftColor: tag.colour } : {}}
+              title={[tag.category, tag.description].filter(Boolean).join(': ')}
               onMouseDown={() => clickedSuggestion(idx)}
             >
+              {tag.alias ? <small>{tag.alias} &rarr; </small> : null}
               {tag.name} <small>({tag.upload_count})</small>
             </div>
           )
@@ -42,7 +42,7 @@ const Uploader = ({ handleSubmit }) => {
 
       const json = await response.json()
 
-      if (json['status'] == 'error') {
+      if (json['status']) {
         fileWithMeta.meta.error = json['reason']
         return {}
       }
@@ -6,6 +6,8 @@ import '../css/pages/profile.css'
 import '../css/pages/user-comments-page.css'
 import '../css/pages/tags-page.css'
 import '../css/pages/logs-page.css'
+import '../css/pages/tag-wiki-page.css'
+import '../css/pages/mass-edit-page.css'
 
 import React from 'react'
 import ReactDOM from 'react-dom'
@@ -22,6 +24,7 @@ import './lib/upload_tooltips'
 if (document.getElementById('search-box-form')) {
   let container = document.getElementById('search-box-form')
   let query = container.dataset.query || ''
+  let sort = container.dataset.sort || ''
 
   if (!query.length) {
     query = ''
@@ -29,6 +32,7 @@ if (document.getElementById('search-box-form')) {
 
   let props = {
     query,
+    sort,
   }
 
   ReactDOM.render(<SearchBox {...props} />, container)
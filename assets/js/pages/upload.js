@@ -88,6 +88,7 @@ const STATE = {
 const UploadPage = () => {
   const [files, setFiles] = useState([])
   const [state, setState] = useState(STATE.upload)
+  const [error, setError] = useState(null)
 
   const handleAfterUpload = (files) => {
     setFiles(files)
@@ -116,7 +117,18 @@ const UploadPage = () => {
           headers: {
             'Content-Type': 'application/json',
           },
-        }).then((response) => response.json())
+        }).then((response) =>
+          response
+            .json()
+            .catch(() => ({ reason: 'Server error' }))
+            .then((body) => {
+              if (!response.ok) {
+                throw new Error(`${file.meta.name}: ${body.reason}`)
+              }
+
+              return body
+            })
+        )
       } else {
         return null
       }
@@ -132,6 +144,7 @@ const UploadPage = () => {
       },
       function (err) {
         console.error(err)
+        setError(err.message)
         setState(STATE.error)
       }
     )
@@ -161,8 +174,8 @@ const UploadPage = () => {
     return (
       <div>
         <div>
-          An unknown error occured. Any successfully uploads will still be
-          processed.
+          {error || 'An unknown error occured.'} Any successfully uploads will
+          still be processed.
         </div>
         <button onClick={reset}>Retry</button>
       </div>
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+
+CREATE TABLE saved_searches (
+  id BIGSERIAL PRIMARY KEY,
+  user_id INTEGER REFERENCES users (id) ON DELETE CASCADE NOT NULL,
+  name TEXT NOT NULL,
+  query TEXT NOT NULL,
+  webhook_url TEXT,
+  last_seen_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+  last_notified_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+  created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+  updated_at TIMESTAMP NOT NULL DEFAULT current_timestamp
+);
+
+SELECT diesel_manage_updated_at('saved_searches');
+
+CREATE INDEX saved_searches_user_id_idx ON saved_searches(user_id);
@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+
+DROP TRIGGER IF EXISTS trigger_upload_comments_on_search_index_update ON upload_comments;
+DROP TRIGGER IF EXISTS trigger_uploads_on_search_index_update ON uploads;
+DROP FUNCTION IF EXISTS upload_comments_search_index_trigger();
+DROP FUNCTION IF EXISTS uploads_search_index_trigger();
+DROP FUNCTION IF EXISTS uploads_search_index(INTEGER, TEXT, TEXT);
+
+ALTER TABLE uploads
+DROP COLUMN IF EXISTS search_index;
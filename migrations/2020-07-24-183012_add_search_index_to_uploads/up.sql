@@ -0,0 +1,55 @@
+-- Your SQL goes here
+
+ALTER TABLE uploads
+ADD search_index tsvector NOT NULL DEFAULT '';
+
+-- Tags are weighted A, the description B and all comments on the upload C, so searches can be
+-- scoped to any of them with ts_filter().
+CREATE FUNCTION uploads_search_index(upload_id INTEGER, tag_string TEXT, description TEXT)
+RETURNS tsvector AS $$
+  SELECT setweight(to_tsvector('pg_catalog.english', coalesce(tag_string, '')), 'A')
+    || setweight(to_tsvector('pg_catalog.english', coalesce(description, '')), 'B')
+    || setweight(to_tsvector('pg_catalog.english', coalesce((
+      SELECT string_agg(upload_comments.comment, ' ')
+      FROM upload_comments
+      WHERE upload_comments.upload_id = $1
+    ), '')), 'C');
+$$ LANGUAGE SQL STABLE;
+
+CREATE FUNCTION uploads_search_index_trigger() RETURNS trigger AS $$
+BEGIN
+  NEW.search_index := uploads_search_index(NEW.id, NEW.tag_string, NEW.description);
+  RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER trigger_uploads_on_search_index_update
+BEFORE INSERT OR UPDATE OF tag_string, description ON uploads
+FOR EACH ROW EXECUTE PROCEDURE uploads_search_index_trigger();
+
+CREATE FUNCTION upload_comments_search_index_trigger() RETURNS trigger AS $$
+DECLARE
+  changed_upload_id INTEGER;
+BEGIN
+  IF TG_OP = 'DELETE' THEN
+    changed_upload_id := OLD.upload_id;
+  ELSE
+    changed_upload_id := NEW.upload_id;
+  END IF;
+
+  UPDATE uploads
+  SET search_index = uploads_search_index(uploads.id, uploads.tag_string, uploads.description)
+  WHERE uploads.id = changed_upload_id;
+
+  RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER trigger_upload_comments_on_search_index_update
+AFTER INSERT OR UPDATE OF comment OR DELETE ON upload_comments
+FOR EACH ROW EXECUTE PROCEDURE upload_comments_search_index_trigger();
+
+UPDATE uploads
+SET search_index = uploads_search_index(id, tag_string, description);
+
+CREATE INDEX index_uploads_on_search_index ON uploads USING gin (search_index);
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+
+CREATE TABLE tag_aliases (
+  id BIGSERIAL PRIMARY KEY,
+  antecedent_name TEXT NOT NULL UNIQUE,
+  consequent_name TEXT NOT NULL,
+  creator_id INTEGER REFERENCES users (id) NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+  updated_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+  CHECK (antecedent_name != consequent_name)
+);
+
+SELECT diesel_manage_updated_at('tag_aliases');
+
+CREATE INDEX tag_aliases_consequent_name_idx ON tag_aliases(consequent_name);
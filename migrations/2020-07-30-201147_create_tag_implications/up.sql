@@ -0,0 +1,14 @@
+-- Your SQL goes here
+
+CREATE TABLE tag_implications (
+  id BIGSERIAL PRIMARY KEY,
+  antecedent_name TEXT NOT NULL,
+  consequent_name TEXT NOT NULL,
+  creator_id INTEGER REFERENCES users (id) NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+  updated_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+  UNIQUE (antecedent_name, consequent_name),
+  CHECK (antecedent_name != consequent_name)
+);
+
+SELECT diesel_manage_updated_at('tag_implications');
@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+
+DROP TRIGGER IF EXISTS trigger_tags_on_category_insert ON tags;
+DROP FUNCTION IF EXISTS tags_category_trigger();
+DROP FUNCTION IF EXISTS tag_category_for(TEXT);
+
+ALTER TABLE tags
+DROP COLUMN IF EXISTS category_id;
+
+DROP TABLE IF EXISTS tag_categories;
@@ -0,0 +1,54 @@
+-- Your SQL goes here
+
+CREATE TABLE tag_categories (
+  id BIGSERIAL PRIMARY KEY,
+  name TEXT NOT NULL UNIQUE,
+  prefix TEXT NOT NULL UNIQUE,
+  display_order INTEGER NOT NULL DEFAULT 0,
+  colour TEXT NOT NULL DEFAULT '#797979',
+  created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+  updated_at TIMESTAMP NOT NULL DEFAULT current_timestamp
+);
+
+SELECT diesel_manage_updated_at('tag_categories');
+
+-- The groups that used to be hard-coded in tag_service::group_tags.
+INSERT INTO tag_categories (name, prefix, display_order, colour) VALUES
+  ('Communities', 'community', 0, '#2a9d8f'),
+  ('Spinners', 'spinner', 1, '#e76f51'),
+  ('Collaboration Video', 'cv', 2, '#6a4c93'),
+  ('Promo Video', 'pv', 3, '#8338ec'),
+  ('Solo Video', 'sv', 4, '#3a86ff'),
+  ('Editors', 'editor', 5, '#f4a261'),
+  ('Events', 'event', 6, '#e63946'),
+  ('Video Type', 'type', 7, '#457b9d'),
+  ('Organizers', 'organizer', 8, '#bc6c25'),
+  ('Teams', 'team', 9, '#2b9348');
+
+ALTER TABLE tags
+ADD category_id BIGINT REFERENCES tag_categories (id) ON DELETE SET NULL;
+
+-- A tag belongs to the category with the longest prefix of its name.
+CREATE FUNCTION tag_category_for(tag_name TEXT) RETURNS BIGINT AS $$
+  SELECT id
+  FROM tag_categories
+  WHERE left(tag_name, length(prefix)) = prefix
+  ORDER BY length(prefix) DESC, display_order
+  LIMIT 1;
+$$ LANGUAGE SQL STABLE;
+
+CREATE FUNCTION tags_category_trigger() RETURNS trigger AS $$
+BEGIN
+  NEW.category_id := tag_category_for(NEW.name);
+  RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER trigger_tags_on_category_insert
+BEFORE INSERT ON tags
+FOR EACH ROW EXECUTE PROCEDURE tags_category_trigger();
+
+UPDATE tags
+SET category_id = tag_category_for(name);
+
+CREATE INDEX index_tags_on_category_id ON tags (category_id);
@@ -0,0 +1,21 @@
+-- Your SQL goes here
+
+CREATE TABLE tag_revisions (
+  id BIGSERIAL PRIMARY KEY,
+  tag_id BIGINT REFERENCES tags (id) ON DELETE CASCADE NOT NULL,
+  body TEXT NOT NULL,
+  summary TEXT NOT NULL DEFAULT '',
+  editor_id INTEGER REFERENCES users (id),
+  created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+  updated_at TIMESTAMP NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX tag_revisions_tag_id_idx ON tag_revisions (tag_id, id DESC);
+
+SELECT diesel_manage_updated_at('tag_revisions');
+
+-- Existing descriptions become the first revision, with no known editor.
+INSERT INTO tag_revisions (tag_id, body, summary)
+SELECT id, description, 'Imported description'
+FROM tags
+WHERE description != '';
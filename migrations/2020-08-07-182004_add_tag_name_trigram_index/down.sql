@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX IF EXISTS index_tag_aliases_on_antecedent_name_trigram;
+DROP INDEX IF EXISTS index_tags_on_name_trigram;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+
+CREATE INDEX index_tags_on_name_trigram ON tags USING gin (name gin_trgm_ops);
+CREATE INDEX index_tag_aliases_on_antecedent_name_trigram ON tag_aliases USING gin (antecedent_name gin_trgm_ops);
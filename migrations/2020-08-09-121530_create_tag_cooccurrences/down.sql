@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+
+DROP MATERIALIZED VIEW IF EXISTS tag_cooccurrences;
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+
+-- How many completed uploads each pair of tags appears on together. Refreshed by the tag rebuild
+-- jobs rather than on every edit.
+CREATE MATERIALIZED VIEW tag_cooccurrences AS
+SELECT tag.name AS tag_name, related.name AS related_name, COUNT(*)::bigint AS count
+FROM uploads,
+  unnest(string_to_array(uploads.tag_string, ' ')) AS tag(name),
+  unnest(string_to_array(uploads.tag_string, ' ')) AS related(name)
+WHERE uploads.status = 2 AND tag.name != related.name
+GROUP BY tag.name, related.name;
+
+-- Needed to refresh the view concurrently.
+CREATE UNIQUE INDEX index_tag_cooccurrences_on_names ON tag_cooccurrences (tag_name, related_name);
+CREATE INDEX index_tag_cooccurrences_on_tag_name_count ON tag_cooccurrences (tag_name, count DESC);
@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX IF EXISTS index_uploads_on_uploader_user_id_created_at;
+
+UPDATE users SET daily_upload_limit = 1 WHERE daily_upload_limit IS NULL;
+
+ALTER TABLE users
+ALTER COLUMN daily_upload_limit SET DEFAULT 1,
+ALTER COLUMN daily_upload_limit SET NOT NULL;
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+
+-- The limit now defaults to one per role, set only for users with an override. The old default of
+-- 1 was never enforced, so it isn't kept as an override.
+ALTER TABLE users
+ALTER COLUMN daily_upload_limit DROP NOT NULL,
+ALTER COLUMN daily_upload_limit DROP DEFAULT;
+
+UPDATE users SET daily_upload_limit = NULL WHERE daily_upload_limit = 1;
+
+CREATE INDEX index_uploads_on_uploader_user_id_created_at ON uploads (uploader_user_id, created_at);
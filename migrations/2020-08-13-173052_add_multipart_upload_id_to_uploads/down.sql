@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE uploads
+DROP COLUMN IF EXISTS multipart_upload_id;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+-- Set while a pending upload is being sent to storage as a multipart upload.
+ALTER TABLE uploads
+ADD COLUMN multipart_upload_id TEXT;
@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+
+DROP TRIGGER IF EXISTS trigger_uploads_on_completed ON uploads;
+DROP FUNCTION IF EXISTS uploads_completed_at_trigger();
+
+DROP INDEX IF EXISTS index_uploads_on_completed_at;
+
+ALTER TABLE uploads
+DROP COLUMN IF EXISTS completed_at;
@@ -0,0 +1,25 @@
+-- Your SQL goes here
+
+ALTER TABLE uploads
+ADD completed_at TIMESTAMP;
+
+UPDATE uploads
+SET completed_at = updated_at
+WHERE status = 2;
+
+-- Uploads can take hours to be finalized and encoded after they're created, so saved searches
+-- look for uploads by when they were completed instead.
+CREATE FUNCTION uploads_completed_at_trigger() RETURNS trigger AS $$
+BEGIN
+  IF NEW.status = 2 AND NEW.completed_at IS NULL THEN
+    NEW.completed_at := clock_timestamp();
+  END IF;
+  RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER trigger_uploads_on_completed
+BEFORE INSERT OR UPDATE OF status ON uploads
+FOR EACH ROW EXECUTE PROCEDURE uploads_completed_at_trigger();
+
+CREATE INDEX index_uploads_on_completed_at ON uploads (completed_at);
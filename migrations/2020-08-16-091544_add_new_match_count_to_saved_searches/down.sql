@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE saved_searches
+DROP COLUMN IF EXISTS new_match_count;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+-- Counted by the saved search matcher, so listing saved searches doesn't have to run them all.
+ALTER TABLE saved_searches
+ADD new_match_count BIGINT NOT NULL DEFAULT 0;
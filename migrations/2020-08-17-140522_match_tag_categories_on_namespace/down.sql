@@ -0,0 +1,16 @@
+-- This file should undo anything in `up.sql`
+
+DROP TRIGGER IF EXISTS trigger_tag_categories_on_change ON tag_categories;
+DROP FUNCTION IF EXISTS tag_categories_recategorize_trigger();
+
+CREATE OR REPLACE FUNCTION tag_category_for(tag_name TEXT) RETURNS BIGINT AS $$
+  SELECT id
+  FROM tag_categories
+  WHERE left(tag_name, length(prefix)) = prefix
+  ORDER BY length(prefix) DESC, display_order
+  LIMIT 1;
+$$ LANGUAGE SQL STABLE;
+
+UPDATE tags
+SET category_id = tag_category_for(name)
+WHERE category_id IS DISTINCT FROM tag_category_for(name);
@@ -0,0 +1,30 @@
+-- Your SQL goes here
+
+-- A tag belongs to the category with the longest prefix that is a whole namespace of its name, so
+-- `spinner/foo` goes to `spinner` but `spinnerette/foo` doesn't.
+CREATE OR REPLACE FUNCTION tag_category_for(tag_name TEXT) RETURNS BIGINT AS $$
+  SELECT id
+  FROM tag_categories
+  WHERE left(tag_name, length(rtrim(prefix, '/')) + 1) = rtrim(prefix, '/') || '/'
+  ORDER BY length(rtrim(prefix, '/')) DESC, display_order
+  LIMIT 1;
+$$ LANGUAGE SQL STABLE;
+
+-- Moves existing tags whenever categories are created, deleted or their prefix or order changes.
+CREATE FUNCTION tag_categories_recategorize_trigger() RETURNS trigger AS $$
+BEGIN
+  UPDATE tags
+  SET category_id = tag_category_for(name)
+  WHERE category_id IS DISTINCT FROM tag_category_for(name);
+
+  RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER trigger_tag_categories_on_change
+AFTER INSERT OR UPDATE OF prefix, display_order OR DELETE ON tag_categories
+FOR EACH STATEMENT EXECUTE PROCEDURE tag_categories_recategorize_trigger();
+
+UPDATE tags
+SET category_id = tag_category_for(name)
+WHERE category_id IS DISTINCT FROM tag_category_for(name);
@@ -14,6 +14,14 @@ pub struct Paginated<T: Serialize> {
   pub total_count: i64,
 }
 
+/// A page of results from a cursor based listing, pass `next_cursor` back to get the next page.
+#[derive(Serialize)]
+pub struct CursorPaginated<T: Serialize> {
+  pub data: Vec<T>,
+  pub page_size: i64,
+  pub next_cursor: Option<String>,
+}
+
 pub struct Auth<'a> {
   pub api_token: &'a ApiToken,
   pub user: &'a User,
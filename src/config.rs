@@ -12,6 +12,84 @@ pub fn get_contributor_webhook_url() -> String {
     env::var("DISCORD_CONTRIBUTOR_WEBHOOK_URL").unwrap_or_default()
 }
 
+/// How often saved searches are checked for new matches to notify about, in seconds.
+pub fn get_saved_search_interval() -> u64 {
+    env::var("SAVED_SEARCH_INTERVAL_SECONDS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(900)
+}
+
+/// Hosts saved search webhooks may point at, comma-separated. Anything else is refused, so users
+/// can't make the server send requests into its own network.
+pub fn get_saved_search_webhook_hosts() -> Vec<String> {
+    get_list("SAVED_SEARCH_WEBHOOK_HOSTS", "discord.com,discordapp.com")
+}
+
+/// Which search backend matches free text, either `postgres` or `tantivy`.
+pub fn get_search_backend() -> String {
+    env::var("SEARCH_BACKEND").unwrap_or("postgres".to_owned())
+}
+
+/// Directory of the on-disk index used by the `tantivy` search backend.
+pub fn get_search_index_path() -> String {
+    env::var("SEARCH_INDEX_PATH").unwrap_or("search_index".to_owned())
+}
+
+fn get_list(key: &str, default: &str) -> Vec<String> {
+    env::var(key)
+        .unwrap_or(default.to_owned())
+        .split(',')
+        .map(|item| item.trim().to_lowercase())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// Characters allowed in tags besides letters and digits.
+pub fn get_tag_allowed_punctuation() -> String {
+    env::var("TAG_ALLOWED_PUNCTUATION").unwrap_or("_-/.'()!&+".to_owned())
+}
+
+/// Namespaces tags may use besides the prefixes of tag categories, comma-separated.
+pub fn get_tag_extra_namespaces() -> Vec<String> {
+    get_list("TAG_EXTRA_NAMESPACES", "")
+}
+
+/// Namespaces a new upload needs at least one tag from, comma-separated.
+pub fn get_tag_required_namespaces() -> Vec<String> {
+    get_list("TAG_REQUIRED_NAMESPACES", "type")
+}
+
+/// Tags that can't be used at all, comma-separated.
+pub fn get_tag_banned() -> Vec<String> {
+    get_list("TAG_BANNED", "tagme,untagged")
+}
+
+/// How many uploads users of a role can start in any 24 hours, unless they have an override.
+/// Read from e.g. `DAILY_UPLOAD_LIMIT_CONTRIBUTOR`.
+pub fn get_daily_upload_limit(role: &str, default: i64) -> i64 {
+    env::var(format!("DAILY_UPLOAD_LIMIT_{}", role.to_uppercase()))
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// How old a pending upload gets before it's considered abandoned and removed, in hours.
+pub fn get_pending_upload_max_age() -> i64 {
+    env::var("PENDING_UPLOAD_MAX_AGE_HOURS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(48)
+}
+
+/// How often abandoned pending uploads are looked for, in seconds.
+pub fn get_pending_upload_cleanup_interval() -> u64 {
+    env::var("PENDING_UPLOAD_CLEANUP_INTERVAL_SECONDS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(3600)
+}
+
 pub fn get_twitter_consumer_key() -> String {
     env::var("TWITTER_CONSUMER_KEY").unwrap_or_default()
 }
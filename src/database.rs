@@ -1,2 +1,36 @@
+use rocket::request::{self, FromRequest, Request};
+use rocket::{Outcome, Rocket, State};
+use rocket_contrib::databases::{r2d2, Poolable};
+
 #[rocket_contrib::database("spin_archive")]
 pub struct DatabaseConnection(diesel::PgConnection);
+
+/// The connection pool, for background jobs that run outside of requests. Jobs take a connection
+/// for each run instead of keeping one out of the pool for good.
+///
+/// Also a request guard, for routes that start a job which outlives the request.
+#[derive(Clone)]
+pub struct ConnectionPool(r2d2::Pool<<diesel::PgConnection as Poolable>::Manager>);
+
+impl ConnectionPool {
+    pub fn from_rocket(rocket: &Rocket) -> Option<ConnectionPool> {
+        rocket
+            .state::<DatabaseConnectionPool>()
+            .map(|pool| ConnectionPool(pool.0.clone()))
+    }
+
+    /// Gets a connection, waiting for one to be returned to the pool if they're all in use.
+    pub fn get(&self) -> Option<DatabaseConnection> {
+        self.0.get().ok().map(DatabaseConnection)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ConnectionPool {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<ConnectionPool, ()> {
+        let pool = request.guard::<State<DatabaseConnectionPool>>()?;
+
+        Outcome::Success(ConnectionPool(pool.0.clone()))
+    }
+}
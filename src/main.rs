@@ -5,9 +5,9 @@ extern crate diesel_migrations;
 #[macro_use]
 extern crate diesel;
 
+use std::collections::HashMap;
 use std::env;
 
-use lazy_static::lazy_static;
 use rocket::http::{Cookie, Cookies, RawStr};
 use rocket::request::FlashMessage;
 use rocket::response::Redirect;
@@ -25,71 +25,69 @@ mod models;
 mod routes;
 mod s3_client;
 mod schema;
+mod search;
 mod services;
 
-use database::DatabaseConnection;
+use database::{ConnectionPool, DatabaseConnection};
+use models::upload::Snippet;
 use models::upload_comment::RecentComment;
-use models::user::{get_user_by_username, User};
+use models::user::User;
+use search::WildcardExpansion;
 use template_utils::{BaseContext, Pagination, Ructe};
 
 embed_migrations!();
 
-#[rocket::get("/?<page>&<q>")]
+#[rocket::get("/?<page>&<q>&<sort>")]
 fn index(
     conn: DatabaseConnection,
     flash: Option<FlashMessage>,
     user: Option<&User>,
     page: Option<&RawStr>,
     q: Option<String>,
+    sort: Option<String>,
 ) -> Ructe {
-    lazy_static! {
-        static ref UPLOADER_REGEX: regex::Regex =
-            regex::Regex::new(r"(uploader:)([a-z_A-Z\d]*)\s?").unwrap();
-    }
-
     let current_page = page.unwrap_or("1".into()).parse::<i64>().unwrap_or(1);
     let per_page = 50;
-    let mut query = q.unwrap_or_default();
+    let facets_per_namespace = 15;
+    let query = q.unwrap_or_default();
 
-    // Check if the query has an `uploader:[USERNAME]` tag.
-    let mut uploader: Option<User> = None;
     let mut recent_comments: Vec<RecentComment> = Vec::default();
-
-    if !query.is_empty() {
-        match UPLOADER_REGEX.captures(&query) {
-            None => (),
-            Some(matches) => {
-                let full_match = &matches[0];
-                let username = &matches[2];
-
-                match get_user_by_username(&conn, &username) {
-                    Some(user) => uploader = Some(user),
-                    _ => (),
-                }
-
-                query = query.replace(full_match, "");
+    let mut search_error: Option<String> = None;
+    let mut sort = sort.unwrap_or_default();
+    let mut expansions: Vec<WildcardExpansion> = Vec::new();
+    let mut snippets: HashMap<i32, Vec<Snippet>> = HashMap::new();
+
+    let search_result = search::parse(&conn, &query, Some(sort.as_str()), user);
+
+    let (uploads, page_count, total_count, facets) = match search_result {
+        Ok(search_query) => {
+            sort = search_query.sort.to_string();
+            expansions = search_query.expansions.clone();
+
+            if search_query.is_empty() {
+                recent_comments = services::comment_service::get_recent_comments(&conn)
+                    .into_iter()
+                    .map(|i| i.into())
+                    .collect();
             }
-        }
-    } else {
-        recent_comments = services::comment_service::get_recent_comments(&conn)
-            .into_iter()
-            .map(|i| i.into())
-            .collect();
-    }
 
-    let (uploads, page_count, total_count) =
-        models::upload::index(&conn, current_page, per_page, &query, uploader);
+            let (uploads, page_count, total_count) =
+                models::upload::index(&conn, current_page, per_page, &search_query);
+            let facets = models::upload::facets(&conn, facets_per_namespace, &search_query);
 
-    let mut raw_tags: Vec<&str> = uploads
-        .iter()
-        .flat_map(|upload| upload.tag_string.split_whitespace().collect::<Vec<&str>>())
-        .collect();
+            let upload_ids = uploads.iter().map(|upload| upload.id).collect::<Vec<i32>>();
+            snippets = models::upload::search_snippets(&conn, &upload_ids, &search_query);
 
-    raw_tags.sort();
-    raw_tags.dedup();
+            (uploads, page_count, total_count, facets)
+        }
+        Err(err) => {
+            search_error = Some(err.to_string());
+            (Vec::new(), 0, 0, Vec::new())
+        }
+    };
 
-    let tags = services::tag_service::by_names(&conn, &raw_tags);
-    let (tag_groups, tags) = services::tag_service::group_tags(tags);
+    let tags = services::tag_service::facet_tags(&conn, &facets);
+    let (tag_groups, tags) = services::tag_service::group_tags(&conn, tags);
 
     let ctx = BaseContext::new(user, flash);
     let pagination = Pagination {
@@ -104,6 +102,10 @@ fn index(
         uploads,
         pagination,
         query,
+        sort,
+        search_error,
+        expansions,
+        snippets,
         tags,
         tag_groups
     ))
@@ -147,6 +149,38 @@ fn run_db_migrations(rocket: rocket::Rocket) -> Result<Rocket, Rocket> {
     }
 }
 
+/// Periodically counts new matches of saved searches and notifies their webhooks.
+fn start_saved_search_matcher(rocket: &Rocket) {
+    let pool = ConnectionPool::from_rocket(rocket).expect("No DB connection pool!");
+    let interval = std::time::Duration::from_secs(config::get_saved_search_interval());
+
+    std::thread::spawn(move || loop {
+        match pool.get() {
+            Some(conn) => services::saved_search_service::check_new_matches(&conn),
+            None => log::warn!("[saved_search_matcher] no DB connection available"),
+        }
+
+        std::thread::sleep(interval);
+    });
+}
+
+/// Periodically removes pending uploads that were never finalized.
+fn start_pending_upload_cleanup(rocket: &Rocket) {
+    let pool = ConnectionPool::from_rocket(rocket).expect("No DB connection pool!");
+    let interval = std::time::Duration::from_secs(config::get_pending_upload_cleanup_interval());
+
+    std::thread::spawn(move || loop {
+        match pool.get() {
+            Some(conn) => {
+                services::upload_cleanup_service::remove_abandoned_uploads(&conn);
+            }
+            None => log::warn!("[upload_cleanup] no DB connection available"),
+        }
+
+        std::thread::sleep(interval);
+    });
+}
+
 #[rocket::get("/log?<page>")]
 fn audit_log(conn: DatabaseConnection, user: Option<&User>, page: Option<&RawStr>) -> Ructe {
     let ctx = BaseContext::new(user, None);
@@ -171,6 +205,14 @@ fn main() {
             "DB Migrations",
             run_db_migrations,
         ))
+        .attach(rocket::fairing::AdHoc::on_launch(
+            "Saved Search Matcher",
+            start_saved_search_matcher,
+        ))
+        .attach(rocket::fairing::AdHoc::on_launch(
+            "Pending Upload Cleanup",
+            start_pending_upload_cleanup,
+        ))
         .mount(
             "/",
             rocket::routes![
@@ -193,6 +235,11 @@ fn main() {
                 routes::upload::log,
                 routes::upload::update,
                 routes::upload::upload,
+                routes::upload::start_multipart,
+                routes::upload::multipart,
+                routes::upload::multipart_part_urls,
+                routes::upload::complete_multipart,
+                routes::upload::abort_multipart,
                 routes::upload::create_comment,
                 routes::upload::edit_comment,
                 routes::upload::edit_comment_page,
@@ -201,6 +248,7 @@ fn main() {
                 routes::webhooks::video::webhook,
             ],
         )
+        .mount("/", routes::feeds::router())
         .mount("/api/v1", routes::api::router())
         .mount("/queue", routes::queue::router())
         .mount("/admin", routes::admin::router())
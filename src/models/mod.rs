@@ -1,6 +1,14 @@
 pub(crate) mod api_token;
 pub(crate) mod audit_log;
+pub(crate) mod saved_search;
+pub(crate) mod sql;
 pub(crate) mod tag;
+pub(crate) mod tag_alias;
+pub(crate) mod tag_category;
+pub(crate) mod tag_cooccurrence;
+pub(crate) mod tag_implication;
+pub(crate) mod tag_revision;
 pub(crate) mod upload;
 pub(crate) mod upload_comment;
+pub(crate) mod upload_query;
 pub(crate) mod user;
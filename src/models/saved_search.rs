@@ -0,0 +1,128 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use serde::{Deserialize, Serialize};
+
+use crate::models::user::User;
+use crate::schema::saved_searches;
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Identifiable, Clone)]
+#[table_name = "saved_searches"]
+pub struct SavedSearch {
+    pub id: i64,
+    pub user_id: i32,
+    pub name: String,
+    pub query: String,
+    pub webhook_url: Option<String>,
+    pub last_seen_at: NaiveDateTime,
+    pub last_notified_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+
+    /// Uploads matching since `last_seen_at`, as of the last time the saved search was checked.
+    pub new_match_count: i64,
+}
+
+impl SavedSearch {
+    pub fn search_url(&self) -> String {
+        format!("/user/settings/saved_searches/{}", self.id)
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "saved_searches"]
+pub struct NewSavedSearch {
+    pub user_id: i32,
+    pub name: String,
+    pub query: String,
+    pub webhook_url: Option<String>,
+}
+
+/// Inserts a new [`SavedSearch`] into the database.
+pub fn insert(conn: &PgConnection, saved_search: &NewSavedSearch) -> QueryResult<SavedSearch> {
+    saved_search
+        .insert_into(saved_searches::table)
+        .returning(saved_searches::all_columns)
+        .get_result(conn)
+}
+
+/// Deletes a [`SavedSearch`] belonging to the given user.
+pub fn delete(conn: &PgConnection, user_id: i32, saved_search_id: i64) -> QueryResult<usize> {
+    diesel::delete(
+        saved_searches::table
+            .filter(saved_searches::id.eq(saved_search_id))
+            .filter(saved_searches::user_id.eq(user_id)),
+    )
+    .execute(conn)
+}
+
+/// Gets a [`SavedSearch`] belonging to the given user.
+pub fn by_id(conn: &PgConnection, user_id: i32, saved_search_id: i64) -> Option<SavedSearch> {
+    saved_searches::table
+        .filter(saved_searches::id.eq(saved_search_id))
+        .filter(saved_searches::user_id.eq(user_id))
+        .first::<SavedSearch>(conn)
+        .ok()
+}
+
+/// Gets all saved searches for a given user_id.
+pub fn by_user(conn: &PgConnection, user_id: i32) -> Vec<SavedSearch> {
+    saved_searches::table
+        .filter(saved_searches::user_id.eq(user_id))
+        .order(saved_searches::name.asc())
+        .load::<SavedSearch>(conn)
+        .unwrap_or_default()
+}
+
+/// Gets all saved searches, along with the user who saved them.
+pub fn all_with_users(conn: &PgConnection) -> Vec<(SavedSearch, User)> {
+    use crate::schema::users;
+
+    saved_searches::table
+        .inner_join(users::table)
+        .select((saved_searches::all_columns, users::all_columns))
+        .load::<(SavedSearch, User)>(conn)
+        .unwrap_or_default()
+}
+
+/// Marks the results of a [`SavedSearch`] as seen up until `seen_at`.
+pub fn update_last_seen(
+    conn: &PgConnection,
+    saved_search_id: i64,
+    seen_at: NaiveDateTime,
+) -> QueryResult<usize> {
+    diesel::update(saved_searches::table.filter(saved_searches::id.eq(saved_search_id)))
+        .set((
+            saved_searches::last_seen_at.eq(seen_at),
+            saved_searches::new_match_count.eq(0),
+        ))
+        .execute(conn)
+}
+
+/// Stores how many uploads matched a [`SavedSearch`] since `last_seen_at`, unless its results
+/// were seen again while they were being counted.
+pub fn update_new_match_count(
+    conn: &PgConnection,
+    saved_search_id: i64,
+    last_seen_at: NaiveDateTime,
+    new_match_count: i64,
+) -> QueryResult<usize> {
+    diesel::update(
+        saved_searches::table
+            .filter(saved_searches::id.eq(saved_search_id))
+            .filter(saved_searches::last_seen_at.eq(last_seen_at)),
+    )
+    .set(saved_searches::new_match_count.eq(new_match_count))
+    .execute(conn)
+}
+
+/// Records that notifications were sent for uploads up until `notified_at`.
+pub fn update_last_notified(
+    conn: &PgConnection,
+    saved_search_id: i64,
+    notified_at: NaiveDateTime,
+) -> QueryResult<usize> {
+    diesel::update(saved_searches::table.filter(saved_searches::id.eq(saved_search_id)))
+        .set(saved_searches::last_notified_at.eq(notified_at))
+        .execute(conn)
+}
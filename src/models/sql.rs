@@ -0,0 +1,104 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use diesel::{
+    deserialize::QueryableByName,
+    pg::Pg,
+    prelude::*,
+    query_builder::{AstPass, QueryFragment, QueryId},
+    sql_types, PgConnection,
+};
+
+/// A value sent as a bind parameter in a [`SqlBuilder`] query.
+#[derive(Debug, Clone)]
+pub enum SqlValue {
+    SmallInt(i16),
+    Int(i32),
+    IntArray(Vec<i32>),
+    BigInt(i64),
+    Text(String),
+    TextArray(Vec<String>),
+    Date(NaiveDate),
+    Timestamp(NaiveDateTime),
+}
+
+#[derive(Debug, Clone)]
+enum SqlPart {
+    Sql(String),
+    Bind(SqlValue),
+}
+
+/// Raw SQL assembled at runtime, with every value sent as a bind parameter.
+///
+/// Diesel can't box `sql_query` with a variable number of binds, so this is used wherever a query
+/// is built from optional pieces.
+#[derive(Debug, Clone)]
+pub struct SqlBuilder {
+    parts: Vec<SqlPart>,
+}
+
+impl SqlBuilder {
+    pub fn new(sql: &str) -> SqlBuilder {
+        SqlBuilder {
+            parts: vec![SqlPart::Sql(sql.to_owned())],
+        }
+    }
+
+    /// Appends raw SQL, this must never contain user input.
+    pub fn push(&mut self, sql: &str) -> &mut SqlBuilder {
+        self.parts.push(SqlPart::Sql(sql.to_owned()));
+        self
+    }
+
+    /// Appends a bind parameter placeholder for `value`.
+    pub fn bind(&mut self, value: SqlValue) -> &mut SqlBuilder {
+        self.parts.push(SqlPart::Bind(value));
+        self
+    }
+
+    pub fn load<T: QueryableByName<Pg>>(&self, conn: &PgConnection) -> QueryResult<Vec<T>> {
+        conn.query_by_name(self)
+    }
+}
+
+impl QueryFragment<Pg> for SqlBuilder {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        for part in self.parts.iter() {
+            match part {
+                SqlPart::Sql(sql) => out.push_sql(sql),
+                SqlPart::Bind(SqlValue::SmallInt(value)) => {
+                    out.push_bind_param::<sql_types::SmallInt, _>(value)?
+                }
+                SqlPart::Bind(SqlValue::Int(value)) => {
+                    out.push_bind_param::<sql_types::Integer, _>(value)?
+                }
+                SqlPart::Bind(SqlValue::IntArray(value)) => {
+                    out.push_bind_param::<sql_types::Array<sql_types::Integer>, _>(value)?
+                }
+                SqlPart::Bind(SqlValue::BigInt(value)) => {
+                    out.push_bind_param::<sql_types::BigInt, _>(value)?
+                }
+                SqlPart::Bind(SqlValue::Text(value)) => {
+                    out.push_bind_param::<sql_types::Text, _>(value)?
+                }
+                SqlPart::Bind(SqlValue::TextArray(value)) => {
+                    out.push_bind_param::<sql_types::Array<sql_types::Text>, _>(value)?
+                }
+                SqlPart::Bind(SqlValue::Date(value)) => {
+                    out.push_bind_param::<sql_types::Date, _>(value)?
+                }
+                SqlPart::Bind(SqlValue::Timestamp(value)) => {
+                    out.push_bind_param::<sql_types::Timestamp, _>(value)?
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl QueryId for SqlBuilder {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
@@ -3,6 +3,7 @@ use diesel::prelude::*;
 use diesel::PgConnection;
 use serde::{Deserialize, Serialize};
 
+use crate::models::upload_query::TagFacet;
 use crate::schema::tags;
 
 #[derive(Debug, Serialize, Deserialize, Queryable, Identifiable, QueryableByName, Clone)]
@@ -14,6 +15,7 @@ pub struct Tag {
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
     pub upload_count: i32,
+    pub category_id: Option<i64>,
 }
 
 impl Tag {
@@ -36,6 +38,112 @@ pub fn insert(conn: &PgConnection, tag: &NewTag) -> QueryResult<usize> {
         .execute(conn)
 }
 
+/// Gets a tag by its name.
+pub fn by_name(conn: &PgConnection, tag_name: &str) -> Option<Tag> {
+    tags::table
+        .filter(tags::name.eq(tag_name))
+        .first::<Tag>(conn)
+        .ok()
+}
+
+/// Gets a tag by its id.
+pub fn by_id(conn: &PgConnection, tag_id: i64) -> Option<Tag> {
+    tags::table
+        .filter(tags::id.eq(tag_id))
+        .first::<Tag>(conn)
+        .ok()
+}
+
+/// Replaces a tag's wiki page.
+pub fn update_description(
+    conn: &PgConnection,
+    tag_id: i64,
+    description: &str,
+) -> QueryResult<usize> {
+    diesel::update(tags::table.filter(tags::id.eq(tag_id)))
+        .set(tags::description.eq(description))
+        .execute(conn)
+}
+
+/// Renames a tag, moving it to the category of its new name.
+pub fn rename(conn: &PgConnection, tag_id: i64, name: &str) -> QueryResult<Tag> {
+    use diesel::dsl::sql;
+    use diesel::sql_types::{BigInt, Nullable, Text};
+
+    diesel::update(tags::table.filter(tags::id.eq(tag_id)))
+        .set((
+            tags::name.eq(name),
+            tags::category_id.eq(sql::<Nullable<BigInt>>("tag_category_for(")
+                .bind::<Text, _>(name)
+                .sql(")")),
+        ))
+        .get_result(conn)
+}
+
+/// Sets the upload count of a tag to the number of completed uploads tagged with it.
+pub fn recount(conn: &PgConnection, tag_name: &str) -> QueryResult<usize> {
+    use diesel::sql_types::Text;
+
+    diesel::sql_query(
+        "UPDATE tags SET upload_count = (
+            SELECT COUNT(*) FROM uploads
+            WHERE uploads.status = 2 AND string_to_array(uploads.tag_string, ' ') @> ARRAY[tags.name]
+        ) WHERE tags.name = $1",
+    )
+    .bind::<Text, _>(tag_name)
+    .execute(conn)
+}
+
+/// Adds each change to the upload count of its tag, never going below zero.
+///
+/// Rows are locked in id order so concurrent edits touching the same tags can't deadlock.
+pub fn adjust_counts(conn: &PgConnection, names: &[String], changes: &[i32]) -> QueryResult<usize> {
+    use diesel::sql_types::{Array, Int4, Text};
+
+    diesel::sql_query(
+        "WITH locked AS (
+            SELECT id, name FROM tags WHERE name = ANY($1) ORDER BY id FOR UPDATE
+        )
+        UPDATE tags SET upload_count = GREATEST(tags.upload_count + delta.change, 0)
+        FROM locked, unnest($1, $2) AS delta(name, change)
+        WHERE tags.id = locked.id AND locked.name = delta.name",
+    )
+    .bind::<Array<Text>, _>(names)
+    .bind::<Array<Int4>, _>(changes)
+    .execute(conn)
+}
+
+/// A tag whose stored upload count doesn't match the number of completed uploads tagged with it.
+#[derive(Debug, QueryableByName)]
+pub struct TagCountDrift {
+    #[sql_type = "diesel::sql_types::Text"]
+    pub name: String,
+
+    #[sql_type = "diesel::sql_types::Int4"]
+    pub upload_count: i32,
+
+    #[sql_type = "diesel::sql_types::BigInt"]
+    pub true_count: i64,
+}
+
+/// Finds every tag whose upload count has drifted, largest drift first.
+pub fn count_drift(conn: &PgConnection) -> Vec<TagCountDrift> {
+    diesel::sql_query(
+        "SELECT tags.name, tags.upload_count, COALESCE(true_counts.true_count, 0) AS true_count
+        FROM tags
+        LEFT JOIN (
+            SELECT tag, COUNT(DISTINCT uploads.id) AS true_count
+            FROM uploads, unnest(string_to_array(tag_string, ' ')) AS tag
+            WHERE uploads.status = 2
+            GROUP BY tag
+        ) true_counts ON true_counts.tag = tags.name
+        WHERE tags.upload_count != COALESCE(true_counts.true_count, 0)
+        ORDER BY abs(tags.upload_count - COALESCE(true_counts.true_count, 0)) DESC, tags.name",
+    )
+    .load::<TagCountDrift>(conn)
+    .unwrap_or_default()
+}
+
 /// Gets tags by their corresponding name.
 pub fn by_names(conn: &PgConnection, tag_names: &Vec<&str>) -> Vec<Tag> {
     tags::table
@@ -45,17 +153,113 @@ pub fn by_names(conn: &PgConnection, tag_names: &Vec<&str>) -> Vec<Tag> {
         .unwrap_or_default()
 }
 
-/// Gets tags by their corresponding name.
-pub fn contains(conn: &PgConnection, prefix: &str, limit: i64) -> Vec<Tag> {
+/// A tag suggested for a partially typed tag, see [`suggest`].
+#[derive(Debug, QueryableByName)]
+pub struct TagSuggestion {
+    #[diesel(embed)]
+    pub tag: Tag,
+
+    /// The alias that matched, when the tag was found through one.
+    #[sql_type = "diesel::sql_types::Nullable<diesel::sql_types::Text>"]
+    pub alias: Option<String>,
+
+    /// 0 for prefix matches, 1 for matches right after a namespace, 2 for substring matches and
+    /// 3 for typos caught by trigram similarity.
+    #[sql_type = "diesel::sql_types::Int4"]
+    pub rank: i32,
+}
+
+/// Escapes `LIKE` wildcards so the text only matches itself.
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Suggests used tags for a partially typed tag. Aliases are matched as well, but suggest the tag
+/// they're aliased to. Prefix matches come first, then matches right after a namespace's `/`,
+/// then substring matches, then similar looking tags; each by upload count.
+pub fn suggest(
+    conn: &PgConnection,
+    query: &str,
+    namespace: Option<&str>,
+    limit: i64,
+) -> Vec<TagSuggestion> {
+    use diesel::sql_types::{BigInt, Text};
+
+    diesel::sql_query(
+        "WITH candidates AS (
+            SELECT tags.id AS tag_id, tags.name AS matched, NULL::text AS alias
+            FROM tags
+            WHERE tags.upload_count > 0
+            UNION ALL
+            SELECT tags.id, tag_aliases.antecedent_name, tag_aliases.antecedent_name
+            FROM tag_aliases
+            INNER JOIN tags ON tags.name = tag_aliases.consequent_name
+            WHERE tags.upload_count > 0
+        ), ranked AS (
+            SELECT DISTINCT ON (tag_id) tag_id, alias,
+                CASE
+                    WHEN matched LIKE $1 || '%' THEN 0
+                    WHEN matched LIKE '%/' || $1 || '%' THEN 1
+                    WHEN matched LIKE '%' || $1 || '%' THEN 2
+                    ELSE 3
+                END AS rank,
+                similarity(matched, $2) AS score
+            FROM candidates
+            WHERE matched LIKE '%' || $1 || '%' OR matched % $2
+            ORDER BY tag_id, rank, score DESC
+        )
+        SELECT tags.*, ranked.alias, ranked.rank
+        FROM ranked
+        INNER JOIN tags ON tags.id = ranked.tag_id
+        WHERE $3 = '' OR tags.name LIKE $3 || '/%'
+        ORDER BY ranked.rank, tags.upload_count DESC, ranked.score DESC, tags.name
+        LIMIT $4",
+    )
+    .bind::<Text, _>(escape_like(query))
+    .bind::<Text, _>(query)
+    .bind::<Text, _>(namespace.map(escape_like).unwrap_or_default())
+    .bind::<BigInt, _>(limit)
+    .load::<TagSuggestion>(conn)
+    .unwrap_or_default()
+}
+
+/// Gets the names of used tags matching a `LIKE` pattern, most used first.
+pub fn names_like(conn: &PgConnection, pattern: &str, limit: i64) -> Vec<String> {
     tags::table
-        .filter(tags::name.ilike(&format!("%{}%", prefix)))
+        .select(tags::name)
+        .filter(tags::name.like(pattern))
         .filter(tags::upload_count.gt(0))
-        .order(tags::upload_count.desc())
+        .order((tags::upload_count.desc(), tags::name.asc()))
         .limit(limit)
-        .load::<Tag>(conn)
+        .load::<String>(conn)
         .unwrap_or_default()
 }
 
+/// Gets the `limit` most used tags of each namespace, as facets over every completed upload.
+pub fn top_by_namespace(conn: &PgConnection, limit: i64) -> Vec<TagFacet> {
+    use diesel::sql_types::BigInt;
+
+    diesel::sql_query(
+        "SELECT tag, count FROM (
+            SELECT name AS tag,
+                upload_count::bigint AS count,
+                row_number() OVER (
+                    PARTITION BY CASE WHEN position('/' in name) > 0 THEN split_part(name, '/', 1) ELSE '' END
+                    ORDER BY upload_count DESC, name
+                ) AS tag_rank
+            FROM tags
+            WHERE upload_count > 0
+        ) ranked_tags
+        WHERE tag_rank <= $1
+        ORDER BY count DESC, tag",
+    )
+    .bind::<BigInt, _>(limit)
+    .load::<TagFacet>(conn)
+    .unwrap_or_default()
+}
+
 /// Gets all tags.
 pub fn all(conn: &PgConnection) -> Vec<Tag> {
     tags::table
@@ -0,0 +1,73 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use serde::{Deserialize, Serialize};
+
+use crate::models::user::User;
+use crate::schema::{tag_aliases, users};
+
+/// Rewrites the antecedent tag to the consequent tag whenever it's used.
+#[derive(Debug, Serialize, Deserialize, Queryable, Identifiable, Clone)]
+#[table_name = "tag_aliases"]
+pub struct TagAlias {
+    pub id: i64,
+    pub antecedent_name: String,
+    pub consequent_name: String,
+    pub creator_id: i32,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "tag_aliases"]
+pub struct NewTagAlias {
+    pub antecedent_name: String,
+    pub consequent_name: String,
+    pub creator_id: i32,
+}
+
+/// Inserts a new [`TagAlias`] into the database.
+pub fn insert(conn: &PgConnection, tag_alias: &NewTagAlias) -> QueryResult<TagAlias> {
+    tag_alias
+        .insert_into(tag_aliases::table)
+        .returning(tag_aliases::all_columns)
+        .get_result(conn)
+}
+
+/// Deletes a [`TagAlias`].
+pub fn delete(conn: &PgConnection, tag_alias_id: i64) -> QueryResult<usize> {
+    diesel::delete(tag_aliases::table.filter(tag_aliases::id.eq(tag_alias_id))).execute(conn)
+}
+
+/// Points every alias for `from` to `to` instead, so aliases never chain.
+pub fn retarget(conn: &PgConnection, from: &str, to: &str) -> QueryResult<usize> {
+    diesel::update(tag_aliases::table.filter(tag_aliases::consequent_name.eq(from)))
+        .set(tag_aliases::consequent_name.eq(to))
+        .execute(conn)
+}
+
+/// Gets the alias for a tag, if it has one.
+pub fn by_antecedent(conn: &PgConnection, antecedent_name: &str) -> Option<TagAlias> {
+    tag_aliases::table
+        .filter(tag_aliases::antecedent_name.eq(antecedent_name))
+        .first::<TagAlias>(conn)
+        .ok()
+}
+
+/// Gets the aliases for any of the given tags.
+pub fn by_antecedents(conn: &PgConnection, antecedent_names: &[String]) -> Vec<TagAlias> {
+    tag_aliases::table
+        .filter(tag_aliases::antecedent_name.eq_any(antecedent_names))
+        .load::<TagAlias>(conn)
+        .unwrap_or_default()
+}
+
+/// Gets all aliases along with the user who created them.
+pub fn all(conn: &PgConnection) -> Vec<(TagAlias, User)> {
+    tag_aliases::table
+        .inner_join(users::table)
+        .select((tag_aliases::all_columns, users::all_columns))
+        .order(tag_aliases::antecedent_name.asc())
+        .load::<(TagAlias, User)>(conn)
+        .unwrap_or_default()
+}
@@ -0,0 +1,67 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use serde::{Deserialize, Serialize};
+
+use crate::schema::tag_categories;
+
+/// Groups tags in the `prefix` namespace, like `spinner/foo` for `spinner`. Tags are assigned the
+/// category with the longest matching prefix by the database, when they're created and whenever
+/// categories change.
+#[derive(Debug, Serialize, Deserialize, Queryable, Identifiable, Clone)]
+#[table_name = "tag_categories"]
+pub struct TagCategory {
+    pub id: i64,
+    pub name: String,
+    pub prefix: String,
+    pub display_order: i32,
+    pub colour: String,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable, AsChangeset)]
+#[table_name = "tag_categories"]
+pub struct NewTagCategory {
+    pub name: String,
+    pub prefix: String,
+    pub display_order: i32,
+    pub colour: String,
+}
+
+/// Inserts a new [`TagCategory`] into the database.
+pub fn insert(conn: &PgConnection, tag_category: &NewTagCategory) -> QueryResult<TagCategory> {
+    tag_category
+        .insert_into(tag_categories::table)
+        .returning(tag_categories::all_columns)
+        .get_result(conn)
+}
+
+/// Updates a [`TagCategory`] with new column values.
+pub fn update(
+    conn: &PgConnection,
+    tag_category_id: i64,
+    tag_category: &NewTagCategory,
+) -> QueryResult<TagCategory> {
+    diesel::update(tag_categories::table.filter(tag_categories::id.eq(tag_category_id)))
+        .set(tag_category)
+        .returning(tag_categories::all_columns)
+        .get_result(conn)
+}
+
+/// Deletes a [`TagCategory`], its tags are left without a category.
+pub fn delete(conn: &PgConnection, tag_category_id: i64) -> QueryResult<usize> {
+    diesel::delete(tag_categories::table.filter(tag_categories::id.eq(tag_category_id)))
+        .execute(conn)
+}
+
+/// Gets all categories in display order.
+pub fn all(conn: &PgConnection) -> Vec<TagCategory> {
+    tag_categories::table
+        .order((
+            tag_categories::display_order.asc(),
+            tag_categories::name.asc(),
+        ))
+        .load::<TagCategory>(conn)
+        .unwrap_or_default()
+}
@@ -0,0 +1,44 @@
+use diesel::prelude::*;
+use diesel::PgConnection;
+
+use crate::models::tag::Tag;
+
+/// A tag that appears alongside another, along with how many completed uploads have both.
+#[derive(Debug, QueryableByName)]
+pub struct RelatedTag {
+    #[diesel(embed)]
+    pub tag: Tag,
+
+    #[sql_type = "diesel::sql_types::BigInt"]
+    pub count: i64,
+}
+
+/// Recomputes the `tag_cooccurrences` materialized view, without blocking reads of it.
+pub fn refresh(conn: &PgConnection) -> QueryResult<usize> {
+    diesel::sql_query("REFRESH MATERIALIZED VIEW CONCURRENTLY tag_cooccurrences").execute(conn)
+}
+
+/// Gets the tags that appear alongside `tag_name` most often, optionally only from one namespace.
+pub fn related(
+    conn: &PgConnection,
+    tag_name: &str,
+    namespace: Option<&str>,
+    limit: i64,
+) -> Vec<RelatedTag> {
+    use diesel::sql_types::{BigInt, Text};
+
+    diesel::sql_query(
+        "SELECT tags.*, tag_cooccurrences.count
+        FROM tag_cooccurrences
+        INNER JOIN tags ON tags.name = tag_cooccurrences.related_name
+        WHERE tag_cooccurrences.tag_name = $1
+            AND ($2 = '' OR starts_with(tag_cooccurrences.related_name, $2 || '/'))
+        ORDER BY tag_cooccurrences.count DESC, tags.name
+        LIMIT $3",
+    )
+    .bind::<Text, _>(tag_name)
+    .bind::<Text, _>(namespace.unwrap_or_default())
+    .bind::<BigInt, _>(limit)
+    .load::<RelatedTag>(conn)
+    .unwrap_or_default()
+}
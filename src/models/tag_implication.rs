@@ -0,0 +1,112 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use serde::{Deserialize, Serialize};
+
+use crate::models::user::User;
+use crate::schema::{tag_implications, users};
+
+/// Adds the consequent tag to every upload tagged with the antecedent tag.
+#[derive(Debug, Serialize, Deserialize, Queryable, Identifiable, Clone)]
+#[table_name = "tag_implications"]
+pub struct TagImplication {
+    pub id: i64,
+    pub antecedent_name: String,
+    pub consequent_name: String,
+    pub creator_id: i32,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "tag_implications"]
+pub struct NewTagImplication {
+    pub antecedent_name: String,
+    pub consequent_name: String,
+    pub creator_id: i32,
+}
+
+/// Inserts a new [`TagImplication`] into the database.
+pub fn insert(
+    conn: &PgConnection,
+    tag_implication: &NewTagImplication,
+) -> QueryResult<TagImplication> {
+    tag_implication
+        .insert_into(tag_implications::table)
+        .returning(tag_implications::all_columns)
+        .get_result(conn)
+}
+
+/// Deletes a [`TagImplication`].
+pub fn delete(conn: &PgConnection, tag_implication_id: i64) -> QueryResult<usize> {
+    diesel::delete(tag_implications::table.filter(tag_implications::id.eq(tag_implication_id)))
+        .execute(conn)
+}
+
+/// Gets the implication between two tags, if there is one.
+pub fn by_names(
+    conn: &PgConnection,
+    antecedent_name: &str,
+    consequent_name: &str,
+) -> Option<TagImplication> {
+    tag_implications::table
+        .filter(tag_implications::antecedent_name.eq(antecedent_name))
+        .filter(tag_implications::consequent_name.eq(consequent_name))
+        .first::<TagImplication>(conn)
+        .ok()
+}
+
+/// Gets the implications of any of the given tags.
+pub fn by_antecedents(conn: &PgConnection, antecedent_names: &[String]) -> Vec<TagImplication> {
+    tag_implications::table
+        .filter(tag_implications::antecedent_name.eq_any(antecedent_names))
+        .order(tag_implications::consequent_name.asc())
+        .load::<TagImplication>(conn)
+        .unwrap_or_default()
+}
+
+/// Moves every implication of or to `from` onto `to`, for when `from` is renamed or merged into
+/// `to`. Implications that would become duplicates or imply themselves are dropped.
+pub fn rename_tag(conn: &PgConnection, from: &str, to: &str) -> QueryResult<usize> {
+    use diesel::sql_types::Text;
+
+    diesel::sql_query(
+        "DELETE FROM tag_implications
+        WHERE (antecedent_name = $1 AND consequent_name = $2)
+            OR (antecedent_name = $2 AND consequent_name = $1)
+            OR (antecedent_name = $1 AND consequent_name IN (
+                SELECT consequent_name FROM tag_implications WHERE antecedent_name = $2
+            ))
+            OR (consequent_name = $1 AND antecedent_name IN (
+                SELECT antecedent_name FROM tag_implications WHERE consequent_name = $2
+            ))",
+    )
+    .bind::<Text, _>(from)
+    .bind::<Text, _>(to)
+    .execute(conn)?;
+
+    let renamed_antecedents =
+        diesel::update(tag_implications::table.filter(tag_implications::antecedent_name.eq(from)))
+            .set(tag_implications::antecedent_name.eq(to))
+            .execute(conn)?;
+
+    let renamed_consequents =
+        diesel::update(tag_implications::table.filter(tag_implications::consequent_name.eq(from)))
+            .set(tag_implications::consequent_name.eq(to))
+            .execute(conn)?;
+
+    Ok(renamed_antecedents + renamed_consequents)
+}
+
+/// Gets all implications along with the user who created them.
+pub fn all(conn: &PgConnection) -> Vec<(TagImplication, User)> {
+    tag_implications::table
+        .inner_join(users::table)
+        .select((tag_implications::all_columns, users::all_columns))
+        .order((
+            tag_implications::antecedent_name.asc(),
+            tag_implications::consequent_name.asc(),
+        ))
+        .load::<(TagImplication, User)>(conn)
+        .unwrap_or_default()
+}
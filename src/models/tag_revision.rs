@@ -0,0 +1,66 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use serde::{Deserialize, Serialize};
+
+use crate::models::user::User;
+use crate::schema::{tag_revisions, users};
+
+/// A saved version of a tag's wiki page.
+#[derive(Debug, Serialize, Deserialize, Queryable, Identifiable, Clone)]
+#[table_name = "tag_revisions"]
+pub struct TagRevision {
+    pub id: i64,
+    pub tag_id: i64,
+    pub body: String,
+    pub summary: String,
+    pub editor_id: Option<i32>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "tag_revisions"]
+pub struct NewTagRevision {
+    pub tag_id: i64,
+    pub body: String,
+    pub summary: String,
+    pub editor_id: Option<i32>,
+}
+
+/// Inserts a new [`TagRevision`] into the database.
+pub fn insert(conn: &PgConnection, tag_revision: &NewTagRevision) -> QueryResult<TagRevision> {
+    tag_revision
+        .insert_into(tag_revisions::table)
+        .returning(tag_revisions::all_columns)
+        .get_result(conn)
+}
+
+/// Gets a [`TagRevision`] by its id.
+pub fn by_id(conn: &PgConnection, tag_revision_id: i64) -> Option<TagRevision> {
+    tag_revisions::table
+        .filter(tag_revisions::id.eq(tag_revision_id))
+        .first::<TagRevision>(conn)
+        .ok()
+}
+
+/// Gets the revision saved right before the given one, if there is one.
+pub fn previous(conn: &PgConnection, tag_revision: &TagRevision) -> Option<TagRevision> {
+    tag_revisions::table
+        .filter(tag_revisions::tag_id.eq(tag_revision.tag_id))
+        .filter(tag_revisions::id.lt(tag_revision.id))
+        .order(tag_revisions::id.desc())
+        .first::<TagRevision>(conn)
+        .ok()
+}
+
+/// Gets every revision of a tag's wiki page along with its editor, newest first.
+pub fn by_tag(conn: &PgConnection, tag_id: i64) -> Vec<(TagRevision, Option<User>)> {
+    tag_revisions::table
+        .left_join(users::table)
+        .select((tag_revisions::all_columns, users::all_columns.nullable()))
+        .filter(tag_revisions::tag_id.eq(tag_id))
+        .order(tag_revisions::id.desc())
+        .load::<(TagRevision, Option<User>)>(conn)
+        .unwrap_or_default()
+}
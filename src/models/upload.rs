@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::io::Write;
 
 use chrono::{NaiveDate, NaiveDateTime};
@@ -10,9 +11,12 @@ use diesel::{
 };
 use serde::{Deserialize, Serialize};
 
-use crate::models::tag::Tag;
+use crate::models::sql::{SqlBuilder, SqlValue};
+use crate::models::tag::{self, Tag};
+use crate::models::upload_query::{Cursor, TagFacet, TextScope};
 use crate::models::user::{User, UserRole};
 use crate::schema::uploads;
+use crate::search::{self, SearchQuery};
 
 type AllColumns = (
     uploads::id,
@@ -83,7 +87,9 @@ impl std::fmt::Display for UploadStatus {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Queryable, Identifiable, AsChangeset, Associations)]
+#[derive(
+    Debug, Clone, Serialize, Deserialize, Queryable, Identifiable, AsChangeset, Associations,
+)]
 #[belongs_to(User, foreign_key = "uploader_user_id")]
 #[table_name = "uploads"]
 pub struct Upload {
@@ -297,6 +303,15 @@ pub fn get_by_file_id(conn: &PgConnection, search_file_id: &str) -> Option<Uploa
         .ok()
 }
 
+/// Gets the [`Upload`]s with the given ids, in id order.
+pub fn by_ids(conn: &PgConnection, ids: &[i32]) -> QueryResult<Vec<Upload>> {
+    uploads::table
+        .filter(uploads::id.eq_any(ids))
+        .select(ALL_COLUMNS)
+        .order(uploads::id.asc())
+        .load::<Upload>(conn)
+}
+
 /// Gets an [`Upload`] by `source`.
 pub fn get_by_source(conn: &PgConnection, source_url: &str) -> Option<Upload> {
     use crate::schema::uploads::dsl::*;
@@ -393,6 +408,104 @@ pub fn update_status(
         .get_result::<Upload>(conn)
 }
 
+/// Updates the `tag_string` of an [`Upload`].
+pub fn update_tag_string(
+    conn: &PgConnection,
+    upload_id: i32,
+    tag_string: &str,
+) -> QueryResult<Upload> {
+    diesel::update(uploads::table.filter(uploads::id.eq(upload_id)))
+        .set(uploads::tag_string.eq(tag_string))
+        .returning(ALL_COLUMNS)
+        .get_result::<Upload>(conn)
+}
+
+/// Gets up to `limit` uploads still pending since before `before`, with an id greater than
+/// `after_id`.
+pub fn pending_before(
+    conn: &PgConnection,
+    before: NaiveDateTime,
+    after_id: i32,
+    limit: i64,
+) -> Vec<Upload> {
+    uploads::table
+        .select(ALL_COLUMNS)
+        .filter(uploads::status.eq(UploadStatus::Pending))
+        .filter(uploads::created_at.lt(before))
+        .filter(uploads::id.gt(after_id))
+        .order(uploads::id.asc())
+        .limit(limit)
+        .load::<Upload>(conn)
+        .unwrap_or_default()
+}
+
+/// Removes an upload, as long as it's still pending.
+pub fn delete_pending(conn: &PgConnection, upload_id: i32) -> QueryResult<usize> {
+    diesel::delete(
+        uploads::table
+            .filter(uploads::id.eq(upload_id))
+            .filter(uploads::status.eq(UploadStatus::Pending)),
+    )
+    .execute(conn)
+}
+
+/// Gets the storage upload id of an upload that's being sent as a multipart upload.
+pub fn multipart_upload_id(conn: &PgConnection, upload_id: i32) -> Option<String> {
+    uploads::table
+        .select(uploads::multipart_upload_id)
+        .filter(uploads::id.eq(upload_id))
+        .first::<Option<String>>(conn)
+        .ok()
+        .flatten()
+}
+
+/// Sets or clears the storage upload id of a multipart upload.
+pub fn update_multipart_upload_id(
+    conn: &PgConnection,
+    upload_id: i32,
+    multipart_upload_id: Option<&str>,
+) -> QueryResult<usize> {
+    diesel::update(uploads::table.filter(uploads::id.eq(upload_id)))
+        .set(uploads::multipart_upload_id.eq(multipart_upload_id))
+        .execute(conn)
+}
+
+/// Gets up to `limit` uploads tagged with `tag_name` that have an id greater than `after_id`,
+/// in any status.
+pub fn get_with_tag(conn: &PgConnection, tag_name: &str, after_id: i32, limit: i64) -> Vec<Upload> {
+    use diesel::dsl::sql;
+    use diesel::sql_types::{Bool, Text};
+
+    uploads::table
+        .select(ALL_COLUMNS)
+        .filter(
+            sql::<Bool>("string_to_array(uploads.tag_string, ' ') @> ARRAY[")
+                .bind::<Text, _>(tag_name)
+                .sql("]"),
+        )
+        .filter(uploads::id.gt(after_id))
+        .order(uploads::id.asc())
+        .limit(limit)
+        .load::<Upload>(conn)
+        .unwrap_or_default()
+}
+
+/// Counts the uploads tagged with `tag_name`, in any status.
+pub fn count_with_tag(conn: &PgConnection, tag_name: &str) -> i64 {
+    use diesel::dsl::sql;
+    use diesel::sql_types::{Bool, Text};
+
+    uploads::table
+        .filter(
+            sql::<Bool>("string_to_array(uploads.tag_string, ' ') @> ARRAY[")
+                .bind::<Text, _>(tag_name)
+                .sql("]"),
+        )
+        .count()
+        .get_result(conn)
+        .unwrap_or(0)
+}
+
 /// Inserts a given [`PendingUpload`] into the database.
 pub fn insert_pending_upload(
     conn: &PgConnection,
@@ -427,189 +540,245 @@ pub fn get_pending_approval_uploads(conn: &PgConnection) -> Vec<(Upload, User)>
         .unwrap_or_default()
 }
 
-/// Index query for uploads, fetches completed uploads by the page number provided.
+/// Index query for uploads, fetches uploads matching the search query by the page number provided.
 ///
-/// Returns a tuple: (Vec<Upload>, page_count).
+/// Returns a tuple: (Vec<Upload>, page_count, total_count).
 pub fn index(
     conn: &PgConnection,
     page: i64,
     per_page: i64,
-    query: &str,
-    uploader: Option<User>,
+    query: &SearchQuery,
 ) -> (Vec<FullUpload>, i64, i64) {
-    use diesel::sql_types::*;
+    let full_uploads = search::upload_query(conn, query)
+        .paginate(page, per_page)
+        .load(conn)
+        .expect("Could not load index()");
+
+    let total_count = full_uploads
+        .first()
+        .map(|full_upload| full_upload.count)
+        .unwrap_or(0);
+
+    let total_pages = (total_count as f64 / per_page as f64).ceil() as i64;
+
+    (full_uploads, total_pages, total_count)
+}
+
+/// Keyset variant of [`index`], fetches the uploads matching the search query that come after
+/// `cursor`.
+///
+/// Returns a tuple: (Vec<Upload>, next_cursor).
+pub fn index_after(
+    conn: &PgConnection,
+    cursor: Option<Cursor>,
+    per_page: i64,
+    query: &SearchQuery,
+) -> (Vec<FullUpload>, Option<Cursor>) {
+    search::upload_query(conn, query)
+        .after(cursor)
+        .load_keyset(conn, per_page)
+        .expect("Could not load index_after()")
+}
+
+/// Counts the tags across every upload matching the search query, see
+/// [`UploadQuery::tag_facets`](crate::models::upload_query::UploadQuery::tag_facets).
+pub fn facets(conn: &PgConnection, per_namespace: i64, query: &SearchQuery) -> Vec<TagFacet> {
+    if query.is_empty() {
+        return tag::top_by_namespace(conn, per_namespace);
+    }
+
+    search::upload_query(conn, query)
+        .tag_facets(conn, per_namespace)
+        .expect("Could not load facets()")
+}
 
-    // It would be nice to use .to_boxed() here, but it is not available in Diesel yet.
-    // https://github.com/diesel-rs/diesel/pull/1975
-    let result = if !query.is_empty() {
-        if uploader.is_some() {
-            diesel::sql_query(
-                "
-                    WITH comment_counts AS (
-                    SELECT upload_comments.upload_id,
-                            count(*) comment_count
-                        FROM upload_comments
-                        GROUP BY upload_comments.upload_id
-                    ),
-                    view_counts AS (
-                        SELECT upload_views.upload_id,
-                            count(*) view_count
-                        FROM upload_views
-                        GROUP BY upload_views.upload_id
-                    )
-                    SELECT uploads.*,
-                        users.username AS uploader_username,
-                        users.role AS uploader_role,
-                        coalesce(comments.comment_count, 0) AS comment_count,
-                        coalesce(views.view_count, 0) AS view_count,
-                        count(*) over ()
-                    FROM uploads
-                    LEFT JOIN users ON (uploads.uploader_user_id = users.id)
-                    LEFT JOIN comment_counts comments ON comments.upload_id = uploads.id
-                    LEFT JOIN view_counts views ON views.upload_id = uploads.id
-                    WHERE uploads.status = $1
-                    AND (uploads.tag_index @@ plainto_tsquery($2) OR uploads.file_name ILIKE CONCAT('%', $2, '%'))
-                    AND uploads.uploader_user_id = $3
-                    GROUP BY (uploads.id, users.username, users.role, comments.comment_count, views.view_count)
-                    ORDER BY uploads.created_at desc
-                    LIMIT $4
-                    OFFSET $5
-                    ",
-            )
-            .bind::<BigInt, _>(2)
-            .bind::<Text, _>(query)
-            .bind::<Int4, _>(uploader.unwrap().id)
-            .bind::<BigInt, _>(per_page)
-            .bind::<BigInt, _>((page - 1) * per_page)
-            .load::<FullUpload>(conn)
-        } else {
-            diesel::sql_query(
-                "
-                    WITH comment_counts AS (
-                    SELECT upload_comments.upload_id,
-                            count(*) comment_count
-                        FROM upload_comments
-                        GROUP BY upload_comments.upload_id
-                    ),
-                    view_counts AS (
-                        SELECT upload_views.upload_id,
-                            count(*) view_count
-                        FROM upload_views
-                        GROUP BY upload_views.upload_id
-                    )
-                    SELECT uploads.*,
-                        users.username AS uploader_username,
-                        users.role AS uploader_role,
-                        coalesce(comments.comment_count, 0) AS comment_count,
-                        coalesce(views.view_count, 0) AS view_count,
-                        count(*) over ()
-                    FROM uploads
-                    LEFT JOIN users ON (uploads.uploader_user_id = users.id)
-                    LEFT JOIN comment_counts comments ON comments.upload_id = uploads.id
-                    LEFT JOIN view_counts views ON views.upload_id = uploads.id
-                    WHERE uploads.status = $1
-                    AND (uploads.tag_index @@ plainto_tsquery($2) OR uploads.file_name ILIKE CONCAT('%', $2, '%'))
-                    GROUP BY (uploads.id, users.username, users.role, comments.comment_count, views.view_count)
-                    ORDER BY uploads.created_at desc
-                    LIMIT $3
-                    OFFSET $4
-                ",
-            )
-            .bind::<BigInt, _>(2)
-            .bind::<Text, _>(query)
-            .bind::<BigInt, _>(per_page)
-            .bind::<BigInt, _>((page - 1) * per_page)
-            .load::<FullUpload>(conn)
+/// A description or comment excerpt matching a free text search.
+#[derive(Debug, Clone, QueryableByName)]
+pub struct Snippet {
+    #[sql_type = "sql_types::Integer"]
+    pub upload_id: i32,
+
+    /// Either `description` or `comment`.
+    #[sql_type = "sql_types::Text"]
+    pub source: String,
+
+    /// The excerpt, with matches wrapped in [`SNIPPET_START`] and [`SNIPPET_END`].
+    #[sql_type = "sql_types::Text"]
+    headline: String,
+}
+
+const SNIPPET_START: char = '\u{2}';
+const SNIPPET_END: char = '\u{3}';
+
+impl Snippet {
+    /// Renders the excerpt as escaped HTML, with matches wrapped in `<mark>`.
+    pub fn to_html(&self) -> String {
+        let mut html = String::with_capacity(self.headline.len());
+
+        for c in self.headline.chars() {
+            match c {
+                SNIPPET_START => html.push_str("<mark>"),
+                SNIPPET_END => html.push_str("</mark>"),
+                '&' => html.push_str("&amp;"),
+                '<' => html.push_str("&lt;"),
+                '>' => html.push_str("&gt;"),
+                '"' => html.push_str("&quot;"),
+                '\'' => html.push_str("&#39;"),
+                c => html.push(c),
+            }
         }
-    } else if uploader.is_some() {
-        diesel::sql_query(
-            "
-                WITH comment_counts AS (
-                SELECT upload_comments.upload_id,
-                        count(*) comment_count
-                    FROM upload_comments
-                    GROUP BY upload_comments.upload_id
-                ),
-                view_counts AS (
-                    SELECT upload_views.upload_id,
-                        count(*) view_count
-                    FROM upload_views
-                    GROUP BY upload_views.upload_id
-                )
-                SELECT uploads.*,
-                    users.username AS uploader_username,
-                    users.role AS uploader_role,
-                    coalesce(comments.comment_count, 0) AS comment_count,
-                    coalesce(views.view_count, 0) AS view_count,
-                    count(*) over ()
-                FROM uploads
-                LEFT JOIN users ON (uploads.uploader_user_id = users.id)
-                LEFT JOIN comment_counts comments ON comments.upload_id = uploads.id
-                LEFT JOIN view_counts views ON views.upload_id = uploads.id
-                WHERE uploads.status = $1
-                AND uploads.uploader_user_id = $2
-                GROUP BY (uploads.id, users.username, users.role, comments.comment_count, views.view_count)
-                ORDER BY uploads.created_at desc
-                LIMIT $3
-                OFFSET $4
-                ",
+
+        html
+    }
+}
+
+/// Gets highlighted excerpts of the descriptions and comments that matched the free text of a
+/// search scoped to them with `in:`, grouped by upload id.
+pub fn search_snippets(
+    conn: &PgConnection,
+    upload_ids: &[i32],
+    query: &SearchQuery,
+) -> HashMap<i32, Vec<Snippet>> {
+    let mut snippets: HashMap<i32, Vec<Snippet>> = HashMap::new();
+
+    if query.text.is_empty() || !(query.scope.description || query.scope.comments) {
+        return snippets;
+    }
+
+    let options = format!(
+        "StartSel=\"{}\", StopSel=\"{}\", MinWords=10, MaxWords=30, MaxFragments=2",
+        SNIPPET_START, SNIPPET_END
+    );
+
+    let mut sql = SqlBuilder::new("WITH search AS (SELECT plainto_tsquery('pg_catalog.english', ");
+    sql.bind(SqlValue::Text(query.text.clone()))
+        .push(") AS query)");
+
+    if query.scope.description {
+        sql.push(
+            " SELECT uploads.id AS upload_id,
+                'description' AS source,
+                ts_headline('pg_catalog.english', uploads.description, search.query, ",
         )
-        .bind::<BigInt, _>(2)
-        .bind::<Int4, _>(uploader.unwrap().id)
-        .bind::<BigInt, _>(per_page)
-        .bind::<BigInt, _>((page - 1) * per_page)
-        .load::<FullUpload>(conn)
-    } else {
-        println!("yes!");
-        diesel::sql_query(
-            "
-                WITH comment_counts AS (
-                SELECT upload_comments.upload_id,
-                        count(*) comment_count
-                    FROM upload_comments
-                    GROUP BY upload_comments.upload_id
-                ),
-                view_counts AS (
-                    SELECT upload_views.upload_id,
-                        count(*) view_count
-                    FROM upload_views
-                    GROUP BY upload_views.upload_id
-                )
-                SELECT uploads.*,
-                    users.username AS uploader_username,
-                    users.role AS uploader_role,
-                    coalesce(comments.comment_count, 0) AS comment_count,
-                    coalesce(views.view_count, 0) AS view_count,
-                    count(*) over ()
-                FROM uploads
-                LEFT JOIN users ON (uploads.uploader_user_id = users.id)
-                LEFT JOIN comment_counts comments ON comments.upload_id = uploads.id
-                LEFT JOIN view_counts views ON views.upload_id = uploads.id
-                WHERE uploads.status = $1
-                GROUP BY (uploads.id, users.username, users.role, comments.comment_count, views.view_count)
-                ORDER BY uploads.created_at desc
-                LIMIT $2
-                OFFSET $3
-            ",
+        .bind(SqlValue::Text(options.clone()))
+        .push(
+            ") AS headline
+            FROM uploads, search
+            WHERE uploads.id = ANY(",
         )
-        .bind::<BigInt, _>(2)
-        .bind::<BigInt, _>(per_page)
-        .bind::<BigInt, _>((page - 1) * per_page)
-        .load::<FullUpload>(conn)
-    };
-
-    match result.unwrap() {
-        full_uploads => {
-            let total_count = full_uploads
-                .first()
-                .map(|full_upload| full_upload.count)
-                .unwrap_or(0);
-
-            let total_pages = (total_count as f64 / per_page as f64).ceil() as i64;
-
-            (full_uploads, total_pages, total_count)
-        }
+        .bind(SqlValue::IntArray(upload_ids.to_vec()))
+        .push(") AND to_tsvector('pg_catalog.english', uploads.description) @@ search.query");
     }
+
+    if query.scope.description && query.scope.comments {
+        sql.push(" UNION ALL");
+    }
+
+    if query.scope.comments {
+        sql.push(
+            " SELECT upload_comments.upload_id AS upload_id,
+                'comment' AS source,
+                ts_headline('pg_catalog.english', upload_comments.comment, search.query, ",
+        )
+        .bind(SqlValue::Text(options))
+        .push(
+            ") AS headline
+            FROM upload_comments, search
+            WHERE upload_comments.upload_id = ANY(",
+        )
+        .bind(SqlValue::IntArray(upload_ids.to_vec()))
+        .push(") AND to_tsvector('pg_catalog.english', upload_comments.comment) @@ search.query");
+    }
+
+    for snippet in sql
+        .load::<Snippet>(conn)
+        .expect("Could not load search_snippets()")
+    {
+        snippets.entry(snippet.upload_id).or_default().push(snippet);
+    }
+
+    snippets
+}
+
+/// Gets the ids of uploads whose `search_index` matches free text, best match first.
+pub fn rank_by_text(conn: &PgConnection, text: &str, scope: TextScope, limit: i64) -> Vec<i32> {
+    #[derive(QueryableByName)]
+    struct RankedUpload {
+        #[sql_type = "sql_types::Integer"]
+        id: i32,
+    }
+
+    diesel::sql_query(
+        "
+            SELECT uploads.id
+            FROM uploads, plainto_tsquery('pg_catalog.english', $1) query
+            WHERE ts_filter(uploads.search_index, $2::\"char\"[]) @@ query
+            ORDER BY ts_rank(ts_filter(uploads.search_index, $2::\"char\"[]), query) DESC,
+                uploads.id DESC
+            LIMIT $3
+        ",
+    )
+    .bind::<sql_types::Text, _>(text)
+    .bind::<sql_types::Text, _>(scope.weights())
+    .bind::<sql_types::BigInt, _>(limit)
+    .load::<RankedUpload>(conn)
+    .expect("Could not rank_by_text()")
+    .into_iter()
+    .map(|ranked_upload| ranked_upload.id)
+    .collect()
+}
+
+/// The searchable text of an upload, as fed to search backends with their own index.
+#[derive(Debug, Clone, QueryableByName)]
+pub struct SearchDocument {
+    #[sql_type = "sql_types::Integer"]
+    pub id: i32,
+
+    #[sql_type = "sql_types::Nullable<sql_types::Text>"]
+    pub file_name: Option<String>,
+
+    #[sql_type = "sql_types::Text"]
+    pub tag_string: String,
+
+    #[sql_type = "sql_types::Text"]
+    pub description: String,
+
+    /// Every comment on the upload, joined by newlines.
+    #[sql_type = "sql_types::Text"]
+    pub comments: String,
+}
+
+const SEARCH_DOCUMENT_SQL: &'static str = "
+    SELECT uploads.id,
+        uploads.file_name,
+        uploads.tag_string,
+        uploads.description,
+        coalesce(string_agg(upload_comments.comment, E'\\n'), '') AS comments
+    FROM uploads
+    LEFT JOIN upload_comments ON upload_comments.upload_id = uploads.id
+    WHERE uploads.status != 4";
+
+/// Gets the [`SearchDocument`]s of up to `limit` uploads with an id greater than `after_id`.
+pub fn search_documents(conn: &PgConnection, after_id: i32, limit: i64) -> Vec<SearchDocument> {
+    diesel::sql_query(format!(
+        "{} AND uploads.id > $1 GROUP BY uploads.id ORDER BY uploads.id LIMIT $2",
+        SEARCH_DOCUMENT_SQL
+    ))
+    .bind::<sql_types::Integer, _>(after_id)
+    .bind::<sql_types::BigInt, _>(limit)
+    .load::<SearchDocument>(conn)
+    .expect("Could not load search_documents()")
+}
+
+/// Gets the [`SearchDocument`] of an upload, unless it was deleted.
+pub fn search_document(conn: &PgConnection, upload_id: i32) -> Option<SearchDocument> {
+    diesel::sql_query(format!(
+        "{} AND uploads.id = $1 GROUP BY uploads.id",
+        SEARCH_DOCUMENT_SQL
+    ))
+    .bind::<sql_types::Integer, _>(upload_id)
+    .get_result::<SearchDocument>(conn)
+    .ok()
 }
 
 pub fn get_upload_count_by_user_id(conn: &PgConnection, user_id: i32) -> i64 {
@@ -623,6 +792,23 @@ pub fn get_upload_count_by_user_id(conn: &PgConnection, user_id: i32) -> i64 {
         .unwrap_or_default()
 }
 
+/// Counts the uploads a user has started since `since`, in any status, along with when the oldest
+/// of them was started.
+pub fn started_since(
+    conn: &PgConnection,
+    user_id: i32,
+    since: NaiveDateTime,
+) -> (i64, Option<NaiveDateTime>) {
+    use diesel::dsl::{count_star, min};
+
+    uploads::table
+        .select((count_star(), min(uploads::created_at)))
+        .filter(uploads::uploader_user_id.eq(user_id))
+        .filter(uploads::created_at.gt(since))
+        .first::<(i64, Option<NaiveDateTime>)>(conn)
+        .unwrap_or_default()
+}
+
 pub fn update_md5(conn: &PgConnection, file_id: &str, md5: &str) -> QueryResult<usize> {
     diesel::update(uploads::table.filter(uploads::file_id.eq(file_id)))
         .set(uploads::md5_hash.eq(md5))
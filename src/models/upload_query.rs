@@ -0,0 +1,683 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use diesel::{sql_types, PgConnection, QueryResult};
+use serde::Serialize;
+
+use crate::models::sql::{SqlBuilder, SqlValue};
+use crate::models::upload::{FullUpload, UploadStatus};
+
+/// Sort orders for upload listings.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sort {
+    Newest,
+    Oldest,
+
+    /// Oldest `original_upload_date` first, undated uploads last.
+    OriginalDate,
+    OriginalDateDesc,
+    MostViewed,
+    MostCommented,
+    Largest,
+
+    /// Shuffled by the given seed, so the order stays the same across pages.
+    Random(String),
+
+    /// In the order of the given upload ids, as ranked by the search backend. Uploads that
+    /// aren't in the list come last.
+    Relevance(Vec<i32>),
+}
+
+impl Default for Sort {
+    fn default() -> Sort {
+        Sort::Newest
+    }
+}
+
+/// Optional upload fields that can be checked for presence.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum HasField {
+    Source,
+    Description,
+    Date,
+}
+
+/// Which fields free text is matched against, set with `in:` in search queries.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TextScope {
+    /// Tags and the original file name.
+    pub tags: bool,
+    pub description: bool,
+    pub comments: bool,
+}
+
+impl Default for TextScope {
+    fn default() -> TextScope {
+        TextScope {
+            tags: true,
+            description: false,
+            comments: false,
+        }
+    }
+}
+
+impl TextScope {
+    /// The `uploads.search_index` weights to match, as a `"char"[]` literal.
+    ///
+    /// Tags are weighted `A`, the description `B` and comments `C`.
+    pub fn weights(&self) -> String {
+        let weights = [
+            (self.tags, "a"),
+            (self.description, "b"),
+            (self.comments, "c"),
+        ]
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, weight)| *weight)
+        .collect::<Vec<&str>>();
+
+        format!("{{{}}}", weights.join(","))
+    }
+}
+
+/// A single condition that an upload must match.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Status(UploadStatus),
+
+    /// Free text matched against the fields in `scope`.
+    Text {
+        text: String,
+        scope: TextScope,
+    },
+
+    /// Upload must not have this tag.
+    ExcludeTag(String),
+
+    /// Upload must have at least one of these tags.
+    AnyTag(Vec<String>),
+
+    Uploader {
+        username: String,
+        negated: bool,
+    },
+
+    /// Substring match against the upload source URL.
+    Source {
+        value: String,
+        negated: bool,
+    },
+
+    Has {
+        field: HasField,
+        negated: bool,
+    },
+
+    /// Inclusive range on `original_upload_date`, either side may be open.
+    OriginalDate {
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    },
+
+    /// Upload was completed, meaning finalized and processed, after the given time.
+    CompletedAfter(NaiveDateTime),
+
+    /// Upload is one of the given ids, used by search backends that match text themselves.
+    Ids(Vec<i32>),
+}
+
+impl Filter {
+    /// Appends this filter as an `AND ...` condition.
+    fn push_sql(&self, sql: &mut SqlBuilder) {
+        match self {
+            Filter::Status(status) => {
+                sql.push(" AND uploads.status = ")
+                    .bind(SqlValue::SmallInt(*status as i16));
+            }
+            Filter::Text { text, scope } if *scope == TextScope::default() => {
+                sql.push(" AND (uploads.tag_index @@ plainto_tsquery(")
+                    .bind(SqlValue::Text(text.clone()))
+                    .push(") OR uploads.file_name ILIKE CONCAT('%', ")
+                    .bind(SqlValue::Text(text.clone()))
+                    .push(", '%'))");
+            }
+            Filter::Text { text, scope } => {
+                sql.push(" AND (ts_filter(uploads.search_index, ")
+                    .bind(SqlValue::Text(scope.weights()))
+                    .push("::\"char\"[]) @@ plainto_tsquery('pg_catalog.english', ")
+                    .bind(SqlValue::Text(text.clone()))
+                    .push(")");
+
+                if scope.tags {
+                    sql.push(" OR uploads.file_name ILIKE CONCAT('%', ")
+                        .bind(SqlValue::Text(text.clone()))
+                        .push(", '%')");
+                }
+
+                sql.push(")");
+            }
+            Filter::ExcludeTag(tag) => {
+                sql.push(" AND NOT (")
+                    .bind(SqlValue::Text(tag.clone()))
+                    .push(" = ANY(string_to_array(uploads.tag_string, ' ')))");
+            }
+            Filter::AnyTag(tags) => {
+                sql.push(" AND string_to_array(uploads.tag_string, ' ') && ")
+                    .bind(SqlValue::TextArray(tags.clone()));
+            }
+            Filter::Uploader {
+                username,
+                negated: false,
+            } => {
+                sql.push(" AND lower(users.username) = lower(")
+                    .bind(SqlValue::Text(username.clone()))
+                    .push(")");
+            }
+            Filter::Uploader {
+                username,
+                negated: true,
+            } => {
+                sql.push(" AND (users.username IS NULL OR lower(users.username) != lower(")
+                    .bind(SqlValue::Text(username.clone()))
+                    .push("))");
+            }
+            Filter::Source {
+                value,
+                negated: false,
+            } => {
+                sql.push(" AND uploads.source ILIKE CONCAT('%', ")
+                    .bind(SqlValue::Text(value.clone()))
+                    .push(", '%')");
+            }
+            Filter::Source {
+                value,
+                negated: true,
+            } => {
+                sql.push(" AND coalesce(uploads.source, '') NOT ILIKE CONCAT('%', ")
+                    .bind(SqlValue::Text(value.clone()))
+                    .push(", '%')");
+            }
+            Filter::Has { field, negated } => {
+                let condition = match field {
+                    HasField::Source => "coalesce(btrim(uploads.source), '') != ''",
+                    HasField::Description => "btrim(uploads.description) != ''",
+                    HasField::Date => "uploads.original_upload_date IS NOT NULL",
+                };
+
+                if *negated {
+                    sql.push(&format!(" AND NOT ({})", condition));
+                } else {
+                    sql.push(&format!(" AND {}", condition));
+                }
+            }
+            Filter::OriginalDate { from, to } => {
+                if let Some(from) = from {
+                    sql.push(" AND uploads.original_upload_date >= ")
+                        .bind(SqlValue::Date(*from));
+                }
+
+                if let Some(to) = to {
+                    sql.push(" AND uploads.original_upload_date <= ")
+                        .bind(SqlValue::Date(*to));
+                }
+            }
+            Filter::CompletedAfter(completed_at) => {
+                sql.push(" AND uploads.completed_at > ")
+                    .bind(SqlValue::Timestamp(*completed_at));
+            }
+            Filter::Ids(ids) => {
+                sql.push(" AND uploads.id = ANY(")
+                    .bind(SqlValue::IntArray(ids.clone()))
+                    .push(")");
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Sort {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Sort::Newest => write!(f, "newest"),
+            Sort::Oldest => write!(f, "oldest"),
+            Sort::OriginalDate => write!(f, "original"),
+            Sort::OriginalDateDesc => write!(f, "original_desc"),
+            Sort::MostViewed => write!(f, "views"),
+            Sort::MostCommented => write!(f, "comments"),
+            Sort::Largest => write!(f, "size"),
+            Sort::Random(seed) => write!(f, "random:{}", seed),
+            Sort::Relevance(_) => write!(f, "relevance"),
+        }
+    }
+}
+
+impl Sort {
+    /// Appends the expression this sort orders by.
+    ///
+    /// Nullable columns are coalesced so that the key has a total order, which keyset pagination
+    /// relies on to compare against a cursor.
+    fn push_key_sql(&self, sql: &mut SqlBuilder) {
+        match self {
+            Sort::Newest | Sort::Oldest => sql.push("uploads.created_at"),
+            Sort::OriginalDate => {
+                sql.push("coalesce(uploads.original_upload_date, 'infinity'::date)")
+            }
+            Sort::OriginalDateDesc => {
+                sql.push("coalesce(uploads.original_upload_date, '-infinity'::date)")
+            }
+            Sort::MostViewed => sql.push("coalesce(views.view_count, 0)"),
+            Sort::MostCommented => sql.push("coalesce(comments.comment_count, 0)"),
+            Sort::Largest => sql.push("coalesce(uploads.file_size, -1)"),
+            Sort::Random(seed) => sql
+                .push("md5(uploads.id::text || ")
+                .bind(SqlValue::Text(seed.clone()))
+                .push(")"),
+            Sort::Relevance(ids) => sql
+                .push("coalesce(array_position(")
+                .bind(SqlValue::IntArray(ids.clone()))
+                .push(", uploads.id), 2147483647)"),
+        };
+    }
+
+    /// The SQL type of the sort key, used to cast a cursor's key back.
+    fn key_type(&self) -> &'static str {
+        match self {
+            Sort::Newest | Sort::Oldest => "timestamp",
+            Sort::OriginalDate | Sort::OriginalDateDesc => "date",
+            Sort::MostViewed | Sort::MostCommented | Sort::Largest => "bigint",
+            Sort::Random(_) => "text",
+            Sort::Relevance(_) => "integer",
+        }
+    }
+
+    /// Whether `key` is a sort key this order hands out, as text, so it can be cast back to
+    /// [`Sort::key_type`] without Postgres rejecting it.
+    fn is_valid_key(&self, key: &str) -> bool {
+        match self {
+            Sort::Newest | Sort::Oldest => {
+                NaiveDateTime::parse_from_str(key, "%Y-%m-%d %H:%M:%S%.f").is_ok()
+            }
+            Sort::OriginalDate | Sort::OriginalDateDesc => {
+                key == "infinity"
+                    || key == "-infinity"
+                    || NaiveDate::parse_from_str(key, "%Y-%m-%d").is_ok()
+            }
+            Sort::MostViewed | Sort::MostCommented | Sort::Largest => key.parse::<i64>().is_ok(),
+            Sort::Random(_) => true,
+            Sort::Relevance(_) => key.parse::<i32>().is_ok(),
+        }
+    }
+
+    fn is_descending(&self) -> bool {
+        match self {
+            Sort::Newest
+            | Sort::OriginalDateDesc
+            | Sort::MostViewed
+            | Sort::MostCommented
+            | Sort::Largest => true,
+            Sort::Oldest | Sort::OriginalDate | Sort::Random(_) | Sort::Relevance(_) => false,
+        }
+    }
+
+    fn push_sql(&self, sql: &mut SqlBuilder) {
+        let direction = if self.is_descending() { "DESC" } else { "ASC" };
+
+        sql.push(" ORDER BY ");
+        self.push_key_sql(sql);
+        sql.push(&format!(" {}, uploads.id {}", direction, direction));
+    }
+}
+
+/// Position in a listing, the sort key and id of the last upload that was returned.
+///
+/// Handed out to API clients as an opaque string, see [`Cursor::encode`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    /// The sort order the cursor was created for, as given by `Sort`'s `Display`.
+    pub sort: String,
+    key: String,
+    id: i32,
+}
+
+impl Cursor {
+    /// Encodes the cursor as a hex string, so clients don't come to rely on what's inside.
+    pub fn encode(&self) -> String {
+        format!("{}\n{}\n{}", self.id, self.key, self.sort)
+            .bytes()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    pub fn decode(value: &str) -> Option<Cursor> {
+        if value.len() % 2 != 0 || !value.is_ascii() {
+            return None;
+        }
+
+        let bytes = (0..value.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&value[index..index + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+
+        let decoded = String::from_utf8(bytes).ok()?;
+        let mut parts = decoded.splitn(3, '\n');
+
+        let id = parts.next()?.parse::<i32>().ok()?;
+        let key = parts.next()?.to_owned();
+        let sort = parts.next()?.to_owned();
+
+        Some(Cursor { sort, key, id })
+    }
+
+    /// Whether the cursor can continue a listing in the given sort order. Cursors come from
+    /// clients, so their key is checked before it ends up in a query.
+    pub fn is_valid_for(&self, sort: &Sort) -> bool {
+        sort.is_valid_key(&self.key)
+    }
+}
+
+/// How many uploads in a result set have a given tag.
+#[derive(Debug, Clone, Serialize, QueryableByName)]
+pub struct TagFacet {
+    #[sql_type = "sql_types::Text"]
+    pub tag: String,
+
+    #[sql_type = "sql_types::BigInt"]
+    pub count: i64,
+}
+
+impl TagFacet {
+    /// The part of the tag before the `/`, or an empty string for tags without a namespace.
+    pub fn namespace(&self) -> &str {
+        match self.tag.find('/') {
+            Some(index) => &self.tag[..index],
+            None => "",
+        }
+    }
+}
+
+/// Groups tags by the part before the `/`, see [`TagFacet::namespace`].
+const TAG_NAMESPACE_SQL: &'static str =
+    "CASE WHEN position('/' in tag) > 0 THEN split_part(tag, '/', 1) ELSE '' END";
+
+#[derive(QueryableByName)]
+struct KeyedUpload {
+    #[diesel(embed)]
+    upload: FullUpload,
+
+    #[sql_type = "sql_types::Text"]
+    sort_key: String,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Page {
+    limit: i64,
+    offset: i64,
+}
+
+/// Builds the [`FullUpload`] listing query out of independent filter, sort and pagination pieces.
+///
+/// Every listing shares the same base query with comment/view counts and either the
+/// `count(*) over ()` total or a keyset cursor, so a new filter only needs a [`Filter`] variant
+/// instead of another copy of the SQL.
+#[derive(Debug, Clone, Default)]
+pub struct UploadQuery {
+    filters: Vec<Filter>,
+    sort: Sort,
+    page: Option<Page>,
+    after: Option<Cursor>,
+}
+
+const SELECT_SQL: &'static str = "
+    WITH comment_counts AS (
+        SELECT upload_comments.upload_id,
+            count(*) comment_count
+        FROM upload_comments
+        GROUP BY upload_comments.upload_id
+    ),
+    view_counts AS (
+        SELECT upload_views.upload_id,
+            count(*) view_count
+        FROM upload_views
+        GROUP BY upload_views.upload_id
+    )
+    SELECT uploads.*,
+        users.username AS uploader_username,
+        users.role AS uploader_role,
+        coalesce(comments.comment_count, 0) AS comment_count,
+        coalesce(views.view_count, 0) AS view_count";
+
+const FROM_SQL: &'static str = "
+    FROM uploads
+    LEFT JOIN users ON (uploads.uploader_user_id = users.id)
+    LEFT JOIN comment_counts comments ON comments.upload_id = uploads.id
+    LEFT JOIN view_counts views ON views.upload_id = uploads.id
+    WHERE true";
+
+impl UploadQuery {
+    pub fn new() -> UploadQuery {
+        UploadQuery::default()
+    }
+
+    pub fn filter(mut self, filter: Filter) -> UploadQuery {
+        self.filters.push(filter);
+        self
+    }
+
+    pub fn sort(mut self, sort: Sort) -> UploadQuery {
+        self.sort = sort;
+        self
+    }
+
+    /// Limits the query to the given 1-indexed page.
+    pub fn paginate(mut self, page: i64, per_page: i64) -> UploadQuery {
+        self.page = Some(Page {
+            limit: per_page,
+            offset: (page.max(1) - 1) * per_page,
+        });
+        self
+    }
+
+    /// Only returns uploads that come after the cursor in this query's sort order.
+    ///
+    /// The cursor must have been created for the same sort order.
+    pub fn after(mut self, cursor: Option<Cursor>) -> UploadQuery {
+        self.after = cursor;
+        self
+    }
+
+    /// Builds the query, `keyed` selects each row's sort key instead of the total count.
+    fn to_sql(&self, keyed: bool) -> SqlBuilder {
+        let mut sql = SqlBuilder::new(SELECT_SQL);
+
+        if keyed {
+            sql.push(", 0::bigint AS count, ");
+            self.sort.push_key_sql(&mut sql);
+            sql.push("::text AS sort_key");
+        } else {
+            sql.push(", count(*) over ()");
+        }
+
+        sql.push(FROM_SQL);
+
+        for filter in self.filters.iter() {
+            filter.push_sql(&mut sql);
+        }
+
+        if let Some(cursor) = &self.after {
+            let operator = if self.sort.is_descending() { "<" } else { ">" };
+
+            sql.push(" AND (");
+            self.sort.push_key_sql(&mut sql);
+            sql.push(&format!(", uploads.id) {} (CAST(", operator))
+                .bind(SqlValue::Text(cursor.key.clone()))
+                .push(&format!(" AS {}), ", self.sort.key_type()))
+                .bind(SqlValue::Int(cursor.id))
+                .push(")");
+        }
+
+        sql.push(
+            " GROUP BY (uploads.id, users.username, users.role, comments.comment_count, views.view_count)",
+        );
+
+        self.sort.push_sql(&mut sql);
+
+        if let Some(page) = self.page {
+            sql.push(" LIMIT ")
+                .bind(SqlValue::BigInt(page.limit))
+                .push(" OFFSET ")
+                .bind(SqlValue::BigInt(page.offset));
+        }
+
+        sql
+    }
+
+    pub fn load(&self, conn: &PgConnection) -> QueryResult<Vec<FullUpload>> {
+        self.to_sql(false).load::<FullUpload>(conn)
+    }
+
+    /// Counts the tags across every upload matching the filters, ignoring sort and pagination, and
+    /// keeps the `limit` most common tags of each namespace.
+    pub fn tag_facets(&self, conn: &PgConnection, limit: i64) -> QueryResult<Vec<TagFacet>> {
+        let mut sql = SqlBuilder::new(&format!(
+            "SELECT tag, count FROM (
+                SELECT tag,
+                    count(*) AS count,
+                    row_number() OVER (PARTITION BY {} ORDER BY count(*) DESC, tag) AS tag_rank
+                FROM (",
+            TAG_NAMESPACE_SQL
+        ));
+
+        sql.push(SELECT_SQL).push(FROM_SQL);
+
+        for filter in self.filters.iter() {
+            filter.push_sql(&mut sql);
+        }
+
+        sql.push(
+            ") matches, unnest(string_to_array(matches.tag_string, ' ')) AS tag
+            GROUP BY tag
+            ) ranked_tags
+            WHERE tag_rank <= ",
+        )
+        .bind(SqlValue::BigInt(limit))
+        .push(" ORDER BY count DESC, tag");
+
+        sql.load::<TagFacet>(conn)
+    }
+
+    /// Counts the uploads matching the query, ignoring any pagination.
+    pub fn count(&self, conn: &PgConnection) -> QueryResult<i64> {
+        let uploads = self.clone().paginate(1, 1).load(conn)?;
+
+        Ok(uploads.first().map(|upload| upload.count).unwrap_or(0))
+    }
+
+    /// Loads up to `limit` uploads following the cursor given to [`UploadQuery::after`], and the
+    /// cursor for the next batch if there are more.
+    ///
+    /// Unlike [`UploadQuery::paginate`] this doesn't count the total or skip over earlier rows, so
+    /// it stays fast and doesn't repeat or miss rows when uploads are added in between requests.
+    /// The `count` of the returned uploads is always 0.
+    pub fn load_keyset(
+        &self,
+        conn: &PgConnection,
+        limit: i64,
+    ) -> QueryResult<(Vec<FullUpload>, Option<Cursor>)> {
+        let query = UploadQuery {
+            page: Some(Page {
+                limit: limit + 1,
+                offset: 0,
+            }),
+            ..self.clone()
+        };
+
+        let mut rows = query.to_sql(true).load::<KeyedUpload>(conn)?;
+
+        let next_cursor = if rows.len() as i64 > limit {
+            rows.truncate(limit.max(0) as usize);
+            rows.last().map(|row| Cursor {
+                sort: self.sort.to_string(),
+                key: row.sort_key.clone(),
+                id: row.upload.id,
+            })
+        } else {
+            None
+        };
+
+        let uploads = rows.into_iter().map(|row| row.upload).collect();
+
+        Ok((uploads, next_cursor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursor(sort: &str, key: &str) -> Cursor {
+        Cursor {
+            sort: sort.to_owned(),
+            key: key.to_owned(),
+            id: 42,
+        }
+    }
+
+    #[test]
+    fn round_trips_cursors() {
+        let original = cursor("random:abc", "0cc175b9c0f1b6a831c399e269772661");
+
+        assert_eq!(Cursor::decode(&original.encode()), Some(original));
+        assert_eq!(Cursor::decode("abc"), None);
+        assert_eq!(Cursor::decode("zz"), None);
+
+        let bad_id = "x\nkey\nnewest"
+            .bytes()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+
+        assert_eq!(Cursor::decode(&bad_id), None);
+    }
+
+    #[test]
+    fn accepts_keys_of_the_sort_order() {
+        let valid = [
+            (Sort::Newest, "2020-08-01 12:34:56.123456"),
+            (Sort::Oldest, "2020-08-01 12:34:56"),
+            (Sort::OriginalDate, "2015-06-21"),
+            (Sort::OriginalDate, "infinity"),
+            (Sort::OriginalDateDesc, "-infinity"),
+            (Sort::MostViewed, "0"),
+            (Sort::Largest, "-1"),
+            (
+                Sort::Random("abc".to_owned()),
+                "0cc175b9c0f1b6a831c399e269772661",
+            ),
+            (Sort::Relevance(Vec::new()), "2147483647"),
+        ];
+
+        for (sort, key) in valid.iter() {
+            assert!(
+                cursor(&sort.to_string(), key).is_valid_for(sort),
+                "{} {}",
+                sort,
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_cursors_with_a_bad_key() {
+        let invalid = [
+            (Sort::Newest, "garbage"),
+            (Sort::Newest, "2015-06-21"),
+            (Sort::OriginalDate, "2015-13-01"),
+            (Sort::MostCommented, "1); DROP TABLE uploads; --"),
+            (Sort::Largest, "1.5"),
+            (Sort::Relevance(Vec::new()), "2147483648"),
+        ];
+
+        for (sort, key) in invalid.iter() {
+            let decoded = Cursor::decode(&cursor(&sort.to_string(), key).encode())
+                .expect("cursor is well-formed");
+
+            assert!(!decoded.is_valid_for(sort), "{} {}", sort, key);
+        }
+    }
+}
@@ -58,7 +58,9 @@ pub struct User {
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
     pub role: UserRole,
-    pub daily_upload_limit: i32,
+
+    /// Overrides the default daily upload limit of the user's role, see `quota_service`.
+    pub daily_upload_limit: Option<i32>,
     pub invited_by_user_id: Option<i32>,
 }
 
@@ -269,3 +271,23 @@ pub(crate) fn by_ids(conn: &PgConnection, ids: Vec<i32>) -> Vec<User> {
         .load::<User>(conn)
         .unwrap_or_default()
 }
+
+/// Sets or clears a user's daily upload limit override.
+pub(crate) fn update_daily_upload_limit(
+    conn: &PgConnection,
+    user_id: i32,
+    daily_upload_limit: Option<i32>,
+) -> QueryResult<User> {
+    diesel::update(users::table.filter(users::id.eq(user_id)))
+        .set(users::daily_upload_limit.eq(daily_upload_limit))
+        .get_result(conn)
+}
+
+/// Locks a user's row until the end of the current transaction.
+pub(crate) fn lock(conn: &PgConnection, user_id: i32) -> QueryResult<i32> {
+    users::table
+        .select(users::id)
+        .filter(users::id.eq(user_id))
+        .for_update()
+        .first(conn)
+}
@@ -1,13 +1,16 @@
-use log::warn;
+use log::{info, warn};
 use rocket::request::{FlashMessage, Form};
 use rocket::response::{Flash, Redirect};
 use rocket::FromForm;
 use serde::{Deserialize, Serialize};
 
 use crate::database::DatabaseConnection;
-use crate::models::user::User;
+use crate::models::user::{self, User};
 use crate::s3_client;
-use crate::services::{encoder_service, tag_service, upload_service};
+use crate::search;
+use crate::services::{
+    encoder_service, tag_category_service, tag_service, upload_cleanup_service, upload_service,
+};
 use crate::template_utils::{BaseContext, Ructe};
 
 /// Admin area.
@@ -41,14 +44,39 @@ pub(crate) fn action_rebuild_tags(user: &User, conn: DatabaseConnection) -> Flas
 #[rocket::post("/actions/rebuild_tag_counts")]
 pub(crate) fn action_rebuild_tag_counts(user: &User, conn: DatabaseConnection) -> Flash<Redirect> {
     if user.is_admin() {
-        tag_service::rebuild_tag_counts(&conn);
+        let repaired = tag_service::rebuild_tag_counts(&conn);
 
-        Flash::success(Redirect::to("/admin"), "Rebuilt tag counts!")
+        Flash::success(
+            Redirect::to("/admin/tag_counts"),
+            format!("Rebuilt tag counts, {} tags were repaired.", repaired.len()),
+        )
     } else {
         Flash::error(Redirect::to("/"), "")
     }
 }
 
+/// Consistency check listing tags whose upload count has drifted from the actual number of
+/// completed uploads tagged with them.
+#[rocket::get("/tag_counts")]
+pub(crate) fn tag_counts(
+    conn: DatabaseConnection,
+    flash: Option<FlashMessage>,
+    user: &User,
+) -> Result<Ructe, Redirect> {
+    if !user.is_admin() {
+        return Err(Redirect::to("/"));
+    }
+
+    let ctx = BaseContext::new(Some(user), flash);
+    let drift = tag_service::count_drift(&conn);
+
+    if !drift.is_empty() {
+        warn!("[admin] {} tags have drifted upload counts", drift.len());
+    }
+
+    Ok(render!(admin::tag_counts(&ctx, drift)))
+}
+
 #[derive(Serialize, Deserialize, FromForm)]
 pub struct EncodeVideoRequest {
     pub file_id: String,
@@ -98,12 +126,204 @@ pub(crate) fn action_rebuild_md5(user: &User, conn: DatabaseConnection) -> Flash
     )
 }
 
+#[rocket::post("/actions/rebuild_search_index")]
+pub(crate) fn action_rebuild_search_index(
+    user: &User,
+    conn: DatabaseConnection,
+) -> Flash<Redirect> {
+    if !user.is_admin() {
+        return Flash::error(Redirect::to("/"), "");
+    }
+
+    std::thread::spawn(move || match search::rebuild_index(&conn) {
+        Ok(indexed) => info!("[action_rebuild_search_index] indexed {} uploads", indexed),
+        Err(e) => warn!("[action_rebuild_search_index] {}", e),
+    });
+
+    Flash::success(
+        Redirect::to("/admin"),
+        "Started to rebuild the search index. This may take a while.",
+    )
+}
+
+#[rocket::post("/actions/remove_abandoned_uploads")]
+pub(crate) fn action_remove_abandoned_uploads(
+    user: &User,
+    conn: DatabaseConnection,
+) -> Flash<Redirect> {
+    if !user.is_admin() {
+        return Flash::error(Redirect::to("/"), "");
+    }
+
+    std::thread::spawn(move || {
+        upload_cleanup_service::remove_abandoned_uploads(&conn);
+    });
+
+    Flash::success(
+        Redirect::to("/admin"),
+        "Started to remove abandoned uploads. This may take a while.",
+    )
+}
+
+/// Lists tag categories for editing.
+#[rocket::get("/tag_categories")]
+pub(crate) fn tag_categories(
+    conn: DatabaseConnection,
+    flash: Option<FlashMessage>,
+    user: &User,
+) -> Result<Ructe, Redirect> {
+    if !user.is_admin() {
+        return Err(Redirect::to("/"));
+    }
+
+    let ctx = BaseContext::new(Some(user), flash);
+    let tag_categories = tag_category_service::all(&conn);
+
+    Ok(render!(admin::tag_categories(&ctx, tag_categories)))
+}
+
+#[derive(Serialize, Deserialize, FromForm)]
+pub struct TagCategoryRequest {
+    pub name: String,
+    pub prefix: String,
+    pub display_order: i32,
+    pub colour: String,
+}
+
+#[rocket::post("/tag_categories", data = "<request>")]
+pub(crate) fn new_tag_category(
+    conn: DatabaseConnection,
+    user: &User,
+    request: Form<TagCategoryRequest>,
+) -> Flash<Redirect> {
+    if !user.is_admin() {
+        return Flash::error(Redirect::to("/"), "");
+    }
+
+    match tag_category_service::new(
+        &conn,
+        &request.name,
+        &request.prefix,
+        request.display_order,
+        &request.colour,
+    ) {
+        Ok(_) => Flash::success(Redirect::to("/admin/tag_categories"), "Added tag category."),
+        Err(err) => Flash::error(
+            Redirect::to("/admin/tag_categories"),
+            format!("Could not add tag category: {}", err),
+        ),
+    }
+}
+
+#[rocket::post("/tag_categories/<id>", data = "<request>")]
+pub(crate) fn update_tag_category(
+    conn: DatabaseConnection,
+    user: &User,
+    id: i64,
+    request: Form<TagCategoryRequest>,
+) -> Flash<Redirect> {
+    if !user.is_admin() {
+        return Flash::error(Redirect::to("/"), "");
+    }
+
+    match tag_category_service::update(
+        &conn,
+        id,
+        &request.name,
+        &request.prefix,
+        request.display_order,
+        &request.colour,
+    ) {
+        Ok(_) => Flash::success(
+            Redirect::to("/admin/tag_categories"),
+            "Updated tag category.",
+        ),
+        Err(err) => Flash::error(
+            Redirect::to("/admin/tag_categories"),
+            format!("Could not update tag category: {}", err),
+        ),
+    }
+}
+
+#[rocket::post("/tag_categories/<id>/delete")]
+pub(crate) fn delete_tag_category(
+    conn: DatabaseConnection,
+    user: &User,
+    id: i64,
+) -> Flash<Redirect> {
+    if !user.is_admin() {
+        return Flash::error(Redirect::to("/"), "");
+    }
+
+    match tag_category_service::delete(&conn, id) {
+        Ok(_) => Flash::success(
+            Redirect::to("/admin/tag_categories"),
+            "Deleted tag category.",
+        ),
+        Err(err) => Flash::error(
+            Redirect::to("/admin/tag_categories"),
+            format!("Could not delete tag category: {}", err),
+        ),
+    }
+}
+
+#[derive(Serialize, Deserialize, FromForm)]
+pub struct UploadLimitRequest {
+    /// Empty to go back to the default of the user's role.
+    pub daily_upload_limit: String,
+}
+
+/// Overrides how many uploads a user can start per day.
+#[rocket::post("/users/<id>/upload_limit", data = "<request>")]
+pub(crate) fn update_upload_limit(
+    conn: DatabaseConnection,
+    user: &User,
+    id: i32,
+    request: Form<UploadLimitRequest>,
+) -> Flash<Redirect> {
+    if !user.is_admin() {
+        return Flash::error(Redirect::to("/"), "");
+    }
+
+    let daily_upload_limit = match request.daily_upload_limit.trim() {
+        "" => None,
+        limit => match limit.parse::<i32>() {
+            Ok(limit) if limit >= 0 => Some(limit),
+            _ => {
+                return Flash::error(
+                    Redirect::to("/admin"),
+                    "Daily upload limit must be a number of uploads.",
+                )
+            }
+        },
+    };
+
+    match user::update_daily_upload_limit(&conn, id, daily_upload_limit) {
+        Ok(updated_user) => Flash::success(
+            Redirect::to(format!("/user/{}", updated_user.username)),
+            "Updated daily upload limit.",
+        ),
+        Err(_) => Flash::error(
+            Redirect::to("/admin"),
+            "Could not update daily upload limit.",
+        ),
+    }
+}
+
 pub(crate) fn router() -> Vec<rocket::Route> {
     rocket::routes![
         index,
         action_rebuild_tags,
         action_rebuild_tag_counts,
+        tag_counts,
         action_encode_video,
-        action_rebuild_md5
+        action_rebuild_md5,
+        action_rebuild_search_index,
+        action_remove_abandoned_uploads,
+        tag_categories,
+        new_tag_category,
+        update_tag_category,
+        delete_tag_category,
+        update_upload_limit
     ]
 }
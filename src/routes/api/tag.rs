@@ -3,12 +3,27 @@ use rocket_contrib::json::Json;
 use serde::{Deserialize, Serialize};
 
 use crate::database::DatabaseConnection;
-use crate::models::tag;
+use crate::services::{tag_alias_service, tag_category_service, tag_policy_service, tag_service};
+
+/// How many tags are suggested at most.
+const SUGGESTION_LIMIT: i64 = 10;
+
+/// How many related tags are returned by default, and at most.
+const RELATED_LIMIT: i64 = 20;
+const MAX_RELATED_LIMIT: i64 = 100;
 
 #[derive(Serialize, Deserialize)]
 pub struct TagJson {
     name: String,
     upload_count: i32,
+    category: Option<String>,
+    colour: Option<String>,
+
+    /// The alias that was matched, when the tag was suggested through one.
+    alias: Option<String>,
+
+    /// Start of the tag's wiki page.
+    description: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -16,17 +31,35 @@ pub struct SuggestionResponse {
     tags: Vec<TagJson>,
 }
 
-#[rocket::get("/tags/suggestions?<q>")]
+/// Suggests tags for a partially typed tag, optionally only from one namespace.
+#[rocket::get("/tags/suggestions?<q>&<namespace>")]
 pub fn suggestions(
     conn: DatabaseConnection,
     q: Option<String>,
+    namespace: Option<String>,
 ) -> Result<Json<SuggestionResponse>, BadRequest<()>> {
-    let prefix = q.unwrap_or_default();
-    let tags = tag::contains(&conn, &prefix, 10)
-        .iter()
-        .map(|tag| TagJson {
-            name: tag.name.clone(),
-            upload_count: tag.upload_count,
+    let query = tag_policy_service::normalize(q.unwrap_or_default().trim());
+    let namespace = namespace
+        .map(|namespace| tag_policy_service::normalize(namespace.trim().trim_end_matches('/')))
+        .filter(|namespace| !namespace.is_empty());
+
+    let tag_categories = tag_category_service::all(&conn);
+    let tags = tag_service::suggest(&conn, &query, namespace.as_deref(), SUGGESTION_LIMIT)
+        .into_iter()
+        .map(|suggestion| {
+            let tag = suggestion.tag;
+            let tag_category = tag_categories
+                .iter()
+                .find(|tag_category| Some(tag_category.id) == tag.category_id);
+
+            TagJson {
+                upload_count: tag.upload_count,
+                category: tag_category.map(|tag_category| tag_category.name.clone()),
+                colour: tag_category.map(|tag_category| tag_category.colour.clone()),
+                alias: suggestion.alias,
+                description: tag_service::description_snippet(&tag.description),
+                name: tag.name,
+            }
         })
         .collect();
 
@@ -35,6 +68,65 @@ pub fn suggestions(
     Ok(Json(response))
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct RelatedTagJson {
+    name: String,
+
+    /// How many completed uploads have both this tag and the requested one.
+    count: i64,
+    upload_count: i32,
+    category: Option<String>,
+    colour: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RelatedResponse {
+    tag: String,
+    tags: Vec<RelatedTagJson>,
+}
+
+/// Tags that most often appear alongside a tag, optionally only from one namespace. The `/` in
+/// namespaced tags has to be percent-encoded, e.g. `/tags/spinner%2Fkamwaiyan/related`.
+#[rocket::get("/tags/<name>/related?<namespace>&<limit>")]
+pub fn related(
+    conn: DatabaseConnection,
+    name: String,
+    namespace: Option<String>,
+    limit: Option<i64>,
+) -> Option<Json<RelatedResponse>> {
+    let name = tag_policy_service::normalize(name.trim());
+    let name = tag_alias_service::resolve(&conn, vec![name]).pop()?;
+    let tag = tag_service::by_name(&conn, &name)?;
+
+    let namespace = namespace
+        .map(|namespace| tag_policy_service::normalize(namespace.trim().trim_end_matches('/')))
+        .filter(|namespace| !namespace.is_empty());
+    let limit = limit.unwrap_or(RELATED_LIMIT).max(1).min(MAX_RELATED_LIMIT);
+
+    let tag_categories = tag_category_service::all(&conn);
+    let tags = tag_service::related_tags(&conn, &tag.name, namespace.as_deref(), limit)
+        .into_iter()
+        .map(|related| {
+            let tag_category = tag_categories
+                .iter()
+                .find(|tag_category| Some(tag_category.id) == related.tag.category_id);
+
+            RelatedTagJson {
+                count: related.count,
+                upload_count: related.tag.upload_count,
+                category: tag_category.map(|tag_category| tag_category.name.clone()),
+                colour: tag_category.map(|tag_category| tag_category.colour.clone()),
+                name: related.tag.name,
+            }
+        })
+        .collect();
+
+    Some(Json(RelatedResponse {
+        tag: tag.name,
+        tags,
+    }))
+}
+
 pub fn routes() -> Vec<rocket::Route> {
-    rocket::routes![suggestions]
+    rocket::routes![suggestions, related]
 }
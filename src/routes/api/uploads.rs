@@ -1,21 +1,28 @@
+use std::collections::{BTreeMap, HashMap};
 use std::path::Path;
 
 use chrono::{NaiveDate, NaiveDateTime};
-use lazy_static::lazy_static;
 use log::warn;
 use rocket::response::status::BadRequest;
 use rocket_contrib::json;
 use rocket_contrib::json::{Json, JsonValue};
 use serde::{Deserialize, Serialize};
 
-use crate::api::{Auth, Paginated};
+use crate::api::{Auth, CursorPaginated, Paginated};
 use crate::database::DatabaseConnection;
 use crate::ingestors;
 use crate::models;
-use crate::models::upload::FullUpload;
-use crate::models::user::{get_user_by_username, User};
+use crate::models::upload::{FullUpload, Snippet};
+use crate::models::upload_query::{Cursor, TagFacet};
+use crate::models::user::User;
 use crate::s3_client::generate_signed_url;
-use crate::services::upload_service;
+use crate::search;
+use crate::services::batch_upload_service::{self, BatchError, BatchFile, BatchOutcome};
+use crate::services::multipart_upload_service::{
+    self, MultipartError, MultipartUpload, PartUrl, ReceivedPart,
+};
+use crate::services::quota_service::{self, Quota};
+use crate::services::upload_service::{self, UploadError};
 
 #[derive(Serialize)]
 pub struct FullUploadJson {
@@ -31,6 +38,37 @@ pub struct FullUploadJson {
     description: String,
     created_at: NaiveDateTime,
     updated_at: NaiveDateTime,
+
+    /// Highlighted matches for searches using `in:description` or `in:comments`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    snippets: Vec<SnippetJson>,
+}
+
+#[derive(Serialize)]
+pub struct SnippetJson {
+    source: String,
+
+    /// The matching excerpt as escaped HTML, with matches wrapped in `<mark>`.
+    html: String,
+}
+
+impl From<&Snippet> for SnippetJson {
+    fn from(snippet: &Snippet) -> Self {
+        SnippetJson {
+            source: snippet.source.clone(),
+            html: snippet.to_html(),
+        }
+    }
+}
+
+impl FullUploadJson {
+    fn with_snippets(mut self, snippets: &HashMap<i32, Vec<Snippet>>, upload_id: i32) -> Self {
+        if let Some(snippets) = snippets.get(&upload_id) {
+            self.snippets = snippets.iter().map(|snippet| snippet.into()).collect();
+        }
+
+        self
+    }
 }
 
 impl From<&FullUpload> for FullUploadJson {
@@ -48,6 +86,7 @@ impl From<&FullUpload> for FullUploadJson {
             description: upload.description.clone(),
             created_at: upload.created_at,
             updated_at: upload.updated_at,
+            snippets: Vec::new(),
         }
     }
 }
@@ -84,50 +123,124 @@ pub fn validate_checksum(
 pub struct SearchParams {
     page: Option<i64>,
     query: Option<String>,
+    sort: Option<String>,
+
+    /// Switches to cursor pagination, an empty string starts from the beginning.
+    cursor: Option<String>,
+
+    /// Whether to include tag facets, defaults to true unless a `cursor` is given.
+    facets: Option<bool>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum SearchResults {
+    Paginated(Paginated<FullUploadJson>),
+    Cursor(CursorPaginated<FullUploadJson>),
+}
+
+#[derive(Serialize)]
+pub struct SearchResponse {
+    #[serde(flatten)]
+    results: SearchResults,
+
+    /// The most common tags of each namespace across all matching uploads, keyed by namespace.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    facets: Option<BTreeMap<String, Vec<TagFacet>>>,
 }
 
-/// Upload page where a user can upload.
+/// Searches uploads using the same query syntax as the index page.
+///
+/// Results are paginated by `page` unless a `cursor` is given, in which case they are returned
+/// in batches with a `next_cursor` to fetch the next one. Cursors keep their sort order, so
+/// `sort` is ignored when continuing from one.
+///
+/// Tag `facets` are counted over the full result set. They're left out by default when a `cursor`
+/// is given, so scripts walking the archive don't count them again for every batch, pass
+/// `"facets": true` to get them anyway.
 #[rocket::post("/uploads/search", format = "json", data = "<request>")]
 pub fn search(
     conn: DatabaseConnection,
     request: Json<SearchParams>,
-    _auth: Auth,
-) -> Json<Paginated<FullUploadJson>> {
-    lazy_static! {
-        static ref UPLOADER_REGEX: regex::Regex =
-            regex::Regex::new(r"(uploader:)([a-z_A-Z\d]*)\s?").unwrap();
-    }
-
+    auth: Auth,
+) -> Result<Json<SearchResponse>, BadRequest<JsonValue>> {
     let per_page = 50;
+    let facets_per_namespace = 15;
     let current_page = request.page.unwrap_or(1);
-    let mut query = request.query.clone().unwrap_or_default();
+    let query = request.query.clone().unwrap_or_default();
+
+    let invalid_query = |err: search::SearchError| {
+        BadRequest(Some(json!({
+            "status": "invalid_query",
+            "reason": err.to_string()
+        })))
+    };
+
+    let mut search_query = search::parse(&conn, &query, request.sort.as_deref(), Some(auth.user))
+        .map_err(invalid_query)?;
+
+    let facets = if request.facets.unwrap_or_else(|| request.cursor.is_none()) {
+        let mut facets: BTreeMap<String, Vec<TagFacet>> = BTreeMap::new();
+
+        for facet in models::upload::facets(&conn, facets_per_namespace, &search_query) {
+            facets
+                .entry(facet.namespace().to_owned())
+                .or_default()
+                .push(facet);
+        }
+
+        Some(facets)
+    } else {
+        None
+    };
 
-    // Check if the query has an `uploader:[USERNAME]` tag.
-    let mut uploader: Option<User> = None;
+    if let Some(cursor) = &request.cursor {
+        let invalid_cursor = || {
+            BadRequest(Some(json!({
+                "status": "invalid_cursor",
+                "reason": "Cursor is malformed"
+            })))
+        };
 
-    if !query.is_empty() {
-        match UPLOADER_REGEX.captures(&query) {
-            None => (),
-            Some(matches) => {
-                let full_match = &matches[0];
-                let username = &matches[2];
+        let cursor = match cursor.as_str() {
+            "" => None,
+            cursor => Some(Cursor::decode(cursor).ok_or_else(invalid_cursor)?),
+        };
 
-                match get_user_by_username(&conn, &username) {
-                    Some(user) => uploader = Some(user),
-                    _ => (),
-                }
+        if let Some(cursor) = &cursor {
+            search_query.sort = search::parse_sort(&cursor.sort).map_err(invalid_query)?;
 
-                query = query.replace(full_match, "");
+            if !cursor.is_valid_for(&search_query.sort) {
+                return Err(invalid_cursor());
             }
         }
+
+        let (uploads, next_cursor) =
+            models::upload::index_after(&conn, cursor, per_page, &search_query);
+        let snippets = search_snippets(&conn, &uploads, &search_query);
+
+        let response = CursorPaginated {
+            data: uploads
+                .iter()
+                .map(|upload| FullUploadJson::from(upload).with_snippets(&snippets, upload.id))
+                .collect(),
+            page_size: per_page,
+            next_cursor: next_cursor.map(|cursor| cursor.encode()),
+        };
+
+        return Ok(Json(SearchResponse {
+            results: SearchResults::Cursor(response),
+            facets,
+        }));
     }
 
     let (uploads, page_count, total_count) =
-        models::upload::index(&conn, current_page, per_page, &query, uploader);
+        models::upload::index(&conn, current_page, per_page, &search_query);
+    let snippets = search_snippets(&conn, &uploads, &search_query);
 
     let full_uploads = uploads
         .iter()
-        .map(|upload| upload.into())
+        .map(|upload| FullUploadJson::from(upload).with_snippets(&snippets, upload.id))
         .collect::<Vec<FullUploadJson>>();
 
     let response = Paginated {
@@ -137,7 +250,20 @@ pub fn search(
         total_count,
     };
 
-    Json(response)
+    Ok(Json(SearchResponse {
+        results: SearchResults::Paginated(response),
+        facets,
+    }))
+}
+
+fn search_snippets(
+    conn: &DatabaseConnection,
+    uploads: &[FullUpload],
+    search_query: &search::SearchQuery,
+) -> HashMap<i32, Vec<Snippet>> {
+    let upload_ids = uploads.iter().map(|upload| upload.id).collect::<Vec<i32>>();
+
+    models::upload::search_snippets(conn, &upload_ids, search_query)
 }
 
 #[derive(Serialize, Deserialize)]
@@ -203,6 +329,7 @@ pub fn new(
                 url: generate_signed_url("uploads", &file_name),
             }))
         }
+        Err(UploadError::QuotaExceeded(quota)) => Err(quota_exceeded(&quota)),
         Err(_) => Err(BadRequest(Some(json!({
             "status": "error",
             "reason": "Server error"
@@ -210,6 +337,168 @@ pub fn new(
     }
 }
 
+fn quota_exceeded(quota: &Quota) -> BadRequest<JsonValue> {
+    BadRequest(Some(quota_exceeded_json(quota)))
+}
+
+fn quota_exceeded_json(quota: &Quota) -> JsonValue {
+    json!({
+        "status": "quota_exceeded",
+        "reason": quota.exceeded_reason(),
+        "quota": quota,
+    })
+}
+
+/// Maps multipart upload errors to a JSON error, shared with the web uploader.
+pub(crate) fn multipart_error(err: MultipartError) -> BadRequest<JsonValue> {
+    let status = match &err {
+        MultipartError::Upload(UploadError::QuotaExceeded(quota)) => return quota_exceeded(quota),
+        MultipartError::MissingParts(missing_parts) => {
+            return BadRequest(Some(json!({
+                "status": "missing_parts",
+                "reason": err.to_string(),
+                "missing_parts": missing_parts,
+            })))
+        }
+        MultipartError::NotFound => "not_found",
+        MultipartError::NotPending => "already_finalized",
+        MultipartError::NotMultipart => "not_multipart",
+        MultipartError::InvalidPartNumber(_) | MultipartError::TooManyParts => "invalid_parts",
+        MultipartError::StorageError(_) => "storage_error",
+        MultipartError::Upload(_) | MultipartError::DatabaseError => "error",
+    };
+
+    BadRequest(Some(json!({
+        "status": status,
+        "reason": err.to_string(),
+    })))
+}
+
+#[derive(Serialize)]
+pub struct MultipartStatusResponse {
+    #[serde(flatten)]
+    pub upload: MultipartUpload,
+    pub parts: Vec<ReceivedPart>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PartUrlsRequest {
+    pub part_numbers: Vec<i64>,
+}
+
+#[derive(Serialize)]
+pub struct PartUrlsResponse {
+    pub parts: Vec<PartUrl>,
+}
+
+#[derive(Serialize)]
+pub struct MultipartCompleteResponse {
+    pub id: String,
+}
+
+/// Starts a resumable multipart upload, for files too large to send in a single request.
+///
+/// The file is split into `part_count` parts of `part_size` bytes, the last one possibly smaller.
+/// Each part is `PUT` to a url from `/uploads/<id>/multipart/parts`, then the upload is completed
+/// with `/uploads/<id>/multipart/complete` and finalized as usual.
+#[rocket::post("/uploads/multipart", format = "json", data = "<request>")]
+pub(crate) fn new_multipart(
+    conn: DatabaseConnection,
+    request: Json<NewUploadRequest>,
+    auth: Auth,
+) -> Result<Json<MultipartUpload>, BadRequest<JsonValue>> {
+    let user = auth.user;
+
+    if !user.can_upload() {
+        return Err(BadRequest(Some(json!({
+          "status": "no_permission",
+          "reason": "You do not have permission to upload."
+        }))));
+    }
+
+    let path = Path::new(&request.file_name);
+
+    let (name, ext) = match (path.file_name(), path.extension()) {
+        (Some(name), Some(ext)) => (name.to_string_lossy(), ext.to_string_lossy()),
+        _ => {
+            return Err(BadRequest(Some(json!({
+                "status": "invalid_file_name",
+                "reason": "Invalid file_name provided."
+            }))))
+        }
+    };
+
+    if upload_service::get_by_md5(&conn, &request.md5_hash).is_some() {
+        return Err(BadRequest(Some(json!({
+            "status": "error",
+            "reason": "Already uploaded"
+        }))));
+    }
+
+    multipart_upload_service::start(
+        &conn,
+        &user,
+        &name,
+        &ext,
+        request.content_length,
+        Some(request.md5_hash.clone()),
+    )
+    .map(Json)
+    .map_err(multipart_error)
+}
+
+/// Lists the parts received so far, to resume an interrupted upload.
+#[rocket::get("/uploads/<id>/multipart")]
+pub(crate) fn multipart(
+    conn: DatabaseConnection,
+    auth: Auth,
+    id: String,
+) -> Result<Json<MultipartStatusResponse>, BadRequest<JsonValue>> {
+    let (upload, parts) =
+        multipart_upload_service::parts(&conn, auth.user, &id).map_err(multipart_error)?;
+
+    Ok(Json(MultipartStatusResponse { upload, parts }))
+}
+
+/// Pre-signs urls to upload the given parts to, at most 100 at a time.
+#[rocket::post("/uploads/<id>/multipart/parts", format = "json", data = "<request>")]
+pub(crate) fn multipart_part_urls(
+    conn: DatabaseConnection,
+    auth: Auth,
+    id: String,
+    request: Json<PartUrlsRequest>,
+) -> Result<Json<PartUrlsResponse>, BadRequest<JsonValue>> {
+    let parts = multipart_upload_service::part_urls(&conn, auth.user, &id, &request.part_numbers)
+        .map_err(multipart_error)?;
+
+    Ok(Json(PartUrlsResponse { parts }))
+}
+
+/// Joins the uploaded parts once they have all been received.
+#[rocket::post("/uploads/<id>/multipart/complete")]
+pub(crate) fn complete_multipart(
+    conn: DatabaseConnection,
+    auth: Auth,
+    id: String,
+) -> Result<Json<MultipartCompleteResponse>, BadRequest<JsonValue>> {
+    let upload =
+        multipart_upload_service::complete(&conn, auth.user, &id).map_err(multipart_error)?;
+
+    Ok(Json(MultipartCompleteResponse { id: upload.file_id }))
+}
+
+/// Gives up on a multipart upload.
+#[rocket::post("/uploads/<id>/multipart/abort")]
+pub(crate) fn abort_multipart(
+    conn: DatabaseConnection,
+    auth: Auth,
+    id: String,
+) -> Result<JsonValue, BadRequest<JsonValue>> {
+    multipart_upload_service::abort(&conn, auth.user, &id).map_err(multipart_error)?;
+
+    Ok(json!({}))
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct FinalizeUploadResponse {
     id: String,
@@ -269,13 +558,182 @@ pub(crate) fn finalize(
             id: upload.file_id.clone(),
             url: format!("https://spin-archive.org/u/{}", upload.file_id),
         })),
-        Err(_) => Err(BadRequest(Some(json!({
-            "status": "error",
-            "reason": "Server error"
-        })))),
+        Err(err) => Err(finalize_error(err)),
     }
 }
 
+/// Maps errors finalizing an upload that the client can do something about to a JSON error,
+/// shared with the web uploader. The upload stays pending, so finalizing can be retried.
+pub(crate) fn finalize_error(err: UploadError) -> BadRequest<JsonValue> {
+    BadRequest(Some(upload_error_json(&err)))
+}
+
+fn upload_error_json(err: &UploadError) -> JsonValue {
+    let status = match err {
+        UploadError::InvalidTags(violations) => {
+            return json!({
+                "status": "invalid_tags",
+                "reason": err.to_string(),
+                "violations": violations,
+            })
+        }
+        UploadError::SizeMismatch { expected, actual } => {
+            return json!({
+                "status": "size_mismatch",
+                "reason": err.to_string(),
+                "expected_size": expected,
+                "actual_size": actual,
+            })
+        }
+        UploadError::NotFound => "not_found",
+        UploadError::AlreadyExists => "already_finalized",
+        UploadError::MultipartIncomplete => "multipart_incomplete",
+        UploadError::ObjectMissing => "object_missing",
+        UploadError::ChecksumMismatch => "checksum_mismatch",
+        UploadError::StorageError => "storage_error",
+        UploadError::QuotaExceeded(quota) => return quota_exceeded_json(quota),
+        UploadError::DatabaseError => {
+            return json!({
+                "status": "error",
+                "reason": "Server error"
+            })
+        }
+    };
+
+    json!({
+        "status": status,
+        "reason": err.to_string(),
+    })
+}
+
+#[derive(Deserialize)]
+pub struct BatchUploadRequest {
+    files: Vec<BatchFile>,
+}
+
+#[derive(Serialize)]
+pub struct BatchUploadResponse {
+    /// One outcome per file, in the order they were given.
+    files: Vec<JsonValue>,
+}
+
+#[derive(Deserialize)]
+pub struct BatchFinalizeRequest {
+    ids: Vec<String>,
+}
+
+fn check_batch_size(count: usize, max: usize) -> Result<(), BadRequest<JsonValue>> {
+    if count > max {
+        return Err(BadRequest(Some(json!({
+            "status": "too_many_files",
+            "reason": format!("At most {} files can be given at once.", max)
+        }))));
+    }
+
+    Ok(())
+}
+
+/// Starts uploading many files at once, each with the tags and details it'll be finalized with.
+///
+/// Every file gets an outcome with a `status`: `created` with the `id` of its pending upload and
+/// a `url` to `PUT` the file to, `duplicate` with the `id` of the existing upload, or an error
+/// like `invalid_tags` or `quota_exceeded`. Once files are uploaded they're finalized together
+/// with `/uploads/batch/finalize`.
+#[rocket::post("/uploads/batch", format = "json", data = "<request>")]
+pub(crate) fn batch(
+    conn: DatabaseConnection,
+    request: Json<BatchUploadRequest>,
+    auth: Auth,
+) -> Result<Json<BatchUploadResponse>, BadRequest<JsonValue>> {
+    let user = auth.user;
+
+    if !user.can_upload() {
+        return Err(BadRequest(Some(json!({
+          "status": "no_permission",
+          "reason": "You do not have permission to upload."
+        }))));
+    }
+
+    check_batch_size(request.files.len(), batch_upload_service::MAX_BATCH_FILES)?;
+
+    let files = batch_upload_service::start(&conn, &user, &request.files)
+        .into_iter()
+        .map(|outcome| match outcome {
+            BatchOutcome::Created(upload) => {
+                let file_name = format!("{}.{}", &upload.file_id, &upload.file_ext);
+
+                json!({
+                    "status": "created",
+                    "id": upload.file_id,
+                    "url": generate_signed_url("uploads", &file_name),
+                })
+            }
+            BatchOutcome::Duplicate(upload) => json!({
+                "status": "duplicate",
+                "id": upload.file_id,
+            }),
+            BatchOutcome::Failed(BatchError::Upload(err)) => upload_error_json(&err),
+            BatchOutcome::Failed(err @ BatchError::InvalidFileName) => json!({
+                "status": "invalid_file_name",
+                "reason": err.to_string(),
+            }),
+            BatchOutcome::Failed(err @ BatchError::InvalidOriginalUploadDate) => json!({
+                "status": "invalid_original_upload_date",
+                "reason": err.to_string(),
+            }),
+        })
+        .collect();
+
+    Ok(Json(BatchUploadResponse { files }))
+}
+
+/// Finalizes uploads started with `/uploads/batch` and starts processing them.
+///
+/// At most `MAX_BATCH_FINALIZE_FILES` ids, 50, can be finalized per call. Every id gets an
+/// outcome, `finalized` with the upload's `url` or an error like `object_missing` for files that
+/// haven't been uploaded yet, which can be finalized later.
+/// Files that still aren't finalized `PENDING_UPLOAD_MAX_AGE_HOURS` after the batch was started,
+/// 48 by default, are removed and have to be started again.
+#[rocket::post("/uploads/batch/finalize", format = "json", data = "<request>")]
+pub(crate) fn finalize_batch(
+    conn: DatabaseConnection,
+    request: Json<BatchFinalizeRequest>,
+    auth: Auth,
+) -> Result<Json<BatchUploadResponse>, BadRequest<JsonValue>> {
+    let user = auth.user;
+
+    if !user.can_upload() {
+        return Err(BadRequest(Some(json!({
+          "status": "no_permission",
+          "reason": "You do not have permission to upload."
+        }))));
+    }
+
+    check_batch_size(
+        request.ids.len(),
+        batch_upload_service::MAX_BATCH_FINALIZE_FILES,
+    )?;
+
+    let files = batch_upload_service::finalize(&conn, &user, &request.ids)
+        .into_iter()
+        .zip(request.ids.iter())
+        .map(|(result, id)| {
+            let mut outcome = match result {
+                Ok(upload) => json!({
+                    "status": "finalized",
+                    "url": format!("https://spin-archive.org/u/{}", upload.file_id),
+                }),
+                Err(err) => upload_error_json(&err),
+            };
+
+            outcome["id"] = json!(id);
+            outcome
+        })
+        .collect();
+
+    Ok(Json(BatchUploadResponse { files }))
+}
+
 #[derive(Deserialize)]
 pub struct TwitterUpload {
     url: String,
@@ -314,6 +772,13 @@ pub fn twitter(
         Some(auth) => auth.user,
     };
 
+    // Checked before downloading anything, the upload itself checks again.
+    let quota = quota_service::quota(&conn, uploader);
+
+    if quota.is_exceeded() {
+        return Err(quota_exceeded(&quota));
+    }
+
     let existing_upload = upload_service::get_by_source(&conn, &request.url);
 
     if existing_upload.is_some() {
@@ -329,6 +794,10 @@ pub fn twitter(
             url: format!("https://spin-archive.org/u/{}", upload.file_id),
         })),
         Err(err) => {
+            if let Some(UploadError::QuotaExceeded(quota)) = err.downcast_ref::<UploadError>() {
+                return Err(quota_exceeded(quota));
+            }
+
             warn!("[api/v1/uploads/twitter] {}", err);
             Err(BadRequest(Some(json!({
                 "status": "error",
@@ -339,5 +808,18 @@ pub fn twitter(
 }
 
 pub fn routes() -> Vec<rocket::Route> {
-    rocket::routes![validate_checksum, search, new, finalize, twitter]
+    rocket::routes![
+        validate_checksum,
+        search,
+        new,
+        new_multipart,
+        multipart,
+        multipart_part_urls,
+        complete_multipart,
+        abort_multipart,
+        finalize,
+        batch,
+        finalize_batch,
+        twitter
+    ]
 }
@@ -3,6 +3,8 @@ use rocket_contrib::json::Json;
 use serde::{Deserialize, Serialize};
 
 use crate::api::Auth;
+use crate::database::DatabaseConnection;
+use crate::services::quota_service::{self, Quota};
 
 #[derive(Serialize, Deserialize)]
 pub struct UserJson {
@@ -22,6 +24,13 @@ pub fn me(auth: Auth) -> Result<Json<UserJson>, BadRequest<()>> {
   Ok(Json(user))
 }
 
+/// How many more uploads the user can start right now, over a rolling 24 hour window. Bulk upload
+/// scripts can wait until `resets_at` once `remaining` reaches 0.
+#[rocket::get("/me/quota")]
+pub fn quota(conn: DatabaseConnection, auth: Auth) -> Json<Quota> {
+  Json(quota_service::quota(&conn, auth.user))
+}
+
 pub fn routes() -> Vec<rocket::Route> {
-  rocket::routes![me]
+  rocket::routes![me, quota]
 }
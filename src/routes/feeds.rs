@@ -0,0 +1,130 @@
+use chrono::NaiveDateTime;
+use rocket::http::uri::Segments;
+use rocket::request::FromSegments;
+use rocket::response::status::BadRequest;
+
+use crate::database::DatabaseConnection;
+use crate::models;
+use crate::models::upload_query::Filter;
+use crate::models::user::{get_user_by_username, User};
+use crate::routes::tags::TagPath;
+use crate::search::{self, SearchQuery};
+use crate::services::tag_alias_service;
+use crate::template_utils::{feed_url, index_url, to_tag_url, to_tag_wiki_url, Atom};
+
+/// How many of the newest matching uploads a feed includes.
+const FEED_ENTRY_LIMIT: i64 = 50;
+
+/// A tag path with an `.atom` extension, such as `/tags/<name..>.atom`.
+pub struct AtomPath(String);
+
+impl<'a> FromSegments<'a> for AtomPath {
+    type Error = &'static str;
+
+    fn from_segments(segments: Segments<'a>) -> Result<AtomPath, &'static str> {
+        let TagPath(path) = TagPath::from_segments(segments)?;
+
+        match path.strip_suffix(".atom") {
+            Some(name) if !name.is_empty() => Ok(AtomPath(name.to_owned())),
+            _ => Err("path does not end in .atom"),
+        }
+    }
+}
+
+fn render_feed(
+    conn: &DatabaseConnection,
+    title: &str,
+    feed_url: &str,
+    html_url: &str,
+    search_query: &SearchQuery,
+) -> Atom {
+    let (uploads, _page_count, _total_count) =
+        models::upload::index(conn, 1, FEED_ENTRY_LIMIT, search_query);
+
+    let updated = uploads
+        .iter()
+        .map(|upload| upload.updated_at)
+        .max()
+        .unwrap_or_else(|| NaiveDateTime::from_timestamp(0, 0));
+
+    render!(
+        Atom,
+        feeds::atom(title, feed_url, html_url, updated, uploads)
+    )
+}
+
+/// Feed of uploads matching a search, the same as the index page.
+#[rocket::get("/feed.atom?<q>")]
+pub(crate) fn search(
+    conn: DatabaseConnection,
+    user: Option<&User>,
+    q: Option<String>,
+) -> Result<Atom, BadRequest<String>> {
+    let query = q.unwrap_or_default();
+    let search_query = search::parse(&conn, &query, None, user)
+        .map_err(|err| BadRequest(Some(err.to_string())))?;
+
+    let title = if query.is_empty() {
+        "spin-archive.org".to_string()
+    } else {
+        format!("spin-archive.org | {}", query)
+    };
+
+    Ok(render_feed(
+        &conn,
+        &title,
+        &feed_url(&query),
+        &index_url(&query, "", 1),
+        &search_query,
+    ))
+}
+
+/// Feed of a user's uploads.
+#[rocket::get("/user/<username>/uploads.atom")]
+pub(crate) fn user_uploads(conn: DatabaseConnection, username: String) -> Option<Atom> {
+    let profile_user = get_user_by_username(&conn, &username)?;
+
+    let search_query = SearchQuery {
+        filters: vec![Filter::Uploader {
+            username: profile_user.username.clone(),
+            negated: false,
+        }],
+        ..SearchQuery::default()
+    };
+
+    Some(render_feed(
+        &conn,
+        &format!("spin-archive.org | Uploads by {}", profile_user.username),
+        &format!("/user/{}/uploads.atom", profile_user.username),
+        &format!("/user/{}", profile_user.username),
+        &search_query,
+    ))
+}
+
+/// Feed of uploads with a tag. Ranked before the tag's wiki page, which shares the path.
+#[rocket::get("/tags/<name..>", rank = 1)]
+pub(crate) fn tag(conn: DatabaseConnection, name: AtomPath) -> Atom {
+    let AtomPath(name) = name;
+
+    let search_query = SearchQuery {
+        filters: vec![Filter::AnyTag(tag_alias_service::resolve(
+            &conn,
+            vec![name.to_lowercase()],
+        ))],
+        ..SearchQuery::default()
+    };
+
+    let feed_url = format!("{}.atom", to_tag_wiki_url(&name));
+
+    render_feed(
+        &conn,
+        &format!("spin-archive.org | {}", name),
+        &feed_url,
+        &to_tag_url(&name),
+        &search_query,
+    )
+}
+
+pub(crate) fn router() -> Vec<rocket::Route> {
+    rocket::routes![search, user_uploads, tag]
+}
@@ -1,5 +1,6 @@
 pub mod admin;
 pub mod api;
+pub mod feeds;
 pub mod login;
 pub mod queue;
 pub mod register;
@@ -33,7 +33,7 @@ pub(crate) fn approve(conn: DatabaseConnection, user: &User, file_id: String) ->
         .ok_or("Upload not found.")
         .and_then(|upload| {
             if upload.status == UploadStatus::PendingApproval {
-                upload_service::update_status(&conn, upload.id, UploadStatus::Completed)
+                upload_service::update_status(&conn, &upload, UploadStatus::Completed)
                     .and_then(|result| {
                         upload
                             .uploader_user_id
@@ -1,9 +1,63 @@
-use rocket::request::FlashMessage;
+use log::{error, info};
+use rocket::http::uri::{Segments, Uri};
+use rocket::http::RawStr;
+use rocket::request::{FlashMessage, Form, FromSegments};
+use rocket::response::{Flash, Redirect};
+use rocket::FromForm;
+use serde::{Deserialize, Serialize};
 
-use crate::database::DatabaseConnection;
+use crate::database::{ConnectionPool, DatabaseConnection};
 use crate::models::user::User;
-use crate::services::tag_service;
-use crate::template_utils::{BaseContext, Ructe};
+use crate::services::{
+    mass_edit_service, tag_alias_service, tag_implication_service, tag_rename_service, tag_service,
+    tag_wiki_service,
+};
+use crate::template_utils::{encode_tag_path, to_tag_wiki_url, BaseContext, Ructe};
+
+/// How many of the newest uploads with a tag its wiki page shows.
+const WIKI_UPLOAD_LIMIT: i64 = 12;
+
+/// Tags on at most this many uploads are renamed while the moderator waits, bigger renames run in
+/// the background.
+const SYNC_RENAME_UPLOADS: i64 = 100;
+
+/// Runs a long job in the background with a connection of its own, so the request's connection
+/// goes back to the pool once the response is sent.
+fn spawn_job<F>(pool: ConnectionPool, name: &'static str, job: F)
+where
+    F: FnOnce(&DatabaseConnection) + Send + 'static,
+{
+    std::thread::spawn(move || match pool.get() {
+        Some(conn) => job(&conn),
+        None => error!("[{}] no DB connection available", name),
+    });
+}
+
+/// A tag name taken from the rest of the path, namespaced tags such as `spinner/kamwaiyan` span
+/// several segments.
+pub struct TagPath(pub String);
+
+impl<'a> FromSegments<'a> for TagPath {
+    type Error = &'static str;
+
+    fn from_segments(segments: Segments<'a>) -> Result<TagPath, &'static str> {
+        let mut decoded = Vec::new();
+
+        for segment in segments {
+            let segment = RawStr::from_str(segment)
+                .percent_decode()
+                .map_err(|_| "path is not valid UTF-8")?;
+
+            decoded.push(segment.into_owned());
+        }
+
+        if decoded.is_empty() {
+            return Err("path is empty");
+        }
+
+        Ok(TagPath(decoded.join("/")))
+    }
+}
 
 #[rocket::get("/")]
 pub(crate) fn index(
@@ -13,11 +67,624 @@ pub(crate) fn index(
 ) -> Ructe {
     let ctx = BaseContext::new(user, flash);
     let tags = tag_service::all(&conn);
-    let (tag_groups, tags) = tag_service::group_tags(tags);
+    let (tag_groups, tags) = tag_service::group_tags(&conn, tags);
 
     render!(tags::index(&ctx, tag_groups, tags))
 }
 
+/// Lists all tag aliases, moderators can add and remove them here.
+#[rocket::get("/aliases")]
+pub(crate) fn aliases(
+    conn: DatabaseConnection,
+    flash: Option<FlashMessage>,
+    user: Option<&User>,
+) -> Ructe {
+    let ctx = BaseContext::new(user, flash);
+    let tag_aliases = tag_alias_service::all(&conn);
+    let can_edit = user.map(|user| user.is_moderator()).unwrap_or(false);
+
+    render!(tags::aliases(&ctx, tag_aliases, can_edit))
+}
+
+#[derive(Serialize, Deserialize, FromForm)]
+pub struct TagAliasRequest {
+    pub antecedent: String,
+    pub consequent: String,
+}
+
+/// Adds a tag alias, then rewrites the tags of existing uploads in the background.
+#[rocket::post("/aliases", data = "<request>")]
+pub(crate) fn new_alias(
+    conn: DatabaseConnection,
+    pool: ConnectionPool,
+    user: &User,
+    request: Form<TagAliasRequest>,
+) -> Flash<Redirect> {
+    if !user.is_moderator() {
+        return Flash::error(
+            Redirect::to("/tags/aliases"),
+            "You do not have access to do that.",
+        );
+    }
+
+    match tag_alias_service::new(&conn, user, &request.antecedent, &request.consequent) {
+        Ok(tag_alias) => {
+            spawn_job(pool, "tag_alias", move |conn| {
+                let rewritten = tag_alias_service::rewrite_uploads(conn, &tag_alias);
+
+                info!(
+                    "[tag_alias] rewrote {} uploads from {} to {}",
+                    rewritten, tag_alias.antecedent_name, tag_alias.consequent_name
+                );
+            });
+
+            Flash::success(
+                Redirect::to("/tags/aliases"),
+                "Added tag alias. Existing uploads are being retagged, this may take a while.",
+            )
+        }
+        Err(err) => Flash::error(
+            Redirect::to("/tags/aliases"),
+            format!("Could not add tag alias: {}", err),
+        ),
+    }
+}
+
+#[rocket::post("/aliases/<id>/delete")]
+pub(crate) fn delete_alias(conn: DatabaseConnection, user: &User, id: i64) -> Flash<Redirect> {
+    if !user.is_moderator() {
+        return Flash::error(
+            Redirect::to("/tags/aliases"),
+            "You do not have access to do that.",
+        );
+    }
+
+    match tag_alias_service::delete(&conn, id) {
+        Ok(_) => Flash::success(Redirect::to("/tags/aliases"), "Deleted tag alias."),
+        Err(_) => Flash::error(Redirect::to("/tags/aliases"), "Could not delete tag alias."),
+    }
+}
+
+/// Lists all tag implications, moderators can add and remove them here.
+#[rocket::get("/implications")]
+pub(crate) fn implications(
+    conn: DatabaseConnection,
+    flash: Option<FlashMessage>,
+    user: Option<&User>,
+) -> Ructe {
+    let ctx = BaseContext::new(user, flash);
+    let tag_implications = tag_implication_service::all(&conn);
+    let can_edit = user.map(|user| user.is_moderator()).unwrap_or(false);
+
+    render!(tags::implications(&ctx, tag_implications, can_edit))
+}
+
+#[derive(Serialize, Deserialize, FromForm)]
+pub struct TagImplicationRequest {
+    pub antecedent: String,
+    pub consequent: String,
+}
+
+/// Shows which existing uploads a new tag implication would change, before it's added.
+#[rocket::post("/implications/preview", data = "<request>")]
+pub(crate) fn preview_implication(
+    conn: DatabaseConnection,
+    user: &User,
+    request: Form<TagImplicationRequest>,
+) -> Result<Ructe, Flash<Redirect>> {
+    if !user.is_moderator() {
+        return Err(Flash::error(
+            Redirect::to("/tags/implications"),
+            "You do not have access to do that.",
+        ));
+    }
+
+    match tag_implication_service::preview(&conn, &request.antecedent, &request.consequent) {
+        Ok(preview) => {
+            let ctx = BaseContext::new(Some(user), None);
+
+            Ok(render!(tags::implication_preview(&ctx, preview)))
+        }
+        Err(err) => Err(Flash::error(
+            Redirect::to("/tags/implications"),
+            format!("Could not add tag implication: {}", err),
+        )),
+    }
+}
+
+/// Adds a tag implication, then adds the implied tags to existing uploads in the background.
+#[rocket::post("/implications", data = "<request>")]
+pub(crate) fn new_implication(
+    conn: DatabaseConnection,
+    pool: ConnectionPool,
+    user: &User,
+    request: Form<TagImplicationRequest>,
+) -> Flash<Redirect> {
+    if !user.is_moderator() {
+        return Flash::error(
+            Redirect::to("/tags/implications"),
+            "You do not have access to do that.",
+        );
+    }
+
+    match tag_implication_service::new(&conn, user, &request.antecedent, &request.consequent) {
+        Ok(tag_implication) => {
+            spawn_job(pool, "tag_implication", move |conn| {
+                let rewritten = tag_implication_service::rewrite_uploads(conn, &tag_implication);
+
+                info!(
+                    "[tag_implication] added tags to {} uploads for {} -> {}",
+                    rewritten, tag_implication.antecedent_name, tag_implication.consequent_name
+                );
+            });
+
+            Flash::success(
+                Redirect::to("/tags/implications"),
+                "Added tag implication. Existing uploads are being retagged, this may take a while.",
+            )
+        }
+        Err(err) => Flash::error(
+            Redirect::to("/tags/implications"),
+            format!("Could not add tag implication: {}", err),
+        ),
+    }
+}
+
+#[rocket::post("/implications/<id>/delete")]
+pub(crate) fn delete_implication(
+    conn: DatabaseConnection,
+    user: &User,
+    id: i64,
+) -> Flash<Redirect> {
+    if !user.is_moderator() {
+        return Flash::error(
+            Redirect::to("/tags/implications"),
+            "You do not have access to do that.",
+        );
+    }
+
+    match tag_implication_service::delete(&conn, id) {
+        Ok(_) => Flash::success(
+            Redirect::to("/tags/implications"),
+            "Deleted tag implication.",
+        ),
+        Err(_) => Flash::error(
+            Redirect::to("/tags/implications"),
+            "Could not delete tag implication.",
+        ),
+    }
+}
+
+/// Form for renaming a tag or merging it into another, moderators only.
+#[rocket::get("/rename?<from>")]
+pub(crate) fn rename(
+    flash: Option<FlashMessage>,
+    user: &User,
+    from: Option<String>,
+) -> Result<Ructe, Flash<Redirect>> {
+    if !user.is_moderator() {
+        return Err(Flash::error(
+            Redirect::to("/tags"),
+            "You do not have access to do that.",
+        ));
+    }
+
+    let ctx = BaseContext::new(Some(user), flash);
+
+    Ok(render!(tags::rename(&ctx, from.unwrap_or_default())))
+}
+
+#[derive(Serialize, Deserialize, FromForm)]
+pub struct TagRenameRequest {
+    pub from: String,
+    pub to: String,
+    pub leave_alias: bool,
+}
+
+/// Shows how many uploads renaming a tag would change, before it's done.
+#[rocket::post("/rename/preview", data = "<request>")]
+pub(crate) fn preview_rename(
+    conn: DatabaseConnection,
+    user: &User,
+    request: Form<TagRenameRequest>,
+) -> Result<Ructe, Flash<Redirect>> {
+    if !user.is_moderator() {
+        return Err(Flash::error(
+            Redirect::to("/tags"),
+            "You do not have access to do that.",
+        ));
+    }
+
+    match tag_rename_service::preview(&conn, &request.from, &request.to, request.leave_alias) {
+        Ok(preview) => {
+            let ctx = BaseContext::new(Some(user), None);
+
+            Ok(render!(tags::rename_preview(
+                &ctx,
+                preview,
+                request.leave_alias
+            )))
+        }
+        Err(err) => Err(Flash::error(
+            Redirect::to("/tags/rename"),
+            format!("Could not rename tag: {}", err),
+        )),
+    }
+}
+
+/// Renames a tag or merges it into another. Small renames are done right away and go to the
+/// renamed tag's wiki page, bigger ones retag uploads in the background and go back to the rename
+/// form, since the renamed tag may not exist yet.
+#[rocket::post("/rename", data = "<request>")]
+pub(crate) fn rename_tag(
+    conn: DatabaseConnection,
+    pool: ConnectionPool,
+    user: &User,
+    request: Form<TagRenameRequest>,
+) -> Flash<Redirect> {
+    if !user.is_moderator() {
+        return Flash::error(Redirect::to("/tags"), "You do not have access to do that.");
+    }
+
+    // Validated up front so mistakes are reported right away rather than only logged.
+    let preview =
+        match tag_rename_service::preview(&conn, &request.from, &request.to, request.leave_alias) {
+            Ok(preview) => preview,
+            Err(err) => {
+                return Flash::error(
+                    Redirect::to("/tags/rename"),
+                    format!("Could not rename tag: {}", err),
+                )
+            }
+        };
+
+    let user_id = user.id;
+    let leave_alias = request.leave_alias;
+
+    if preview.upload_count <= SYNC_RENAME_UPLOADS {
+        let outcome =
+            tag_rename_service::rename(&conn, user_id, &preview.from, &preview.to, leave_alias);
+
+        return match outcome {
+            Ok(retagged) => Flash::success(
+                Redirect::to(to_tag_wiki_url(&preview.to)),
+                format!("Renamed tag, retagged {} uploads.", retagged),
+            ),
+            Err(err) => Flash::error(
+                Redirect::to("/tags/rename"),
+                format!("Could not rename tag: {}", err),
+            ),
+        };
+    }
+
+    let message = format!(
+        "Renaming {} to {}. Uploads are being retagged, this may take a while.",
+        preview.from, preview.to
+    );
+
+    spawn_job(pool, "tag_rename", move |conn| {
+        let outcome =
+            tag_rename_service::rename(conn, user_id, &preview.from, &preview.to, leave_alias);
+
+        match outcome {
+            Ok(retagged) => info!(
+                "[tag_rename] renamed {} to {}, retagged {} uploads",
+                preview.from, preview.to, retagged
+            ),
+            Err(err) => error!(
+                "[tag_rename] could not rename {} to {}: {}",
+                preview.from, preview.to, err
+            ),
+        }
+    });
+
+    Flash::success(Redirect::to("/tags/rename"), message)
+}
+
+/// Form for changing the tags of every upload matching a search, moderators only.
+#[rocket::get("/mass_edit?<q>")]
+pub(crate) fn mass_edit(
+    flash: Option<FlashMessage>,
+    user: &User,
+    q: Option<String>,
+) -> Result<Ructe, Flash<Redirect>> {
+    if !user.is_moderator() {
+        return Err(Flash::error(
+            Redirect::to("/tags"),
+            "You do not have access to do that.",
+        ));
+    }
+
+    let ctx = BaseContext::new(Some(user), flash);
+
+    Ok(render!(tags::mass_edit(
+        &ctx,
+        q.unwrap_or_default(),
+        String::new()
+    )))
+}
+
+#[derive(Serialize, Deserialize, FromForm)]
+pub struct MassEditRequest {
+    pub query: String,
+    pub operations: String,
+}
+
+/// Shows which uploads a mass edit would change and how, before it's done.
+#[rocket::post("/mass_edit/preview", data = "<request>")]
+pub(crate) fn preview_mass_edit(
+    conn: DatabaseConnection,
+    user: &User,
+    request: Form<MassEditRequest>,
+) -> Result<Ructe, Flash<Redirect>> {
+    if !user.is_moderator() {
+        return Err(Flash::error(
+            Redirect::to("/tags"),
+            "You do not have access to do that.",
+        ));
+    }
+
+    let preview = mass_edit_service::parse_query(&conn, &request.query, user).and_then(|query| {
+        let operations = mass_edit_service::parse_operations(&request.operations)?;
+
+        mass_edit_service::preview(&conn, &query, &operations)
+    });
+
+    match preview {
+        Ok(preview) => {
+            let ctx = BaseContext::new(Some(user), None);
+
+            Ok(render!(tags::mass_edit_preview(
+                &ctx,
+                &request.query,
+                &request.operations,
+                preview
+            )))
+        }
+        Err(err) => Err(Flash::error(
+            Redirect::to(format!(
+                "/tags/mass_edit?q={}",
+                Uri::percent_encode(&request.query)
+            )),
+            format!("Could not edit uploads: {}", err),
+        )),
+    }
+}
+
+/// Changes the tags of every upload matching a search in the background.
+#[rocket::post("/mass_edit", data = "<request>")]
+pub(crate) fn apply_mass_edit(
+    conn: DatabaseConnection,
+    pool: ConnectionPool,
+    user: &User,
+    request: Form<MassEditRequest>,
+) -> Flash<Redirect> {
+    if !user.is_moderator() {
+        return Flash::error(Redirect::to("/tags"), "You do not have access to do that.");
+    }
+
+    // Validated up front so mistakes are reported right away rather than only logged.
+    let parsed = mass_edit_service::parse_query(&conn, &request.query, user).and_then(|query| {
+        Ok((
+            query,
+            mass_edit_service::parse_operations(&request.operations)?,
+        ))
+    });
+
+    let (query, operations) = match parsed {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            return Flash::error(
+                Redirect::to("/tags/mass_edit"),
+                format!("Could not edit uploads: {}", err),
+            )
+        }
+    };
+
+    let user_id = user.id;
+    let search_url = format!("/?q={}", Uri::percent_encode(&request.query));
+    let input = request.into_inner();
+
+    spawn_job(pool, "mass_edit", move |conn| {
+        let outcome = mass_edit_service::apply(conn, user_id, &query, &operations);
+
+        match outcome {
+            Ok(result) => info!(
+                "[mass_edit] `{}` on `{}`: changed {} uploads, skipped {}",
+                input.operations, input.query, result.changed, result.skipped
+            ),
+            Err(err) => error!(
+                "[mass_edit] `{}` on `{}` failed: {}",
+                input.operations, input.query, err
+            ),
+        }
+    });
+
+    Flash::success(
+        Redirect::to(search_url),
+        "Editing uploads. They are being retagged in batches, this may take a while.",
+    )
+}
+
+/// A tag's wiki page, along with its newest uploads and related tags. Aliased tags redirect to
+/// the tag they're aliased to.
+#[rocket::get("/<name..>", rank = 2)]
+pub(crate) fn wiki(
+    conn: DatabaseConnection,
+    flash: Option<FlashMessage>,
+    user: Option<&User>,
+    name: TagPath,
+) -> Option<Result<Ructe, Redirect>> {
+    let TagPath(name) = name;
+    let resolved = tag_alias_service::resolve(&conn, vec![name.to_lowercase()]).pop()?;
+
+    if resolved != name {
+        return Some(Err(Redirect::to(to_tag_wiki_url(&resolved))));
+    }
+
+    let tag = tag_service::by_name(&conn, &name)?;
+    let uploads = tag_wiki_service::recent_uploads(&conn, &tag, WIKI_UPLOAD_LIMIT);
+    let related_tags = tag_wiki_service::related_tags(&conn, &tag);
+    let (tag_groups, related_tags) = tag_service::group_tags(&conn, related_tags);
+    let can_edit = user.map(|user| user.is_contributor()).unwrap_or(false);
+    let can_rename = user.map(|user| user.is_moderator()).unwrap_or(false);
+    let ctx = BaseContext::new(user, flash);
+
+    Some(Ok(render!(tags::wiki(
+        &ctx,
+        tag,
+        uploads,
+        tag_groups,
+        related_tags,
+        can_edit,
+        can_rename
+    ))))
+}
+
+#[rocket::get("/edit/<name..>")]
+pub(crate) fn edit_wiki(
+    conn: DatabaseConnection,
+    flash: Option<FlashMessage>,
+    user: &User,
+    name: TagPath,
+) -> Option<Result<Ructe, Flash<Redirect>>> {
+    let TagPath(name) = name;
+    let tag = tag_service::by_name(&conn, &name)?;
+
+    if !user.is_contributor() {
+        return Some(Err(Flash::error(
+            Redirect::to(to_tag_wiki_url(&tag.name)),
+            "You do not have access to do that.",
+        )));
+    }
+
+    let ctx = BaseContext::new(Some(user), flash);
+
+    Some(Ok(render!(tags::edit(&ctx, tag))))
+}
+
+#[derive(Serialize, Deserialize, FromForm)]
+pub struct TagWikiRequest {
+    pub body: String,
+    pub summary: String,
+}
+
+/// Saves a new revision of a tag's wiki page.
+#[rocket::post("/edit/<name..>", data = "<request>")]
+pub(crate) fn update_wiki(
+    conn: DatabaseConnection,
+    user: &User,
+    name: TagPath,
+    request: Form<TagWikiRequest>,
+) -> Option<Flash<Redirect>> {
+    let TagPath(name) = name;
+    let tag = tag_service::by_name(&conn, &name)?;
+    let wiki_url = to_tag_wiki_url(&tag.name);
+
+    if !user.is_contributor() {
+        return Some(Flash::error(
+            Redirect::to(wiki_url),
+            "You do not have access to do that.",
+        ));
+    }
+
+    match tag_wiki_service::edit(&conn, user, &tag, &request.body, &request.summary) {
+        Ok(_) => Some(Flash::success(Redirect::to(wiki_url), "Saved wiki page.")),
+        Err(err) => Some(Flash::error(
+            Redirect::to(format!("/tags/edit/{}", encode_tag_path(&tag.name))),
+            format!("Could not save wiki page: {}", err),
+        )),
+    }
+}
+
+/// Every revision of a tag's wiki page, newest first.
+#[rocket::get("/history/<name..>")]
+pub(crate) fn wiki_history(
+    conn: DatabaseConnection,
+    flash: Option<FlashMessage>,
+    user: Option<&User>,
+    name: TagPath,
+) -> Option<Ructe> {
+    let TagPath(name) = name;
+    let tag = tag_service::by_name(&conn, &name)?;
+    let tag_revisions = tag_wiki_service::history(&conn, tag.id);
+    let can_edit = user.map(|user| user.is_contributor()).unwrap_or(false);
+    let ctx = BaseContext::new(user, flash);
+
+    Some(render!(tags::history(&ctx, tag, tag_revisions, can_edit)))
+}
+
+/// A revision of a tag's wiki page, diffed against the revision before it.
+#[rocket::get("/revisions/<id>")]
+pub(crate) fn wiki_revision(
+    conn: DatabaseConnection,
+    flash: Option<FlashMessage>,
+    user: Option<&User>,
+    id: i64,
+) -> Option<Ructe> {
+    let tag_revision = tag_wiki_service::by_id(&conn, id)?;
+    let tag = tag_service::by_id(&conn, tag_revision.tag_id)?;
+    let changes = tag_wiki_service::changes(&conn, &tag_revision);
+    let can_edit = user.map(|user| user.is_contributor()).unwrap_or(false);
+    let ctx = BaseContext::new(user, flash);
+
+    Some(render!(tags::revision(
+        &ctx,
+        tag,
+        tag_revision,
+        changes,
+        can_edit
+    )))
+}
+
+/// Restores a tag's wiki page to an old revision.
+#[rocket::post("/revisions/<id>/revert")]
+pub(crate) fn revert_wiki(conn: DatabaseConnection, user: &User, id: i64) -> Flash<Redirect> {
+    let revision_url = format!("/tags/revisions/{}", id);
+
+    if !user.is_contributor() {
+        return Flash::error(
+            Redirect::to(revision_url),
+            "You do not have access to do that.",
+        );
+    }
+
+    match tag_wiki_service::revert(&conn, user, id) {
+        Ok((tag, tag_revision)) => Flash::success(
+            Redirect::to(to_tag_wiki_url(&tag.name)),
+            format!(
+                "Reverted wiki page to revision #{}, saved as revision #{}.",
+                id, tag_revision.id
+            ),
+        ),
+        Err(err) => Flash::error(
+            Redirect::to(revision_url),
+            format!("Could not revert wiki page: {}", err),
+        ),
+    }
+}
+
 pub(crate) fn router() -> Vec<rocket::Route> {
-    rocket::routes![index]
+    rocket::routes![
+        index,
+        aliases,
+        new_alias,
+        delete_alias,
+        implications,
+        preview_implication,
+        new_implication,
+        delete_implication,
+        rename,
+        preview_rename,
+        rename_tag,
+        mass_edit,
+        preview_mass_edit,
+        apply_mass_edit,
+        wiki,
+        edit_wiki,
+        update_wiki,
+        wiki_history,
+        wiki_revision,
+        revert_wiki
+    ]
 }
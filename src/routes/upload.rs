@@ -11,8 +11,17 @@ use serde::{Deserialize, Serialize};
 use crate::database::DatabaseConnection;
 use crate::models::upload;
 use crate::models::user::User;
+use crate::routes::api::uploads::{
+    finalize_error, multipart_error, MultipartCompleteResponse, MultipartStatusResponse,
+    PartUrlsRequest, PartUrlsResponse,
+};
 use crate::s3_client::generate_signed_url;
-use crate::services::{comment_service, notification_service, tag_service, upload_service};
+use crate::services::multipart_upload_service::{self, MultipartUpload};
+use crate::services::upload_service::UploadError;
+use crate::services::{
+    comment_service, notification_service, tag_implication_service, tag_policy_service,
+    tag_service, upload_service,
+};
 use crate::template_utils::{BaseContext, Ructe};
 
 #[derive(Serialize, Deserialize)]
@@ -91,6 +100,13 @@ pub(crate) fn get(
             let comments_with_authors = comment_service::get_comments_for_upload(&conn, &upload);
             let raw_tags = upload.tag_string.split_whitespace().collect::<Vec<&str>>();
             let tags = tag_service::by_names(&conn, &raw_tags);
+            let implied_tags = tag_implication_service::implied_by(
+                &conn,
+                &tags
+                    .iter()
+                    .map(|tag| tag.name.clone())
+                    .collect::<Vec<String>>(),
+            );
             let recommended_uploads =
                 upload_service::get_recommended_uploads(&conn, &tags, upload.id);
 
@@ -100,6 +116,7 @@ pub(crate) fn get(
                 &ctx,
                 &upload,
                 tags,
+                implied_tags,
                 uploader_user,
                 view_count,
                 comments_with_authors,
@@ -304,6 +321,11 @@ pub(crate) fn upload(
                 "url": generate_signed_url("uploads", &file_name),
             }))
         }
+        Err(UploadError::QuotaExceeded(quota)) => Ok(json!({
+            "status": "quota_exceeded",
+            "reason": quota.exceeded_reason(),
+            "quota": quota,
+        })),
         Err(_) => Ok(json!({
             "status": "error",
             "reason": "Server error"
@@ -311,6 +333,100 @@ pub(crate) fn upload(
     }
 }
 
+/// Starts a resumable multipart upload, for large files, see the multipart endpoints of the API.
+#[rocket::post("/upload/multipart", format = "json", data = "<request>")]
+pub(crate) fn start_multipart(
+    conn: DatabaseConnection,
+    user: &User,
+    request: Json<UploadRequest>,
+) -> Result<Json<MultipartUpload>, BadRequest<JsonValue>> {
+    if !user.can_upload() {
+        return Err(BadRequest(None));
+    }
+
+    let path = Path::new(&request.file_name);
+
+    let (name, ext) = match (path.file_name(), path.extension()) {
+        (Some(name), Some(ext)) => (name.to_string_lossy(), ext.to_string_lossy()),
+        _ => return Err(BadRequest(None)),
+    };
+
+    // Basic duplicate check by MD5 hash.
+    if upload_service::get_by_md5(&conn, &request.md5_hash).is_some() {
+        return Err(BadRequest(Some(json!({
+            "status": "error",
+            "reason": "Already uploaded"
+        }))));
+    }
+
+    multipart_upload_service::start(
+        &conn,
+        &user,
+        &name,
+        &ext,
+        request.content_length,
+        Some(request.md5_hash.clone()),
+    )
+    .map(Json)
+    .map_err(multipart_error)
+}
+
+/// Lists the parts of a multipart upload received so far.
+#[rocket::get("/upload/<file_id>/multipart")]
+pub(crate) fn multipart(
+    conn: DatabaseConnection,
+    user: &User,
+    file_id: String,
+) -> Result<Json<MultipartStatusResponse>, BadRequest<JsonValue>> {
+    let (upload, parts) =
+        multipart_upload_service::parts(&conn, user, &file_id).map_err(multipart_error)?;
+
+    Ok(Json(MultipartStatusResponse { upload, parts }))
+}
+
+/// Pre-signs urls to upload parts of a multipart upload to.
+#[rocket::post(
+    "/upload/<file_id>/multipart/parts",
+    format = "json",
+    data = "<request>"
+)]
+pub(crate) fn multipart_part_urls(
+    conn: DatabaseConnection,
+    user: &User,
+    file_id: String,
+    request: Json<PartUrlsRequest>,
+) -> Result<Json<PartUrlsResponse>, BadRequest<JsonValue>> {
+    let parts = multipart_upload_service::part_urls(&conn, user, &file_id, &request.part_numbers)
+        .map_err(multipart_error)?;
+
+    Ok(Json(PartUrlsResponse { parts }))
+}
+
+/// Completes a multipart upload, after which it can be finalized.
+#[rocket::post("/upload/<file_id>/multipart/complete")]
+pub(crate) fn complete_multipart(
+    conn: DatabaseConnection,
+    user: &User,
+    file_id: String,
+) -> Result<Json<MultipartCompleteResponse>, BadRequest<JsonValue>> {
+    let upload =
+        multipart_upload_service::complete(&conn, user, &file_id).map_err(multipart_error)?;
+
+    Ok(Json(MultipartCompleteResponse { id: upload.file_id }))
+}
+
+/// Gives up on a multipart upload.
+#[rocket::post("/upload/<file_id>/multipart/abort")]
+pub(crate) fn abort_multipart(
+    conn: DatabaseConnection,
+    user: &User,
+    file_id: String,
+) -> Result<JsonValue, BadRequest<JsonValue>> {
+    multipart_upload_service::abort(&conn, user, &file_id).map_err(multipart_error)?;
+
+    Ok(json!({}))
+}
+
 /// Finalizes an upload and starts processing it.
 #[rocket::post("/upload/<file_id>/finalize", format = "json", data = "<request>")]
 pub(crate) fn finalize(
@@ -318,7 +434,7 @@ pub(crate) fn finalize(
     user: &User,
     file_id: String,
     request: Json<FinalizeUploadRequest>,
-) -> Result<Json<FinalizeUploadResponse>, BadRequest<()>> {
+) -> Result<Json<FinalizeUploadResponse>, BadRequest<JsonValue>> {
     if !user.can_upload() {
         return Err(BadRequest(None));
     }
@@ -345,7 +461,7 @@ pub(crate) fn finalize(
         parsed_original_date.unwrap(),
     ) {
         Ok(_upload) => Ok(Json(FinalizeUploadResponse {})),
-        Err(_err) => Err(BadRequest(None)),
+        Err(err) => Err(finalize_error(err)),
     }
 }
 
@@ -386,6 +502,13 @@ pub(crate) fn update(
         parsed_original_date.unwrap(),
     ) {
         Ok(_upload) => Flash::success(Redirect::to(path), "Edited!"),
+        Err(UploadError::InvalidTags(violations)) => Flash::error(
+            Redirect::to(format!("{}/edit", path)),
+            format!(
+                "Could not edit upload: {}",
+                tag_policy_service::describe(&violations)
+            ),
+        ),
         Err(_err) => Flash::error(
             Redirect::to(format!("{}/edit", path)),
             "Could not edit upload.",
@@ -1,11 +1,15 @@
 use rocket::http::RawStr;
-use rocket::request::FlashMessage;
+use rocket::request::{FlashMessage, Form};
 use rocket::response::{Flash, Redirect};
+use rocket::FromForm;
+use serde::{Deserialize, Serialize};
 
 use crate::database::DatabaseConnection;
 use crate::models::user::{get_user_by_username, User};
-use crate::services::{api_token_service, comment_service, upload_service};
-use crate::template_utils::{BaseContext, Ructe};
+use crate::services::{
+    api_token_service, comment_service, quota_service, saved_search_service, upload_service,
+};
+use crate::template_utils::{index_url, BaseContext, Ructe};
 
 #[rocket::get("/settings")]
 pub(crate) fn settings(
@@ -15,8 +19,9 @@ pub(crate) fn settings(
 ) -> Result<Ructe, Redirect> {
     let ctx = BaseContext::new(Some(user), flash);
     let api_tokens = api_token_service::get_tokens_by_user(&conn, user.id);
+    let saved_searches = saved_search_service::by_user(&conn, user.id);
 
-    Ok(render!(users::settings(&ctx, api_tokens)))
+    Ok(render!(users::settings(&ctx, api_tokens, saved_searches)))
 }
 
 #[rocket::post("/settings/api_tokens")]
@@ -58,6 +63,62 @@ pub(crate) fn delete_api_token(conn: DatabaseConnection, id: i64, user: &User) -
     }
 }
 
+#[derive(Serialize, Deserialize, FromForm)]
+pub struct SavedSearchRequest {
+    pub name: String,
+    pub query: String,
+    pub webhook_url: Option<String>,
+}
+
+#[rocket::post("/settings/saved_searches", data = "<request>")]
+pub(crate) fn new_saved_search(
+    conn: DatabaseConnection,
+    user: &User,
+    request: Form<SavedSearchRequest>,
+) -> Flash<Redirect> {
+    match saved_search_service::new(
+        &conn,
+        user,
+        &request.name,
+        &request.query,
+        request.webhook_url.as_deref(),
+    ) {
+        Ok(_) => Flash::success(Redirect::to("/user/settings"), "Saved search."),
+        Err(err) => Flash::error(
+            Redirect::to("/user/settings"),
+            format!("Could not save search: {}", err),
+        ),
+    }
+}
+
+/// Opens the results of a saved search, marking them as seen.
+#[rocket::get("/settings/saved_searches/<id>")]
+pub(crate) fn open_saved_search(conn: DatabaseConnection, id: i64, user: &User) -> Redirect {
+    match saved_search_service::by_id(&conn, user.id, id) {
+        Some(saved_search) => {
+            saved_search_service::mark_seen(&conn, &saved_search);
+
+            Redirect::to(index_url(&saved_search.query, "", 1))
+        }
+        None => Redirect::to("/404"),
+    }
+}
+
+#[rocket::post("/settings/saved_searches/<id>/delete")]
+pub(crate) fn delete_saved_search(
+    conn: DatabaseConnection,
+    id: i64,
+    user: &User,
+) -> Flash<Redirect> {
+    match saved_search_service::delete(&conn, user.id, id) {
+        Ok(_) => Flash::success(Redirect::to("/user/settings"), "Deleted saved search."),
+        Err(_) => Flash::error(
+            Redirect::to("/user/settings"),
+            "Could not delete saved search.",
+        ),
+    }
+}
+
 #[rocket::get("/<username>")]
 pub(crate) fn index(
     conn: DatabaseConnection,
@@ -72,12 +133,20 @@ pub(crate) fn index(
             let comment_count =
                 comment_service::get_comment_count_by_user_id(&conn, profile_user.id);
             let upload_count = upload_service::get_upload_count_by_user_id(&conn, profile_user.id);
+            let can_set_upload_limit = user.map(|user| user.is_admin()).unwrap_or(false);
+
+            // Only shown to the user themselves and to admins.
+            let quota = user
+                .filter(|user| user.id == profile_user.id || can_set_upload_limit)
+                .map(|_| quota_service::quota(&conn, &profile_user));
 
             Ok(render!(users::profile(
                 &ctx,
                 profile_user,
                 comment_count,
-                upload_count
+                upload_count,
+                quota,
+                can_set_upload_limit
             )))
         }
         _ => Err(Redirect::to("/404")),
@@ -122,5 +191,14 @@ pub(crate) fn comments(
 }
 
 pub(crate) fn router() -> Vec<rocket::Route> {
-    rocket::routes![index, comments, settings, new_api_token, delete_api_token,]
+    rocket::routes![
+        index,
+        comments,
+        settings,
+        new_api_token,
+        delete_api_token,
+        new_saved_search,
+        open_saved_search,
+        delete_saved_search,
+    ]
 }
@@ -1,12 +1,18 @@
 use std::time::Duration;
 
 use anyhow::Result;
+use chrono::{DateTime, NaiveDateTime};
 use rusoto_core::credential::StaticProvider;
-use rusoto_core::Region;
+use rusoto_core::{Region, RusotoError};
 use rusoto_credential::AwsCredentials;
 use rusoto_s3::util::{PreSignedRequest, PreSignedRequestOption};
 use rusoto_s3::S3;
-use rusoto_s3::{ListObjectsV2Request, Object, PutObjectRequest, S3Client};
+use rusoto_s3::{
+    AbortMultipartUploadRequest, CompleteMultipartUploadRequest, CompletedMultipartUpload,
+    CompletedPart, CreateMultipartUploadRequest, DeleteObjectRequest, HeadObjectError,
+    HeadObjectRequest, ListObjectsV2Request, ListPartsError, ListPartsRequest, Object,
+    PutObjectRequest, S3Client, UploadPartRequest,
+};
 
 use crate::config;
 
@@ -16,6 +22,25 @@ pub struct UploadObject {
     pub file_id: String,
 }
 
+/// What storage knows about a stored object.
+#[derive(Debug)]
+pub struct ObjectInfo {
+    pub size: i64,
+
+    /// The MD5 of the object for single uploads. Multipart uploads get an ETag with a `-` in it
+    /// instead, which isn't the MD5 of the whole object.
+    pub e_tag: String,
+}
+
+/// A part of a multipart upload that has been received.
+#[derive(Debug, Clone)]
+pub struct UploadedPart {
+    pub part_number: i64,
+    pub size: i64,
+    pub e_tag: String,
+    pub last_modified: Option<NaiveDateTime>,
+}
+
 fn region() -> Region {
     Region::Custom {
         name: "us-west-1".to_owned(),
@@ -32,6 +57,14 @@ fn credentials() -> AwsCredentials {
     )
 }
 
+fn client() -> S3Client {
+    S3Client::new_with(
+        rusoto_core::request::HttpClient::new().expect("Failed to create HTTP client"),
+        StaticProvider::from(credentials()),
+        region(),
+    )
+}
+
 fn key_to_file_id(key: String) -> String {
     key.split('.')
         .next()
@@ -107,3 +140,199 @@ pub fn generate_signed_url(folder_name: &str, file_name: &str) -> String {
 
     url
 }
+
+/// Looks up the size and ETag of `folder_name/file_name`, `None` if there's no such object.
+#[tokio::main(basic_scheduler)]
+pub async fn head_object(folder_name: &str, file_name: &str) -> Result<Option<ObjectInfo>> {
+    let request = HeadObjectRequest {
+        bucket: "bits.spin-archive.org".to_owned(),
+        key: format!("{}/{}", folder_name, file_name),
+        ..Default::default()
+    };
+
+    match client().head_object(request).await {
+        Ok(response) => Ok(Some(ObjectInfo {
+            size: response.content_length.unwrap_or_default(),
+            e_tag: response.e_tag.unwrap_or_default().replace("\"", ""),
+        })),
+        // HEAD responses have no body, so a missing object usually comes back as a bare 404.
+        Err(RusotoError::Service(HeadObjectError::NoSuchKey(_))) => Ok(None),
+        Err(RusotoError::Unknown(response)) if response.status.as_u16() == 404 => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Deletes `folder_name/file_name`, succeeding if it doesn't exist.
+#[tokio::main(basic_scheduler)]
+pub async fn delete_object(folder_name: &str, file_name: &str) -> Result<()> {
+    let request = DeleteObjectRequest {
+        bucket: "bits.spin-archive.org".to_owned(),
+        key: format!("{}/{}", folder_name, file_name),
+        ..Default::default()
+    };
+
+    client().delete_object(request).await?;
+
+    Ok(())
+}
+
+/// Starts a multipart upload to `folder_name/file_name`, returning its upload id.
+#[tokio::main(basic_scheduler)]
+pub async fn create_multipart_upload(folder_name: &str, file_name: &str) -> Result<String> {
+    let request = CreateMultipartUploadRequest {
+        bucket: "bits.spin-archive.org".to_owned(),
+        key: format!("{}/{}", folder_name, file_name),
+        acl: Some("public-read".to_owned()),
+        ..Default::default()
+    };
+
+    let response = client().create_multipart_upload(request).await?;
+
+    response
+        .upload_id
+        .ok_or_else(|| anyhow::anyhow!("No upload id returned for {}", file_name))
+}
+
+/// Generates a pre-signed url for uploading a single part of a multipart upload.
+pub fn generate_signed_part_url(
+    folder_name: &str,
+    file_name: &str,
+    upload_id: &str,
+    part_number: i64,
+) -> String {
+    let request = UploadPartRequest {
+        bucket: "bits.spin-archive.org".to_owned(),
+        key: format!("{}/{}", folder_name, file_name),
+        upload_id: upload_id.to_owned(),
+        part_number,
+        ..Default::default()
+    };
+
+    request.get_presigned_url(
+        &region(),
+        &credentials(),
+        &PreSignedRequestOption {
+            expires_in: Duration::from_secs(60 * 15),
+        },
+    )
+}
+
+async fn fetch_parts(
+    folder_name: &str,
+    file_name: &str,
+    upload_id: &str,
+) -> std::result::Result<Vec<UploadedPart>, RusotoError<ListPartsError>> {
+    let client = client();
+    let mut parts: Vec<UploadedPart> = Vec::new();
+    let mut part_number_marker = None;
+
+    loop {
+        let request = ListPartsRequest {
+            bucket: "bits.spin-archive.org".to_owned(),
+            key: format!("{}/{}", folder_name, file_name),
+            upload_id: upload_id.to_owned(),
+            part_number_marker,
+            ..Default::default()
+        };
+
+        let response = client.list_parts(request).await?;
+
+        parts.extend(response.parts.unwrap_or_default().into_iter().map(|part| {
+            UploadedPart {
+                part_number: part.part_number.unwrap_or_default(),
+                size: part.size.unwrap_or_default(),
+                e_tag: part.e_tag.unwrap_or_default(),
+                last_modified: part
+                    .last_modified
+                    .and_then(|last_modified| DateTime::parse_from_rfc3339(&last_modified).ok())
+                    .map(|last_modified| last_modified.naive_utc()),
+            }
+        }));
+
+        if !response.is_truncated.unwrap_or(false) {
+            break;
+        }
+
+        part_number_marker = response.next_part_number_marker;
+    }
+
+    Ok(parts)
+}
+
+/// Lists the parts of a multipart upload received so far, in part number order.
+#[tokio::main(basic_scheduler)]
+pub async fn list_parts(
+    folder_name: &str,
+    file_name: &str,
+    upload_id: &str,
+) -> Result<Vec<UploadedPart>> {
+    Ok(fetch_parts(folder_name, file_name, upload_id).await?)
+}
+
+/// When a multipart upload last received a part, `None` if it has no parts or doesn't exist
+/// anymore, which happens once storage expires it.
+#[tokio::main(basic_scheduler)]
+pub async fn last_part_uploaded_at(
+    folder_name: &str,
+    file_name: &str,
+    upload_id: &str,
+) -> Result<Option<NaiveDateTime>> {
+    match fetch_parts(folder_name, file_name, upload_id).await {
+        Ok(parts) => Ok(parts
+            .into_iter()
+            .filter_map(|part| part.last_modified)
+            .max()),
+        Err(RusotoError::Unknown(response)) if response.status.as_u16() == 404 => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Completes a multipart upload, joining the given parts into the final object.
+#[tokio::main(basic_scheduler)]
+pub async fn complete_multipart_upload(
+    folder_name: &str,
+    file_name: &str,
+    upload_id: &str,
+    parts: &[UploadedPart],
+) -> Result<()> {
+    let request = CompleteMultipartUploadRequest {
+        bucket: "bits.spin-archive.org".to_owned(),
+        key: format!("{}/{}", folder_name, file_name),
+        upload_id: upload_id.to_owned(),
+        multipart_upload: Some(CompletedMultipartUpload {
+            parts: Some(
+                parts
+                    .iter()
+                    .map(|part| CompletedPart {
+                        e_tag: Some(part.e_tag.clone()),
+                        part_number: Some(part.part_number),
+                    })
+                    .collect(),
+            ),
+        }),
+        ..Default::default()
+    };
+
+    client().complete_multipart_upload(request).await?;
+
+    Ok(())
+}
+
+/// Aborts a multipart upload, discarding any parts received.
+#[tokio::main(basic_scheduler)]
+pub async fn abort_multipart_upload(
+    folder_name: &str,
+    file_name: &str,
+    upload_id: &str,
+) -> Result<()> {
+    let request = AbortMultipartUploadRequest {
+        bucket: "bits.spin-archive.org".to_owned(),
+        key: format!("{}/{}", folder_name, file_name),
+        upload_id: upload_id.to_owned(),
+        ..Default::default()
+    };
+
+    client().abort_multipart_upload(request).await?;
+
+    Ok(())
+}
@@ -64,6 +64,77 @@ table! {
     }
 }
 
+table! {
+    use diesel::sql_types::*;
+
+    saved_searches (id) {
+        id -> Int8,
+        user_id -> Int4,
+        name -> Text,
+        query -> Text,
+        webhook_url -> Nullable<Text>,
+        last_seen_at -> Timestamp,
+        last_notified_at -> Timestamp,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        new_match_count -> Int8,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+
+    tag_aliases (id) {
+        id -> Int8,
+        antecedent_name -> Text,
+        consequent_name -> Text,
+        creator_id -> Int4,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+
+    tag_categories (id) {
+        id -> Int8,
+        name -> Text,
+        prefix -> Text,
+        display_order -> Int4,
+        colour -> Text,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+
+    tag_implications (id) {
+        id -> Int8,
+        antecedent_name -> Text,
+        consequent_name -> Text,
+        creator_id -> Int4,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+
+    tag_revisions (id) {
+        id -> Int8,
+        tag_id -> Int8,
+        body -> Text,
+        summary -> Text,
+        editor_id -> Nullable<Int4>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
 table! {
     use diesel::sql_types::*;
 
@@ -74,6 +145,7 @@ table! {
         created_at -> Timestamp,
         updated_at -> Timestamp,
         upload_count -> Int4,
+        category_id -> Nullable<Int8>,
     }
 }
 
@@ -139,6 +211,9 @@ table! {
         video_url -> Nullable<Text>,
         description -> Text,
         original_upload_date -> Nullable<Date>,
+        search_index -> Tsvector,
+        multipart_upload_id -> Nullable<Text>,
+        completed_at -> Nullable<Timestamp>,
     }
 }
 
@@ -153,7 +228,7 @@ table! {
         created_at -> Timestamp,
         updated_at -> Timestamp,
         role -> Int2,
-        daily_upload_limit -> Int4,
+        daily_upload_limit -> Nullable<Int4>,
         invited_by_user_id -> Nullable<Int4>,
     }
 }
@@ -162,6 +237,12 @@ joinable!(api_tokens -> users (user_id));
 joinable!(audit_log -> users (changed_by));
 joinable!(posts -> threads (thread_id));
 joinable!(posts -> users (author_id));
+joinable!(saved_searches -> users (user_id));
+joinable!(tag_aliases -> users (creator_id));
+joinable!(tag_implications -> users (creator_id));
+joinable!(tag_revisions -> tags (tag_id));
+joinable!(tag_revisions -> users (editor_id));
+joinable!(tags -> tag_categories (category_id));
 joinable!(threads -> forums (forum_id));
 joinable!(threads -> users (author_id));
 joinable!(upload_comments -> uploads (upload_id));
@@ -175,6 +256,11 @@ allow_tables_to_appear_in_same_query!(
     forums,
     invitations,
     posts,
+    saved_searches,
+    tag_aliases,
+    tag_categories,
+    tag_implications,
+    tag_revisions,
     tags,
     threads,
     upload_comments,
@@ -0,0 +1,26 @@
+use diesel::PgConnection;
+
+use crate::models::upload_query::Filter;
+use crate::search::SearchQuery;
+use crate::services::tag_alias_service;
+
+/// Replaces aliased tags in the query's tag filters with the tags they're aliased to, since
+/// uploads are only ever tagged with the latter.
+///
+/// Free text is left as written, it's matched against file names, descriptions and comments too,
+/// where a word that happens to be an aliased tag means something else.
+pub fn resolve(conn: &PgConnection, query: &mut SearchQuery) {
+    for filter in query.filters.iter_mut() {
+        match filter {
+            Filter::AnyTag(tags) => {
+                *tags = tag_alias_service::resolve(conn, std::mem::take(tags));
+            }
+            Filter::ExcludeTag(tag) => {
+                if let Some(resolved) = tag_alias_service::resolve(conn, vec![tag.clone()]).pop() {
+                    *tag = resolved;
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,111 @@
+// Search backends decide how the free text of a search is matched and ranked. Every other part
+// of a search is still a `Filter` on the Postgres upload query, so backends only need to know
+// about text.
+
+mod postgres;
+mod tantivy_index;
+
+use diesel::PgConnection;
+use lazy_static::lazy_static;
+use log::{error, warn};
+use thiserror::Error;
+
+use crate::config;
+use crate::models::upload_query::UploadQuery;
+use crate::search::SearchQuery;
+
+use postgres::PostgresBackend;
+use tantivy_index::TantivyBackend;
+
+/// The most uploads a backend ranks for one search when sorting by relevance, text matches past
+/// this still match but come after the ranked ones.
+const MAX_RANKED_UPLOADS: usize = 1000;
+
+#[derive(Error, Debug)]
+pub enum BackendError {
+    #[error("Search index error: {0}")]
+    Index(#[from] tantivy::TantivyError),
+
+    #[error("Could not open search index: {0}")]
+    OpenDirectory(#[from] tantivy::directory::error::OpenDirectoryError),
+
+    #[error("Could not create search index directory: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Search index writer is unavailable")]
+    WriterUnavailable,
+}
+
+pub trait SearchBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Builds the upload query for a search, including how its free text is matched and ranked.
+    fn upload_query(
+        &self,
+        conn: &PgConnection,
+        query: &SearchQuery,
+    ) -> Result<UploadQuery, BackendError>;
+
+    /// Adds, updates or removes uploads in the backend's own index, if it has one.
+    fn index_uploads(&self, _conn: &PgConnection, _upload_ids: &[i32]) -> Result<(), BackendError> {
+        Ok(())
+    }
+
+    /// Rebuilds the backend's own index from scratch, returns how many uploads were indexed.
+    fn rebuild(&self, _conn: &PgConnection) -> Result<usize, BackendError> {
+        Ok(0)
+    }
+}
+
+lazy_static! {
+    static ref BACKEND: Box<dyn SearchBackend> = match config::get_search_backend().as_str() {
+        "tantivy" => match TantivyBackend::open(&config::get_search_index_path()) {
+            Ok(backend) => Box::new(backend),
+            Err(err) => {
+                error!("[search] {}, falling back to postgres", err);
+                Box::new(PostgresBackend)
+            }
+        },
+        _ => Box::new(PostgresBackend),
+    };
+}
+
+/// Builds the upload query for a search with the configured backend, falling back to Postgres
+/// if the backend fails.
+pub fn upload_query(conn: &PgConnection, query: &SearchQuery) -> UploadQuery {
+    BACKEND.upload_query(conn, query).unwrap_or_else(|err| {
+        warn!("[search] {} backend failed: {}", BACKEND.name(), err);
+
+        PostgresBackend
+            .upload_query(conn, query)
+            .unwrap_or_else(|_| query.to_upload_query())
+    })
+}
+
+/// Brings the search index up to date after an upload or its comments changed.
+pub fn index_upload(conn: &PgConnection, upload_id: i32) {
+    if let Err(err) = BACKEND.index_uploads(conn, &[upload_id]) {
+        warn!("[search] could not index upload {}: {}", upload_id, err);
+    }
+}
+
+/// Brings the search index up to date after many uploads changed at once. Call this after the
+/// transaction changing them commits, so the index doesn't get ahead of the database.
+pub fn index_uploads(conn: &PgConnection, upload_ids: &[i32]) {
+    if upload_ids.is_empty() {
+        return;
+    }
+
+    if let Err(err) = BACKEND.index_uploads(conn, upload_ids) {
+        warn!(
+            "[search] could not index {} uploads: {}",
+            upload_ids.len(),
+            err
+        );
+    }
+}
+
+/// Rebuilds the search index from scratch.
+pub fn rebuild_index(conn: &PgConnection) -> Result<usize, BackendError> {
+    BACKEND.rebuild(conn)
+}
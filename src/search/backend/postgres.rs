@@ -0,0 +1,33 @@
+use diesel::PgConnection;
+
+use crate::models::upload;
+use crate::models::upload_query::{Sort, UploadQuery};
+use crate::search::backend::{BackendError, SearchBackend, MAX_RANKED_UPLOADS};
+use crate::search::SearchQuery;
+
+/// Matches text with Postgres full text search on `tag_index` and `search_index`.
+pub struct PostgresBackend;
+
+impl SearchBackend for PostgresBackend {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn upload_query(
+        &self,
+        conn: &PgConnection,
+        query: &SearchQuery,
+    ) -> Result<UploadQuery, BackendError> {
+        let upload_query = query.to_upload_query();
+
+        match query.sort {
+            Sort::Relevance(_) if !query.text.is_empty() => {
+                let ranked_ids =
+                    upload::rank_by_text(conn, &query.text, query.scope, MAX_RANKED_UPLOADS as i64);
+
+                Ok(upload_query.sort(Sort::Relevance(ranked_ids)))
+            }
+            _ => Ok(upload_query),
+        }
+    }
+}
@@ -0,0 +1,314 @@
+use std::sync::Mutex;
+
+use diesel::PgConnection;
+use log::{debug, warn};
+use tantivy::collector::{Count, TopDocs};
+use tantivy::directory::MmapDirectory;
+use tantivy::query::{BooleanQuery, BoostQuery, FuzzyTermQuery, Occur, Query, TermQuery};
+use tantivy::schema::{Field, IndexRecordOption, Schema, FAST, INDEXED, TEXT};
+use tantivy::{Document, Index, IndexReader, IndexWriter, TantivyError, Term};
+
+use crate::models::upload::{self, SearchDocument};
+use crate::models::upload_query::{Filter, Sort, TextScope, UploadQuery};
+use crate::search::backend::postgres::PostgresBackend;
+use crate::search::backend::{BackendError, SearchBackend, MAX_RANKED_UPLOADS};
+use crate::search::SearchQuery;
+
+/// Memory budget for the index writer, split between its indexing threads.
+const WRITER_HEAP_SIZE: usize = 50_000_000;
+
+/// How many uploads are loaded at a time while rebuilding.
+const REBUILD_BATCH_SIZE: i64 = 250;
+
+/// The most matches a search reads from the index. Searches matching more than this keep their
+/// text filter in Postgres instead of a list of ids.
+const MAX_TEXT_MATCHES: usize = 10_000;
+
+/// Tags matter more than the file name and description, which matter more than comments.
+const TAGS_BOOST: f32 = 3.0;
+const FILE_NAME_BOOST: f32 = 1.5;
+const DESCRIPTION_BOOST: f32 = 1.5;
+const COMMENTS_BOOST: f32 = 1.0;
+
+struct Fields {
+    id: Field,
+    tags: Field,
+    file_name: Field,
+    description: Field,
+    comments: Field,
+}
+
+/// Matches text against an embedded Tantivy index on local disk, with fuzzy terms and BM25
+/// relevance ranking.
+pub struct TantivyBackend {
+    index: Index,
+    reader: IndexReader,
+    writer: Mutex<IndexWriter>,
+    fields: Fields,
+}
+
+/// The ids of the uploads matching a search, best match first.
+struct TextMatches {
+    ids: Vec<i32>,
+
+    /// Whether every match is in `ids`, or only the best `MAX_TEXT_MATCHES`.
+    complete: bool,
+}
+
+impl TantivyBackend {
+    /// Opens the index at `path`, creating it if needed.
+    pub fn open(path: &str) -> Result<TantivyBackend, BackendError> {
+        let mut schema_builder = Schema::builder();
+
+        let fields = Fields {
+            id: schema_builder.add_i64_field("id", INDEXED | FAST),
+            tags: schema_builder.add_text_field("tags", TEXT),
+            file_name: schema_builder.add_text_field("file_name", TEXT),
+            description: schema_builder.add_text_field("description", TEXT),
+            comments: schema_builder.add_text_field("comments", TEXT),
+        };
+
+        let index = open_index(path, schema_builder.build())?;
+        let reader = index.reader()?;
+        let writer = index.writer(WRITER_HEAP_SIZE)?;
+
+        Ok(TantivyBackend {
+            index,
+            reader,
+            writer: Mutex::new(writer),
+            fields,
+        })
+    }
+
+    fn to_document(&self, search_document: &SearchDocument) -> Document {
+        let mut document = Document::default();
+
+        document.add_i64(self.fields.id, search_document.id as i64);
+        document.add_text(self.fields.tags, &search_document.tag_string);
+        document.add_text(
+            self.fields.file_name,
+            search_document.file_name.as_deref().unwrap_or_default(),
+        );
+        document.add_text(self.fields.description, &search_document.description);
+        document.add_text(self.fields.comments, &search_document.comments);
+
+        document
+    }
+
+    /// Builds a query where every term has to match at least one of the scoped fields, allowing
+    /// typos in longer terms.
+    fn text_query(&self, text: &str, scope: TextScope) -> Result<BooleanQuery, BackendError> {
+        let mut fields = Vec::new();
+
+        if scope.tags {
+            fields.push((self.fields.tags, TAGS_BOOST));
+            fields.push((self.fields.file_name, FILE_NAME_BOOST));
+        }
+
+        if scope.description {
+            fields.push((self.fields.description, DESCRIPTION_BOOST));
+        }
+
+        if scope.comments {
+            fields.push((self.fields.comments, COMMENTS_BOOST));
+        }
+
+        let tokenizer = self.index.tokenizer_for_field(self.fields.tags)?;
+        let mut token_stream = tokenizer.token_stream(text);
+        let mut term_queries: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+
+        while token_stream.advance() {
+            let token = token_stream.token().text.clone();
+            let distance = match token.chars().count() {
+                0..=3 => 0,
+                4..=7 => 1,
+                _ => 2,
+            };
+
+            let mut field_queries: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+
+            for (field, boost) in fields.iter() {
+                let term = Term::from_field_text(*field, &token);
+
+                // Exact matches are scored by BM25, fuzzy matches only make sure misspelled
+                // terms are found at all.
+                field_queries.push((
+                    Occur::Should,
+                    Box::new(BoostQuery::new(
+                        Box::new(TermQuery::new(term.clone(), IndexRecordOption::WithFreqs)),
+                        *boost,
+                    )),
+                ));
+
+                if distance > 0 {
+                    field_queries.push((
+                        Occur::Should,
+                        Box::new(FuzzyTermQuery::new(term, distance, true)),
+                    ));
+                }
+            }
+
+            term_queries.push((Occur::Must, Box::new(BooleanQuery::from(field_queries))));
+        }
+
+        Ok(BooleanQuery::from(term_queries))
+    }
+
+    /// Gets the ids of the best matches for the text. The index holds uploads in any status, so
+    /// the ids are only narrowed down further by the upload query.
+    fn search(&self, text: &str, scope: TextScope) -> Result<TextMatches, BackendError> {
+        let query = self.text_query(text, scope)?;
+        let searcher = self.reader.searcher();
+        let (count, top_docs) =
+            searcher.search(&query, &(Count, TopDocs::with_limit(MAX_TEXT_MATCHES)))?;
+
+        // Ids are read from the fast field of each segment, without loading any documents.
+        let id_readers = searcher
+            .segment_readers()
+            .iter()
+            .map(|segment_reader| segment_reader.fast_fields().i64(self.fields.id))
+            .collect::<Vec<_>>();
+
+        let ids = top_docs
+            .iter()
+            .filter_map(|(_score, doc_address)| {
+                id_readers[doc_address.segment_ord() as usize]
+                    .as_ref()
+                    .map(|id_reader| id_reader.get(doc_address.doc()) as i32)
+            })
+            .collect();
+
+        Ok(TextMatches {
+            ids,
+            complete: count <= MAX_TEXT_MATCHES,
+        })
+    }
+}
+
+/// Opens the index at `path`, creating it if needed. An index written with an older schema is
+/// recreated empty, and has to be rebuilt.
+fn open_index(path: &str, schema: Schema) -> Result<Index, BackendError> {
+    std::fs::create_dir_all(path)?;
+
+    match Index::open_or_create(MmapDirectory::open(path)?, schema.clone()) {
+        Err(TantivyError::SchemaError(err)) => {
+            warn!(
+                "[search] {}, recreating the index at {}, rebuild it to search text with tantivy",
+                err, path
+            );
+
+            std::fs::remove_dir_all(path)?;
+            std::fs::create_dir_all(path)?;
+
+            Ok(Index::create(MmapDirectory::open(path)?, schema)?)
+        }
+        index => Ok(index?),
+    }
+}
+
+impl SearchBackend for TantivyBackend {
+    fn name(&self) -> &'static str {
+        "tantivy"
+    }
+
+    fn upload_query(
+        &self,
+        conn: &PgConnection,
+        query: &SearchQuery,
+    ) -> Result<UploadQuery, BackendError> {
+        if query.text.is_empty() {
+            return Ok(query.to_upload_query());
+        }
+
+        // An index that was never built would match nothing.
+        if self.reader.searcher().num_docs() == 0 {
+            return PostgresBackend.upload_query(conn, query);
+        }
+
+        let matches = self.search(&query.text, query.scope)?;
+        let ranked_ids = matches
+            .ids
+            .iter()
+            .take(MAX_RANKED_UPLOADS)
+            .copied()
+            .collect();
+
+        // Too many matches to send to Postgres as ids, so Postgres matches the text itself and
+        // only the ranking comes from the index.
+        let upload_query = if matches.complete {
+            SearchQuery {
+                text: String::new(),
+                ..query.clone()
+            }
+            .to_upload_query()
+            .filter(Filter::Ids(matches.ids))
+        } else {
+            query.to_upload_query()
+        };
+
+        // Like the Postgres backend, only the best matches are ranked, but every match is kept.
+        match query.sort {
+            Sort::Relevance(_) => Ok(upload_query.sort(Sort::Relevance(ranked_ids))),
+            _ => Ok(upload_query),
+        }
+    }
+
+    fn index_uploads(&self, conn: &PgConnection, upload_ids: &[i32]) -> Result<(), BackendError> {
+        let search_documents = upload_ids
+            .iter()
+            .map(|upload_id| (*upload_id, upload::search_document(conn, *upload_id)))
+            .collect::<Vec<_>>();
+
+        let mut writer = self
+            .writer
+            .lock()
+            .map_err(|_| BackendError::WriterUnavailable)?;
+
+        for (upload_id, search_document) in search_documents {
+            writer.delete_term(Term::from_field_i64(self.fields.id, upload_id as i64));
+
+            if let Some(search_document) = search_document {
+                writer.add_document(self.to_document(&search_document));
+            }
+        }
+
+        writer.commit()?;
+
+        Ok(())
+    }
+
+    fn rebuild(&self, conn: &PgConnection) -> Result<usize, BackendError> {
+        let mut writer = self
+            .writer
+            .lock()
+            .map_err(|_| BackendError::WriterUnavailable)?;
+
+        writer.delete_all_documents()?;
+
+        let mut indexed = 0;
+        let mut last_id = 0;
+
+        loop {
+            let search_documents = upload::search_documents(conn, last_id, REBUILD_BATCH_SIZE);
+
+            for search_document in search_documents.iter() {
+                writer.add_document(self.to_document(search_document));
+            }
+
+            indexed += search_documents.len();
+
+            debug!("[search] rebuild indexed={}", indexed);
+
+            match search_documents.last() {
+                Some(search_document) if search_documents.len() as i64 == REBUILD_BATCH_SIZE => {
+                    last_id = search_document.id;
+                }
+                _ => break,
+            }
+        }
+
+        writer.commit()?;
+
+        Ok(indexed)
+    }
+}
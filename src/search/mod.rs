@@ -0,0 +1,44 @@
+// This module handles parsing and running search queries, shared by the index page and the JSON
+// API.
+
+mod alias;
+mod backend;
+mod query;
+mod wildcard;
+
+use diesel::PgConnection;
+
+use crate::models::upload::UploadStatus;
+use crate::models::user::User;
+
+pub use backend::{index_upload, index_uploads, rebuild_index, upload_query, BackendError};
+pub use query::{parse_sort, SearchError, SearchQuery};
+pub use wildcard::{WildcardExpansion, MAX_WILDCARD_TAGS};
+
+/// Parses a search query, checks that the given user is allowed to run it, expands wildcard
+/// tags and resolves tag aliases.
+///
+/// A non-empty `sort` overrides any `sort:` given in the query itself.
+pub fn parse(
+    conn: &PgConnection,
+    input: &str,
+    sort: Option<&str>,
+    user: Option<&User>,
+) -> Result<SearchQuery, SearchError> {
+    let mut query = SearchQuery::parse(input)?;
+
+    if let Some(sort) = sort.filter(|sort| !sort.is_empty()) {
+        query.sort = parse_sort(sort)?;
+    }
+
+    let is_contributor = user.map(|user| user.is_contributor()).unwrap_or(false);
+
+    if query.status != UploadStatus::Completed && !is_contributor {
+        return Err(SearchError::StatusNotAllowed);
+    }
+
+    wildcard::expand(conn, &mut query)?;
+    alias::resolve(conn, &mut query);
+
+    Ok(query)
+}
@@ -0,0 +1,726 @@
+use chrono::{Datelike, NaiveDate};
+use nanoid::nanoid;
+use thiserror::Error;
+
+use crate::models::upload::UploadStatus;
+use crate::models::upload_query::{Filter, HasField, Sort, TextScope, UploadQuery};
+use crate::search::wildcard::{self, WildcardExpansion};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SearchError {
+    #[error("Missing a value for `{0}:`")]
+    MissingValue(String),
+
+    #[error("`{0}:` can only be used once per search")]
+    DuplicateQualifier(String),
+
+    #[error("`{0}:` can not be negated")]
+    CannotNegate(String),
+
+    #[error("Unknown status `{0}`, expected one of: completed, pending, processing, failed, deleted, pending_approval")]
+    InvalidStatus(String),
+
+    #[error("Invalid date `{0}`, expected a date like `2015`, `2015-06`, `2015-06-21` or a range like `2014..2016`")]
+    InvalidDate(String),
+
+    #[error("Unknown field `{0}` for `has:`, expected one of: source, description, date")]
+    InvalidHasField(String),
+
+    #[error("Unknown sort order `{0}`, expected one of: newest, oldest, original, original_desc, views, comments, size, random, relevance")]
+    InvalidSort(String),
+
+    #[error("Unknown scope `{0}` for `in:`, expected one of: tags, description, comments, all")]
+    InvalidScope(String),
+
+    #[error("Wildcard `{0}` needs at least one character besides `*`")]
+    WildcardTooBroad(String),
+
+    #[error("Search query has an unterminated quote")]
+    UnterminatedQuote,
+
+    #[error("You do not have permission to search by status")]
+    StatusNotAllowed,
+}
+
+/// A parsed search query.
+///
+/// Bare words are kept as free text and matched against the tag index and file name, the same
+/// way plain searches always have been, unless `in:` widens the scope. Everything else ends up as
+/// a [`Filter`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchQuery {
+    pub text: String,
+    pub scope: TextScope,
+    pub filters: Vec<Filter>,
+    pub status: UploadStatus,
+    pub sort: Sort,
+
+    /// Filled in once wildcard tags have been expanded, see [`wildcard::expand`].
+    pub expansions: Vec<WildcardExpansion>,
+}
+
+impl Default for SearchQuery {
+    fn default() -> SearchQuery {
+        SearchQuery {
+            text: String::new(),
+            scope: TextScope::default(),
+            filters: Vec::new(),
+            status: UploadStatus::Completed,
+            sort: Sort::default(),
+            expansions: Vec::new(),
+        }
+    }
+}
+
+impl SearchQuery {
+    /// Parses a query string such as:
+    ///
+    /// `spinner/foo -type/solo_video ~event/wt13 ~event/wt15 date:2014..2016 has:source sort:oldest`
+    ///
+    /// Supported syntax:
+    ///
+    /// - `-tag` excludes uploads with that tag.
+    /// - `a|b` matches uploads with either tag, every `~tag` in a query forms one such group.
+    /// - `*` in a tag matches any characters, e.g. `spinner/*` or `-event/wt15*`.
+    /// - `uploader:`, `status:`, `date:`, `source:`, `has:` and `sort:` qualifiers.
+    /// - `in:description`, `in:comments`, `in:tags` or `in:all` sets what the free text is
+    ///   matched against, several can be given like `in:tags,description`.
+    /// - `-uploader:`, `-source:` and `-has:` negate the qualifier.
+    /// - Any other `word:rest`, like a URL, is free text.
+    /// - Double quotes group a value containing spaces, e.g. `source:"some url"`.
+    pub fn parse(input: &str) -> Result<SearchQuery, SearchError> {
+        let mut query = SearchQuery::default();
+        let mut text: Vec<String> = Vec::new();
+        let mut tilde_group: Vec<String> = Vec::new();
+        let mut seen_status = false;
+        let mut seen_sort = false;
+        let mut seen_date = false;
+        let mut seen_scope = false;
+
+        for token in tokenize(input)? {
+            let (negated, body) = match token.strip_prefix('-') {
+                Some(rest) if !rest.is_empty() => (true, rest),
+                _ => (false, token.as_str()),
+            };
+
+            if let Some((qualifier, value)) = split_qualifier(body) {
+                let name = qualifier.name().to_owned();
+
+                if value.is_empty() {
+                    return Err(SearchError::MissingValue(name));
+                }
+
+                match qualifier {
+                    Qualifier::Uploader => query.filters.push(Filter::Uploader {
+                        username: value.to_owned(),
+                        negated,
+                    }),
+                    Qualifier::Source => query.filters.push(Filter::Source {
+                        value: value.to_owned(),
+                        negated,
+                    }),
+                    Qualifier::Has => query.filters.push(Filter::Has {
+                        field: parse_has_field(value)?,
+                        negated,
+                    }),
+                    Qualifier::Status | Qualifier::Date | Qualifier::Sort | Qualifier::In
+                        if negated =>
+                    {
+                        return Err(SearchError::CannotNegate(name));
+                    }
+                    Qualifier::Status if seen_status => {
+                        return Err(SearchError::DuplicateQualifier(name));
+                    }
+                    Qualifier::Status => {
+                        query.status = parse_status(value)?;
+                        seen_status = true;
+                    }
+                    Qualifier::Date if seen_date => {
+                        return Err(SearchError::DuplicateQualifier(name));
+                    }
+                    Qualifier::Date => {
+                        let (from, to) = parse_date_range(value)?;
+                        query.filters.push(Filter::OriginalDate { from, to });
+                        seen_date = true;
+                    }
+                    Qualifier::Sort if seen_sort => {
+                        return Err(SearchError::DuplicateQualifier(name));
+                    }
+                    Qualifier::Sort => {
+                        query.sort = parse_sort(value)?;
+                        seen_sort = true;
+                    }
+                    Qualifier::In => {
+                        if !seen_scope {
+                            query.scope = TextScope {
+                                tags: false,
+                                description: false,
+                                comments: false,
+                            };
+                            seen_scope = true;
+                        }
+
+                        add_text_scope(&mut query.scope, value)?;
+                    }
+                }
+
+                continue;
+            }
+
+            if let Some(tag) = body.strip_prefix('~') {
+                if !tag.is_empty() {
+                    tilde_group.push(tag.to_lowercase());
+                }
+            } else if body.contains('|') {
+                let tags = body
+                    .split('|')
+                    .filter(|tag| !tag.is_empty())
+                    .map(|tag| tag.to_lowercase())
+                    .collect::<Vec<String>>();
+
+                if negated {
+                    query
+                        .filters
+                        .extend(tags.into_iter().map(Filter::ExcludeTag));
+                } else if !tags.is_empty() {
+                    query.filters.push(Filter::AnyTag(tags));
+                }
+            } else if negated {
+                query.filters.push(Filter::ExcludeTag(body.to_lowercase()));
+            } else if wildcard::is_wildcard(body) {
+                query
+                    .filters
+                    .push(Filter::AnyTag(vec![body.to_lowercase()]));
+            } else {
+                text.push(body.to_owned());
+            }
+        }
+
+        if !tilde_group.is_empty() {
+            query.filters.push(Filter::AnyTag(tilde_group));
+        }
+
+        query.text = text.join(" ");
+
+        Ok(query)
+    }
+
+    /// Builds the upload listing query for this search.
+    pub fn to_upload_query(&self) -> UploadQuery {
+        let mut upload_query = UploadQuery::new()
+            .filter(Filter::Status(self.status))
+            .sort(self.sort.clone());
+
+        if !self.text.is_empty() {
+            upload_query = upload_query.filter(Filter::Text {
+                text: self.text.clone(),
+                scope: self.scope,
+            });
+        }
+
+        self.filters
+            .iter()
+            .cloned()
+            .fold(upload_query, |upload_query, filter| {
+                upload_query.filter(filter)
+            })
+    }
+
+    /// Returns true if the query doesn't narrow down the default listing at all.
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty() && self.filters.is_empty() && self.status == UploadStatus::Completed
+    }
+}
+
+/// Splits a query into whitespace-separated tokens, keeping double-quoted runs together.
+fn tokenize(input: &str) -> Result<Vec<String>, SearchError> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+
+    for c in input.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            c if c.is_whitespace() && !in_quotes => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err(SearchError::UnterminatedQuote);
+    }
+
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+
+    Ok(tokens)
+}
+
+/// The `name:value` qualifiers a query can use.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Qualifier {
+    Uploader,
+    Status,
+    Date,
+    Source,
+    Has,
+    Sort,
+    In,
+}
+
+impl Qualifier {
+    fn from_name(name: &str) -> Option<Qualifier> {
+        match name.to_lowercase().as_str() {
+            "uploader" => Some(Qualifier::Uploader),
+            "status" => Some(Qualifier::Status),
+            "date" => Some(Qualifier::Date),
+            "source" => Some(Qualifier::Source),
+            "has" => Some(Qualifier::Has),
+            "sort" => Some(Qualifier::Sort),
+            "in" => Some(Qualifier::In),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Qualifier::Uploader => "uploader",
+            Qualifier::Status => "status",
+            Qualifier::Date => "date",
+            Qualifier::Source => "source",
+            Qualifier::Has => "has",
+            Qualifier::Sort => "sort",
+            Qualifier::In => "in",
+        }
+    }
+}
+
+/// Splits `name:value`, only treating it as a qualifier if `name` is a known one. Anything else
+/// is kept as free text, so URLs and tags like `re:zero` can still be searched for.
+fn split_qualifier(token: &str) -> Option<(Qualifier, &str)> {
+    let index = token.find(':')?;
+    let qualifier = Qualifier::from_name(&token[..index])?;
+
+    Some((qualifier, &token[index + 1..]))
+}
+
+fn parse_status(value: &str) -> Result<UploadStatus, SearchError> {
+    match value.to_lowercase().as_str() {
+        "pending" => Ok(UploadStatus::Pending),
+        "processing" => Ok(UploadStatus::Processing),
+        "completed" => Ok(UploadStatus::Completed),
+        "failed" => Ok(UploadStatus::Failed),
+        "deleted" => Ok(UploadStatus::Deleted),
+        "pending_approval" => Ok(UploadStatus::PendingApproval),
+        _ => Err(SearchError::InvalidStatus(value.to_owned())),
+    }
+}
+
+/// Parses a sort order, `random` takes an optional `random:<seed>` to keep the order stable
+/// across pages and gets a fresh seed otherwise.
+///
+/// `relevance` is ranked by the search backend once the rest of the query is known.
+pub fn parse_sort(value: &str) -> Result<Sort, SearchError> {
+    let (name, seed) = match value.find(':') {
+        Some(index) => (&value[..index], Some(&value[index + 1..])),
+        None => (value, None),
+    };
+
+    match (name.to_lowercase().as_str(), seed) {
+        ("newest", None) => Ok(Sort::Newest),
+        ("oldest", None) => Ok(Sort::Oldest),
+        ("original", None) => Ok(Sort::OriginalDate),
+        ("original_desc", None) => Ok(Sort::OriginalDateDesc),
+        ("views", None) => Ok(Sort::MostViewed),
+        ("comments", None) => Ok(Sort::MostCommented),
+        ("size", None) => Ok(Sort::Largest),
+        ("random", None) => Ok(Sort::Random(nanoid!(8))),
+        ("random", Some(seed)) if !seed.is_empty() => Ok(Sort::Random(seed.to_owned())),
+        ("relevance", None) => Ok(Sort::Relevance(Vec::new())),
+        _ => Err(SearchError::InvalidSort(value.to_owned())),
+    }
+}
+
+fn parse_has_field(value: &str) -> Result<HasField, SearchError> {
+    match value.to_lowercase().as_str() {
+        "source" => Ok(HasField::Source),
+        "description" => Ok(HasField::Description),
+        "date" => Ok(HasField::Date),
+        _ => Err(SearchError::InvalidHasField(value.to_owned())),
+    }
+}
+
+/// Adds the comma separated fields of an `in:` qualifier to the scope.
+fn add_text_scope(scope: &mut TextScope, value: &str) -> Result<(), SearchError> {
+    for field in value.split(',') {
+        match field.to_lowercase().as_str() {
+            "tags" => scope.tags = true,
+            "description" => scope.description = true,
+            "comments" => scope.comments = true,
+            "all" => {
+                scope.tags = true;
+                scope.description = true;
+                scope.comments = true;
+            }
+            _ => return Err(SearchError::InvalidScope(field.to_owned())),
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses `2015`, `2015-06`, `2015-06-21`, or a `from..to` range of those where either side can
+/// be left empty.
+fn parse_date_range(value: &str) -> Result<(Option<NaiveDate>, Option<NaiveDate>), SearchError> {
+    let invalid = || SearchError::InvalidDate(value.to_owned());
+
+    let (from, to) = match value.find("..") {
+        Some(index) => {
+            let (start, end) = (&value[..index], &value[index + 2..]);
+
+            if start.is_empty() && end.is_empty() {
+                return Err(invalid());
+            }
+
+            let from = match start {
+                "" => None,
+                start => Some(parse_date_bound(start, false).ok_or_else(invalid)?),
+            };
+
+            let to = match end {
+                "" => None,
+                end => Some(parse_date_bound(end, true).ok_or_else(invalid)?),
+            };
+
+            (from, to)
+        }
+        None => (
+            Some(parse_date_bound(value, false).ok_or_else(invalid)?),
+            Some(parse_date_bound(value, true).ok_or_else(invalid)?),
+        ),
+    };
+
+    match (from, to) {
+        (Some(from), Some(to)) if from > to => Err(invalid()),
+        range => Ok(range),
+    }
+}
+
+/// Parses a possibly partial date, rounding down to the first day of the period, or up to the
+/// last day when `end` is set.
+fn parse_date_bound(value: &str, end: bool) -> Option<NaiveDate> {
+    let parts = value
+        .split('-')
+        .map(|part| part.parse::<u32>().ok())
+        .collect::<Option<Vec<u32>>>()?;
+
+    match parts.as_slice() {
+        [year] if end => NaiveDate::from_ymd_opt(*year as i32, 12, 31),
+        [year] => NaiveDate::from_ymd_opt(*year as i32, 1, 1),
+        [year, month] if end => {
+            let first = NaiveDate::from_ymd_opt(*year as i32, *month, 1)?;
+            let next_month = if first.month() == 12 {
+                NaiveDate::from_ymd_opt(first.year() + 1, 1, 1)?
+            } else {
+                NaiveDate::from_ymd_opt(first.year(), first.month() + 1, 1)?
+            };
+
+            next_month.pred_opt()
+        }
+        [year, month] => NaiveDate::from_ymd_opt(*year as i32, *month, 1),
+        [year, month, day] => NaiveDate::from_ymd_opt(*year as i32, *month, *day),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> SearchQuery {
+        SearchQuery::parse(input).unwrap()
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> Option<NaiveDate> {
+        Some(NaiveDate::from_ymd(year, month, day))
+    }
+
+    #[test]
+    fn keeps_bare_words_as_text() {
+        let query = parse("Spinner/Foo  bar");
+
+        assert_eq!(query.text, "Spinner/Foo bar");
+        assert!(query.filters.is_empty());
+        assert!(!query.is_empty());
+        assert!(parse("").is_empty());
+    }
+
+    #[test]
+    fn keeps_unknown_qualifiers_as_text() {
+        let query = parse("re:zero https://example.com/a:b");
+
+        assert_eq!(query.text, "re:zero https://example.com/a:b");
+        assert!(query.filters.is_empty());
+
+        assert_eq!(
+            parse("-re:zero").filters,
+            vec![Filter::ExcludeTag("re:zero".to_owned())]
+        );
+    }
+
+    #[test]
+    fn groups_quoted_values() {
+        assert_eq!(
+            parse(r#"source:"some url" "two words""#).filters,
+            vec![Filter::Source {
+                value: "some url".to_owned(),
+                negated: false,
+            }]
+        );
+        assert_eq!(parse(r#""two words""#).text, "two words");
+        assert_eq!(
+            SearchQuery::parse(r#"source:"some url"#),
+            Err(SearchError::UnterminatedQuote)
+        );
+    }
+
+    #[test]
+    fn negates_tags_and_qualifiers() {
+        assert_eq!(
+            parse("-Type/Solo_Video -uploader:bob -source:youtube -has:date").filters,
+            vec![
+                Filter::ExcludeTag("type/solo_video".to_owned()),
+                Filter::Uploader {
+                    username: "bob".to_owned(),
+                    negated: true,
+                },
+                Filter::Source {
+                    value: "youtube".to_owned(),
+                    negated: true,
+                },
+                Filter::Has {
+                    field: HasField::Date,
+                    negated: true,
+                },
+            ]
+        );
+
+        assert_eq!(parse("-").text, "-");
+
+        for qualifier in &["status", "date", "sort", "in"] {
+            assert_eq!(
+                SearchQuery::parse(&format!("-{}:x", qualifier)),
+                Err(SearchError::CannotNegate((*qualifier).to_owned()))
+            );
+        }
+    }
+
+    #[test]
+    fn groups_tilde_tags() {
+        assert_eq!(
+            parse("~event/wt13 spinner/foo ~Event/WT15 ~").filters,
+            vec![Filter::AnyTag(vec![
+                "event/wt13".to_owned(),
+                "event/wt15".to_owned()
+            ])]
+        );
+    }
+
+    #[test]
+    fn groups_pipe_tags() {
+        assert_eq!(
+            parse("a|B||c").filters,
+            vec![Filter::AnyTag(vec![
+                "a".to_owned(),
+                "b".to_owned(),
+                "c".to_owned()
+            ])]
+        );
+        assert_eq!(
+            parse("-a|b").filters,
+            vec![
+                Filter::ExcludeTag("a".to_owned()),
+                Filter::ExcludeTag("b".to_owned()),
+            ]
+        );
+        assert!(parse("|").filters.is_empty());
+    }
+
+    #[test]
+    fn matches_wildcards_as_tags() {
+        assert_eq!(
+            parse("Spinner/*").filters,
+            vec![Filter::AnyTag(vec!["spinner/*".to_owned()])]
+        );
+        assert_eq!(
+            parse("-event/wt15*").filters,
+            vec![Filter::ExcludeTag("event/wt15*".to_owned())]
+        );
+    }
+
+    #[test]
+    fn parses_uploader() {
+        assert_eq!(
+            parse("Uploader:Bob").filters,
+            vec![Filter::Uploader {
+                username: "Bob".to_owned(),
+                negated: false,
+            }]
+        );
+        assert_eq!(
+            SearchQuery::parse("uploader:"),
+            Err(SearchError::MissingValue("uploader".to_owned()))
+        );
+    }
+
+    #[test]
+    fn parses_status() {
+        assert_eq!(parse("").status, UploadStatus::Completed);
+        assert_eq!(parse("status:deleted").status, UploadStatus::Deleted);
+        assert_eq!(
+            parse("status:Pending_Approval").status,
+            UploadStatus::PendingApproval
+        );
+        assert_eq!(
+            SearchQuery::parse("status:gone"),
+            Err(SearchError::InvalidStatus("gone".to_owned()))
+        );
+        assert_eq!(
+            SearchQuery::parse("status:failed status:pending"),
+            Err(SearchError::DuplicateQualifier("status".to_owned()))
+        );
+    }
+
+    #[test]
+    fn parses_date() {
+        let range = |input: &str| match parse(input).filters.as_slice() {
+            [Filter::OriginalDate { from, to }] => (*from, *to),
+            filters => panic!("unexpected filters {:?}", filters),
+        };
+
+        assert_eq!(range("date:2015"), (date(2015, 1, 1), date(2015, 12, 31)));
+        assert_eq!(range("date:2016-02"), (date(2016, 2, 1), date(2016, 2, 29)));
+        assert_eq!(
+            range("date:2015-12"),
+            (date(2015, 12, 1), date(2015, 12, 31))
+        );
+        assert_eq!(
+            range("date:2015-06-21"),
+            (date(2015, 6, 21), date(2015, 6, 21))
+        );
+        assert_eq!(
+            range("date:2014..2016"),
+            (date(2014, 1, 1), date(2016, 12, 31))
+        );
+        assert_eq!(range("date:2014.."), (date(2014, 1, 1), None));
+        assert_eq!(range("date:..2016-06"), (None, date(2016, 6, 30)));
+
+        for value in &["..", "2016..2014", "2015-13", "soon"] {
+            assert_eq!(
+                SearchQuery::parse(&format!("date:{}", value)),
+                Err(SearchError::InvalidDate((*value).to_owned()))
+            );
+        }
+
+        assert_eq!(
+            SearchQuery::parse("date:2014 date:2015"),
+            Err(SearchError::DuplicateQualifier("date".to_owned()))
+        );
+    }
+
+    #[test]
+    fn parses_source() {
+        assert_eq!(
+            parse("source:https://youtube.com/watch?v=a:b").filters,
+            vec![Filter::Source {
+                value: "https://youtube.com/watch?v=a:b".to_owned(),
+                negated: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_has() {
+        assert_eq!(
+            parse("has:source has:Description").filters,
+            vec![
+                Filter::Has {
+                    field: HasField::Source,
+                    negated: false,
+                },
+                Filter::Has {
+                    field: HasField::Description,
+                    negated: false,
+                },
+            ]
+        );
+        assert_eq!(
+            SearchQuery::parse("has:tags"),
+            Err(SearchError::InvalidHasField("tags".to_owned()))
+        );
+    }
+
+    #[test]
+    fn parses_sort() {
+        assert_eq!(parse("").sort, Sort::Newest);
+        assert_eq!(parse("sort:oldest").sort, Sort::Oldest);
+        assert_eq!(parse("Sort:Original_Desc").sort, Sort::OriginalDateDesc);
+        assert_eq!(parse("sort:relevance").sort, Sort::Relevance(Vec::new()));
+        assert_eq!(
+            parse("sort:random:abc").sort,
+            Sort::Random("abc".to_owned())
+        );
+
+        match parse("sort:random").sort {
+            Sort::Random(seed) => assert_eq!(seed.len(), 8),
+            sort => panic!("unexpected sort {:?}", sort),
+        }
+
+        assert_eq!(
+            SearchQuery::parse("sort:random:"),
+            Err(SearchError::InvalidSort("random:".to_owned()))
+        );
+        assert_eq!(
+            SearchQuery::parse("sort:oldest sort:newest"),
+            Err(SearchError::DuplicateQualifier("sort".to_owned()))
+        );
+    }
+
+    #[test]
+    fn parses_in() {
+        assert_eq!(parse("").scope, TextScope::default());
+        assert_eq!(
+            parse("in:description in:Comments").scope,
+            TextScope {
+                tags: false,
+                description: true,
+                comments: true,
+            }
+        );
+        assert_eq!(
+            parse("in:tags,description").scope,
+            TextScope {
+                tags: true,
+                description: true,
+                comments: false,
+            }
+        );
+        assert_eq!(
+            parse("in:all").scope,
+            TextScope {
+                tags: true,
+                description: true,
+                comments: true,
+            }
+        );
+        assert_eq!(
+            SearchQuery::parse("in:files"),
+            Err(SearchError::InvalidScope("files".to_owned()))
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use diesel::PgConnection;
+
+use crate::models::tag;
+use crate::models::upload_query::Filter;
+use crate::search::{SearchError, SearchQuery};
+
+/// The most tags a single wildcard term can expand to, the most used tags are kept.
+pub const MAX_WILDCARD_TAGS: i64 = 50;
+
+/// The tags a wildcard term such as `spinner/*` or `event/wt15*` was expanded to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WildcardExpansion {
+    pub pattern: String,
+    pub tags: Vec<String>,
+
+    /// Set when more tags matched than [`MAX_WILDCARD_TAGS`].
+    pub truncated: bool,
+}
+
+pub fn is_wildcard(tag: &str) -> bool {
+    tag.contains('*')
+}
+
+/// Replaces every wildcard tag in the query's filters with the tags it matches.
+///
+/// A wildcard that matches no tags is kept as an empty group, so the search returns nothing
+/// rather than silently ignoring the term.
+pub fn expand(conn: &PgConnection, query: &mut SearchQuery) -> Result<(), SearchError> {
+    let mut filters = Vec::with_capacity(query.filters.len());
+
+    for filter in std::mem::take(&mut query.filters) {
+        match filter {
+            Filter::AnyTag(tags) if tags.iter().any(|tag| is_wildcard(tag)) => {
+                let mut expanded = Vec::new();
+
+                for tag in tags {
+                    if is_wildcard(&tag) {
+                        expanded.extend(expand_pattern(conn, query, &tag)?);
+                    } else {
+                        expanded.push(tag);
+                    }
+                }
+
+                expanded.sort();
+                expanded.dedup();
+
+                filters.push(Filter::AnyTag(expanded));
+            }
+            Filter::ExcludeTag(tag) if is_wildcard(&tag) => {
+                let expanded = expand_pattern(conn, query, &tag)?;
+
+                filters.extend(expanded.into_iter().map(Filter::ExcludeTag));
+            }
+            filter => filters.push(filter),
+        }
+    }
+
+    query.filters = filters;
+
+    Ok(())
+}
+
+/// Looks up the tags matching `pattern` and records the expansion on the query.
+fn expand_pattern(
+    conn: &PgConnection,
+    query: &mut SearchQuery,
+    pattern: &str,
+) -> Result<Vec<String>, SearchError> {
+    if pattern.chars().all(|c| c == '*') {
+        return Err(SearchError::WildcardTooBroad(pattern.to_owned()));
+    }
+
+    let mut tags = tag::names_like(conn, &to_like_pattern(pattern), MAX_WILDCARD_TAGS + 1);
+    let truncated = tags.len() as i64 > MAX_WILDCARD_TAGS;
+
+    tags.truncate(MAX_WILDCARD_TAGS as usize);
+
+    if !query
+        .expansions
+        .iter()
+        .any(|expansion| expansion.pattern == pattern)
+    {
+        query.expansions.push(WildcardExpansion {
+            pattern: pattern.to_owned(),
+            tags: tags.clone(),
+            truncated,
+        });
+    }
+
+    Ok(tags)
+}
+
+/// Turns `event/wt15*` into the `LIKE` pattern `event/wt15%`, escaping `%` and `_` which are
+/// common in tag names.
+fn to_like_pattern(pattern: &str) -> String {
+    let mut like = String::with_capacity(pattern.len());
+
+    for c in pattern.chars() {
+        match c {
+            '\\' | '%' | '_' => {
+                like.push('\\');
+                like.push(c);
+            }
+            '*' => like.push('%'),
+            c => like.push(c),
+        }
+    }
+
+    like
+}
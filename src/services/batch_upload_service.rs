@@ -0,0 +1,175 @@
+// Uploads of many files at once, for archiving whole folders. Every file gets its own outcome, so
+// one bad file doesn't hold up the rest of the batch.
+//
+// The details of each file are checked and stored on its pending upload when the batch is
+// started, so finalizing the batch only needs the ids of the files that were uploaded.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use chrono::NaiveDate;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::models::upload::{self, UpdateUpload, Upload, UploadStatus};
+use crate::models::user::User;
+use crate::services::upload_service::{self, UploadError};
+
+/// Most files a single batch can have.
+pub const MAX_BATCH_FILES: usize = 500;
+
+/// Most files that can be finalized in one call. Every file is checked in storage one after the
+/// other, so bigger batches are finalized over several calls.
+pub const MAX_BATCH_FINALIZE_FILES: usize = 50;
+
+#[derive(Error, Debug)]
+pub(crate) enum BatchError {
+    #[error("{0}")]
+    Upload(#[from] UploadError),
+
+    #[error("Invalid file_name provided.")]
+    InvalidFileName,
+
+    #[error("Invalid original_upload_date provided.")]
+    InvalidOriginalUploadDate,
+}
+
+impl From<diesel::result::Error> for BatchError {
+    fn from(_err: diesel::result::Error) -> Self {
+        BatchError::Upload(UploadError::DatabaseError)
+    }
+}
+
+/// A file to upload as part of a batch, with everything needed to finalize it.
+#[derive(Debug, Deserialize)]
+pub struct BatchFile {
+    pub file_name: String,
+    pub content_length: i64,
+    pub md5_hash: String,
+    pub tags: String,
+
+    #[serde(default)]
+    pub source: String,
+
+    #[serde(default)]
+    pub description: String,
+
+    /// Formatted as `YYYY-MM-DD`, empty or missing if unknown.
+    pub original_upload_date: Option<String>,
+}
+
+/// What happened to a file of a batch.
+pub(crate) enum BatchOutcome {
+    /// A pending upload was created, the file can be sent to storage.
+    Created(Upload),
+
+    /// The file was already uploaded, either before or earlier in the same batch.
+    Duplicate(Upload),
+
+    Failed(BatchError),
+}
+
+/// Starts uploading every file, in order. Duplicates are found by MD5 hash and aren't counted
+/// towards the daily upload limit.
+pub(crate) fn start(conn: &PgConnection, user: &User, files: &[BatchFile]) -> Vec<BatchOutcome> {
+    let md5_hashes = files
+        .iter()
+        .map(|file| file.md5_hash.clone())
+        .collect::<Vec<String>>();
+
+    let mut existing = upload_service::where_md5(conn, &md5_hashes)
+        .into_iter()
+        .filter_map(|upload| Some((upload.md5_hash.clone()?, upload)))
+        .collect::<HashMap<String, Upload>>();
+
+    files
+        .iter()
+        .map(|file| {
+            if let Some(upload) = existing.get(&file.md5_hash) {
+                return BatchOutcome::Duplicate(upload.clone());
+            }
+
+            match start_file(conn, user, file) {
+                Ok(upload) => {
+                    existing.insert(file.md5_hash.clone(), upload.clone());
+
+                    BatchOutcome::Created(upload)
+                }
+                Err(err) => BatchOutcome::Failed(err),
+            }
+        })
+        .collect()
+}
+
+/// Checks a file's details and creates its pending upload with them.
+fn start_file(conn: &PgConnection, user: &User, file: &BatchFile) -> Result<Upload, BatchError> {
+    let path = Path::new(&file.file_name);
+
+    let (name, ext) = match (path.file_name(), path.extension()) {
+        (Some(name), Some(ext)) => (name.to_string_lossy(), ext.to_string_lossy()),
+        _ => return Err(BatchError::InvalidFileName),
+    };
+
+    let original_upload_date = match file.original_upload_date.as_deref() {
+        None | Some("") => None,
+        Some(date) => Some(
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map_err(|_| BatchError::InvalidOriginalUploadDate)?,
+        ),
+    };
+
+    let tag_string = upload_service::validate_tags(conn, &file.tags, None)?;
+
+    conn.transaction::<_, BatchError, _>(|| {
+        let upload = upload_service::new_pending_upload(
+            conn,
+            user,
+            &name,
+            &ext,
+            file.content_length,
+            Some(file.md5_hash.clone()),
+        )?;
+
+        let update_upload = UpdateUpload {
+            id: upload.id,
+            status: UploadStatus::Pending,
+            source: Some(file.source.clone()),
+            tag_string,
+            description: file.description.clone(),
+            original_upload_date,
+        };
+
+        Ok(upload::update(conn, &update_upload)?)
+    })
+}
+
+/// Finalizes the user's pending uploads with the details given when the batch was started.
+/// Files that aren't in storage yet fail and stay pending, so they can be finalized later, but
+/// only until `upload_cleanup_service` removes them as abandoned, 48 hours after the batch was
+/// started by default.
+pub(crate) fn finalize(
+    conn: &PgConnection,
+    user: &User,
+    file_ids: &[String],
+) -> Vec<Result<Upload, UploadError>> {
+    file_ids
+        .iter()
+        .map(|file_id| {
+            let upload = upload::get_by_file_id(conn, file_id)
+                .filter(|upload| upload.uploader_user_id == Some(user.id))
+                .ok_or(UploadError::NotFound)?;
+
+            upload_service::finalize_upload(
+                conn,
+                user,
+                &upload.file_id,
+                &upload.tag_string,
+                upload.source.as_deref().unwrap_or_default(),
+                &upload.description,
+                upload.original_upload_date,
+            )
+        })
+        .collect()
+}
@@ -3,6 +3,7 @@ use diesel::PgConnection;
 use crate::models::upload::Upload;
 use crate::models::upload_comment::{self, NewUploadComment, UpdateUploadComment, UploadComment};
 use crate::models::user::User;
+use crate::search;
 
 pub use crate::models::upload_comment::{
     get_comment_by_id, get_comment_count_by_user_id, get_paginated_comments, get_recent_comments,
@@ -20,7 +21,10 @@ pub fn create_comment_on_upload(
         comment: comment.to_string(),
     };
 
-    upload_comment::insert(conn, &comment).ok()
+    let upload_comment = upload_comment::insert(conn, &comment).ok()?;
+    search::index_upload(conn, upload.id);
+
+    Some(upload_comment)
 }
 
 pub fn edit_comment(
@@ -37,7 +41,10 @@ pub fn edit_comment(
         comment: comment.to_string(),
     };
 
-    upload_comment::update(&conn, upload_comment.id, &update).ok()
+    let upload_comment = upload_comment::update(&conn, upload_comment.id, &update).ok()?;
+    search::index_upload(conn, upload_comment.upload_id);
+
+    Some(upload_comment)
 }
 
 pub fn get_comments_for_upload(conn: &PgConnection, upload: &Upload) -> Vec<(UploadComment, User)> {
@@ -9,6 +9,7 @@ use serde_json::Value;
 use crate::database::DatabaseConnection;
 use crate::models::upload::{self, FinishedEncodingUpload, Upload, UploadStatus};
 use crate::models::user::get_user_by_id;
+use crate::services::tag_service;
 
 const HOST: &'static str = "https://s3.us-west-1.wasabisys.com";
 const BUCKET: &'static str = "bits.spin-archive.org";
@@ -114,7 +115,11 @@ pub fn accept_webhook(
                 };
 
                 match upload::update_encoding(&conn, upload.id, &finished_encoding) {
-                    Ok(upload) => Ok(upload),
+                    Ok(new_upload) => {
+                        let _ =
+                            tag_service::update_upload_counts(&conn, Some(&upload), &new_upload);
+                        Ok(new_upload)
+                    }
                     Err(_) => Err(EncoderError::ApiFailure),
                 }
             } else {
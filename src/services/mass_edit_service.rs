@@ -0,0 +1,304 @@
+use diesel::prelude::*;
+use diesel::PgConnection;
+use log::{debug, warn};
+use thiserror::Error;
+
+use crate::models::upload::{self, FullUpload, Upload};
+use crate::models::upload_query::Sort;
+use crate::models::user::User;
+use crate::search::{self, SearchError, SearchQuery};
+use crate::services::tag_policy_service;
+use crate::services::upload_service::{self, UploadError};
+
+/// How many uploads are changed per transaction.
+const EDIT_BATCH_SIZE: i64 = 100;
+
+/// How many uploads are shown when previewing a mass edit.
+const PREVIEW_LIMIT: i64 = 24;
+
+/// The most uploads a single mass edit can change, larger cleanups are better done as a tag rename
+/// or in several steps.
+pub const MAX_MASS_EDIT_UPLOADS: i64 = 5_000;
+
+#[derive(Error, Debug)]
+pub(crate) enum MassEditError {
+    #[error("{0}")]
+    InvalidQuery(#[from] SearchError),
+
+    #[error("A search query is needed, mass edits can't change every upload")]
+    EmptyQuery,
+
+    #[error("`{0}` is not a tag operation, expected `+tag`, `-tag` or `old->new`")]
+    InvalidOperation(String),
+
+    #[error("No tag operations were given")]
+    NoOperations,
+
+    #[error(
+        "{0} uploads match, mass edits can change at most {}",
+        MAX_MASS_EDIT_UPLOADS
+    )]
+    TooManyUploads(i64),
+
+    #[error("Error occured in database")]
+    DatabaseError,
+}
+
+/// A change to make to the tags of every matching upload.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagOperation {
+    Add(String),
+    Remove(String),
+    Replace(String, String),
+}
+
+impl std::fmt::Display for TagOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TagOperation::Add(tag) => write!(f, "+{}", tag),
+            TagOperation::Remove(tag) => write!(f, "-{}", tag),
+            TagOperation::Replace(from, to) => write!(f, "{}->{}", from, to),
+        }
+    }
+}
+
+/// How a mass edit would change a single upload.
+pub struct UploadChange {
+    pub upload: FullUpload,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+
+    /// Set when the new tags break the tag policy, such uploads are skipped.
+    pub error: Option<String>,
+}
+
+/// What a mass edit would do, shown to moderators before it's applied.
+pub struct MassEditPreview {
+    pub upload_count: i64,
+    pub changes: Vec<UploadChange>,
+}
+
+/// What a finished mass edit did.
+pub struct MassEditResult {
+    pub changed: usize,
+    pub skipped: usize,
+}
+
+/// Parses the search query selecting the uploads to edit. Uploads are always visited newest
+/// first, so the order doesn't depend on anything the edit changes.
+pub(crate) fn parse_query(
+    conn: &PgConnection,
+    input: &str,
+    user: &User,
+) -> Result<SearchQuery, MassEditError> {
+    if input.trim().is_empty() {
+        return Err(MassEditError::EmptyQuery);
+    }
+
+    let mut query = search::parse(conn, input, None, Some(user))?;
+    query.sort = Sort::Newest;
+
+    Ok(query)
+}
+
+/// Parses whitespace separated tag operations, such as `+type/solo_video -tagme sv/a->sv/b`.
+pub(crate) fn parse_operations(input: &str) -> Result<Vec<TagOperation>, MassEditError> {
+    let operations = input
+        .split_whitespace()
+        .map(|operation| {
+            let invalid = || MassEditError::InvalidOperation(operation.to_owned());
+            let tag =
+                |tag: &str| Some(tag_policy_service::normalize(tag)).filter(|t| !t.is_empty());
+
+            if let Some(index) = operation.find("->") {
+                match (tag(&operation[..index]), tag(&operation[index + 2..])) {
+                    (Some(from), Some(to)) if from != to => Ok(TagOperation::Replace(from, to)),
+                    _ => Err(invalid()),
+                }
+            } else if let Some(tag_name) = operation.strip_prefix('+') {
+                tag(tag_name).map(TagOperation::Add).ok_or_else(invalid)
+            } else if let Some(tag_name) = operation.strip_prefix('-') {
+                tag(tag_name).map(TagOperation::Remove).ok_or_else(invalid)
+            } else {
+                Err(invalid())
+            }
+        })
+        .collect::<Result<Vec<TagOperation>, MassEditError>>()?;
+
+    if operations.is_empty() {
+        return Err(MassEditError::NoOperations);
+    }
+
+    Ok(operations)
+}
+
+/// Applies the operations to a tag string, in order.
+fn apply_operations(tag_string: &str, operations: &[TagOperation]) -> String {
+    let mut tags = tag_string
+        .split_whitespace()
+        .map(str::to_owned)
+        .collect::<Vec<String>>();
+
+    for operation in operations {
+        match operation {
+            TagOperation::Add(tag) => {
+                if !tags.contains(tag) {
+                    tags.push(tag.clone());
+                }
+            }
+            TagOperation::Remove(tag) => tags.retain(|existing| existing != tag),
+            TagOperation::Replace(from, to) => {
+                if tags.contains(from) {
+                    tags.retain(|existing| existing != from && existing != to);
+                    tags.push(to.clone());
+                }
+            }
+        }
+    }
+
+    tags.join(" ")
+}
+
+/// Works out what a mass edit would do, without changing anything.
+pub(crate) fn preview(
+    conn: &PgConnection,
+    query: &SearchQuery,
+    operations: &[TagOperation],
+) -> Result<MassEditPreview, MassEditError> {
+    let (uploads, _page_count, upload_count) = upload::index(conn, 1, PREVIEW_LIMIT, query);
+
+    if upload_count > MAX_MASS_EDIT_UPLOADS {
+        return Err(MassEditError::TooManyUploads(upload_count));
+    }
+
+    let changes = uploads
+        .into_iter()
+        .map(|upload| {
+            let old_tags = upload.tag_string.split_whitespace().collect::<Vec<&str>>();
+            let tags = apply_operations(&upload.tag_string, operations);
+
+            match upload_service::validate_tags(conn, &tags, Some(&upload.tag_string)) {
+                Ok(tag_string) => {
+                    let new_tags = tag_string.split_whitespace().collect::<Vec<&str>>();
+
+                    UploadChange {
+                        added: difference(&new_tags, &old_tags),
+                        removed: difference(&old_tags, &new_tags),
+                        error: None,
+                        upload,
+                    }
+                }
+                Err(err) => UploadChange {
+                    added: Vec::new(),
+                    removed: Vec::new(),
+                    error: Some(err.to_string()),
+                    upload,
+                },
+            }
+        })
+        .collect();
+
+    Ok(MassEditPreview {
+        upload_count,
+        changes,
+    })
+}
+
+fn difference(tags: &[&str], other: &[&str]) -> Vec<String> {
+    tags.iter()
+        .filter(|tag| !other.contains(tag))
+        .map(|tag| (*tag).to_owned())
+        .collect()
+}
+
+/// Applies the operations to every upload matching the query, on behalf of `changed_by`.
+///
+/// The matching uploads are looked up before anything changes, so uploads the edit makes stop
+/// matching are still visited exactly once. Every upload is updated the way a regular edit would
+/// be, with its own audit log entry, and uploads whose new tags break the tag policy are skipped.
+/// Each batch of uploads is changed in its own transaction and reindexed once it commits.
+pub(crate) fn apply(
+    conn: &PgConnection,
+    changed_by: i32,
+    query: &SearchQuery,
+    operations: &[TagOperation],
+) -> Result<MassEditResult, MassEditError> {
+    let mut upload_ids: Vec<i32> = Vec::new();
+    let mut cursor = None;
+
+    loop {
+        let (uploads, next_cursor) = upload::index_after(conn, cursor, EDIT_BATCH_SIZE, query);
+
+        upload_ids.extend(uploads.iter().map(|upload| upload.id));
+
+        if upload_ids.len() as i64 > MAX_MASS_EDIT_UPLOADS {
+            return Err(MassEditError::TooManyUploads(upload_ids.len() as i64));
+        }
+
+        match next_cursor {
+            Some(next_cursor) => cursor = Some(next_cursor),
+            None => break,
+        }
+    }
+
+    let mut result = MassEditResult {
+        changed: 0,
+        skipped: 0,
+    };
+
+    for batch in upload_ids.chunks(EDIT_BATCH_SIZE as usize) {
+        let (changed_ids, skipped) = conn
+            .transaction::<_, diesel::result::Error, _>(|| {
+                let (mut changed_ids, mut skipped) = (Vec::new(), 0);
+
+                for upload in upload::by_ids(conn, batch)? {
+                    match edit_upload(conn, changed_by, &upload, operations) {
+                        Ok(true) => changed_ids.push(upload.id),
+                        Ok(false) => {}
+                        Err(UploadError::InvalidTags(violations)) => {
+                            warn!(
+                                "[mass_edit] skipped upload {}: {}",
+                                upload.file_id,
+                                tag_policy_service::describe(&violations)
+                            );
+                            skipped += 1;
+                        }
+                        Err(_) => return Err(diesel::result::Error::RollbackTransaction),
+                    }
+                }
+
+                Ok((changed_ids, skipped))
+            })
+            .map_err(|_| MassEditError::DatabaseError)?;
+
+        search::index_uploads(conn, &changed_ids);
+
+        result.changed += changed_ids.len();
+        result.skipped += skipped;
+
+        debug!(
+            "[mass_edit] batch done changed={} skipped={}",
+            result.changed, result.skipped
+        );
+    }
+
+    Ok(result)
+}
+
+/// Edits the tags of a single upload, returning whether anything changed.
+fn edit_upload(
+    conn: &PgConnection,
+    changed_by: i32,
+    upload: &Upload,
+    operations: &[TagOperation],
+) -> Result<bool, UploadError> {
+    let tags = apply_operations(&upload.tag_string, operations);
+
+    if tags == upload.tag_string {
+        return Ok(false);
+    }
+
+    let new_upload = upload_service::update_tags(conn, changed_by, upload, &tags)?;
+
+    Ok(new_upload.tag_string != upload.tag_string)
+}
@@ -1,7 +1,19 @@
 pub(crate) mod api_token_service;
 pub(crate) mod audit_service;
+pub(crate) mod batch_upload_service;
 pub(crate) mod comment_service;
 pub(crate) mod encoder_service;
+pub(crate) mod mass_edit_service;
+pub(crate) mod multipart_upload_service;
 pub(crate) mod notification_service;
+pub(crate) mod quota_service;
+pub(crate) mod saved_search_service;
+pub(crate) mod tag_alias_service;
+pub(crate) mod tag_category_service;
+pub(crate) mod tag_implication_service;
+pub(crate) mod tag_policy_service;
+pub(crate) mod tag_rename_service;
 pub(crate) mod tag_service;
+pub(crate) mod tag_wiki_service;
+pub(crate) mod upload_cleanup_service;
 pub(crate) mod upload_service;
@@ -0,0 +1,261 @@
+// Resumable uploads of large files, sent straight to storage in parts. A multipart upload belongs
+// to a pending upload: parts can be retried or resumed in any order until it's completed, after
+// which the pending upload is finalized as usual.
+
+use diesel::PgConnection;
+use log::warn;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::models::upload::{self, Upload, UploadStatus};
+use crate::models::user::User;
+use crate::s3_client::{self, UploadedPart};
+use crate::services::upload_service::{self, UploadError};
+
+/// Smallest part that can be uploaded, storage requires at least 5 MiB for every part but the last.
+const MIN_PART_SIZE: i64 = 8 * 1024 * 1024;
+
+/// Most parts a multipart upload can have.
+const MAX_PARTS: i64 = 10_000;
+
+/// Most part URLs handed out at once.
+const MAX_PART_URLS: usize = 100;
+
+#[derive(Error, Debug)]
+pub(crate) enum MultipartError {
+    #[error("{0}")]
+    Upload(#[from] UploadError),
+
+    #[error("Upload was not found")]
+    NotFound,
+
+    #[error("Upload was already finalized")]
+    NotPending,
+
+    #[error("Upload is not being sent as a multipart upload")]
+    NotMultipart,
+
+    #[error("Part {0} is out of range")]
+    InvalidPartNumber(i64),
+
+    #[error("At most {} part URLs can be requested at once", MAX_PART_URLS)]
+    TooManyParts,
+
+    #[error("Parts {0:?} have not been uploaded")]
+    MissingParts(Vec<i64>),
+
+    #[error("Storage error: {0}")]
+    StorageError(String),
+
+    #[error("Error occured in database")]
+    DatabaseError,
+}
+
+impl From<diesel::result::Error> for MultipartError {
+    fn from(_err: diesel::result::Error) -> Self {
+        MultipartError::DatabaseError
+    }
+}
+
+/// How a file should be split up, parts are numbered from 1 and all but the last are
+/// `part_size` bytes.
+#[derive(Debug, Serialize)]
+pub struct MultipartUpload {
+    pub id: String,
+    pub part_size: i64,
+    pub part_count: i64,
+}
+
+impl MultipartUpload {
+    fn new(upload: &Upload) -> MultipartUpload {
+        let file_size = upload.file_size.unwrap_or_default().max(1);
+        let part_size = MIN_PART_SIZE.max((file_size + MAX_PARTS - 1) / MAX_PARTS);
+
+        MultipartUpload {
+            id: upload.file_id.clone(),
+            part_size,
+            part_count: (file_size + part_size - 1) / part_size,
+        }
+    }
+}
+
+/// A pre-signed url to `PUT` a single part to.
+#[derive(Debug, Serialize)]
+pub struct PartUrl {
+    pub part_number: i64,
+    pub url: String,
+}
+
+/// A part storage has received.
+#[derive(Debug, Serialize)]
+pub struct ReceivedPart {
+    pub part_number: i64,
+    pub size: i64,
+}
+
+fn storage_file_name(upload: &Upload) -> String {
+    format!("{}.{}", upload.file_id, upload.file_ext)
+}
+
+fn storage_error(err: anyhow::Error) -> MultipartError {
+    MultipartError::StorageError(err.to_string())
+}
+
+/// Finds a pending upload of the user that's being sent as a multipart upload, along with its
+/// storage upload id.
+fn pending_multipart(
+    conn: &PgConnection,
+    user: &User,
+    file_id: &str,
+) -> Result<(Upload, String), MultipartError> {
+    let upload = upload::get_by_file_id(conn, file_id)
+        .filter(|upload| upload.uploader_user_id == Some(user.id))
+        .ok_or(MultipartError::NotFound)?;
+
+    if upload.status != UploadStatus::Pending {
+        return Err(MultipartError::NotPending);
+    }
+
+    let multipart_upload_id =
+        upload::multipart_upload_id(conn, upload.id).ok_or(MultipartError::NotMultipart)?;
+
+    Ok((upload, multipart_upload_id))
+}
+
+/// Creates a pending upload and starts sending it to storage as a multipart upload.
+pub(crate) fn start(
+    conn: &PgConnection,
+    user: &User,
+    file_name: &str,
+    file_ext: &str,
+    file_size: i64,
+    md5_hash: Option<String>,
+) -> Result<MultipartUpload, MultipartError> {
+    let upload =
+        upload_service::new_pending_upload(conn, user, file_name, file_ext, file_size, md5_hash)?;
+
+    let multipart_upload_id =
+        match s3_client::create_multipart_upload("uploads", &storage_file_name(&upload)) {
+            Ok(multipart_upload_id) => multipart_upload_id,
+            Err(err) => {
+                let _ = upload::update_status(conn, upload.id, UploadStatus::Failed);
+
+                return Err(storage_error(err));
+            }
+        };
+
+    upload::update_multipart_upload_id(conn, upload.id, Some(&multipart_upload_id))?;
+
+    Ok(MultipartUpload::new(&upload))
+}
+
+/// Pre-signs urls for uploading the given parts. Parts can be requested again whenever their
+/// urls expire, or to retry them.
+pub(crate) fn part_urls(
+    conn: &PgConnection,
+    user: &User,
+    file_id: &str,
+    part_numbers: &[i64],
+) -> Result<Vec<PartUrl>, MultipartError> {
+    let (upload, multipart_upload_id) = pending_multipart(conn, user, file_id)?;
+    let multipart_upload = MultipartUpload::new(&upload);
+
+    if part_numbers.len() > MAX_PART_URLS {
+        return Err(MultipartError::TooManyParts);
+    }
+
+    if let Some(part_number) = part_numbers
+        .iter()
+        .find(|part_number| **part_number < 1 || **part_number > multipart_upload.part_count)
+    {
+        return Err(MultipartError::InvalidPartNumber(*part_number));
+    }
+
+    let file_name = storage_file_name(&upload);
+
+    Ok(part_numbers
+        .iter()
+        .map(|part_number| PartUrl {
+            part_number: *part_number,
+            url: s3_client::generate_signed_part_url(
+                "uploads",
+                &file_name,
+                &multipart_upload_id,
+                *part_number,
+            ),
+        })
+        .collect())
+}
+
+/// Lists the parts received so far, so an interrupted upload can carry on with the missing ones.
+pub(crate) fn parts(
+    conn: &PgConnection,
+    user: &User,
+    file_id: &str,
+) -> Result<(MultipartUpload, Vec<ReceivedPart>), MultipartError> {
+    let (upload, multipart_upload_id) = pending_multipart(conn, user, file_id)?;
+
+    let parts = s3_client::list_parts("uploads", &storage_file_name(&upload), &multipart_upload_id)
+        .map_err(storage_error)?
+        .into_iter()
+        .map(|part| ReceivedPart {
+            part_number: part.part_number,
+            size: part.size,
+        })
+        .collect();
+
+    Ok((MultipartUpload::new(&upload), parts))
+}
+
+/// Joins the received parts into the uploaded file, once every part has been received. The
+/// upload can be finalized afterwards.
+pub(crate) fn complete(
+    conn: &PgConnection,
+    user: &User,
+    file_id: &str,
+) -> Result<Upload, MultipartError> {
+    let (upload, multipart_upload_id) = pending_multipart(conn, user, file_id)?;
+    let multipart_upload = MultipartUpload::new(&upload);
+    let file_name = storage_file_name(&upload);
+
+    let parts: Vec<UploadedPart> =
+        s3_client::list_parts("uploads", &file_name, &multipart_upload_id)
+            .map_err(storage_error)?;
+
+    let missing_parts = (1..=multipart_upload.part_count)
+        .filter(|part_number| !parts.iter().any(|part| part.part_number == *part_number))
+        .collect::<Vec<i64>>();
+
+    if !missing_parts.is_empty() {
+        return Err(MultipartError::MissingParts(missing_parts));
+    }
+
+    s3_client::complete_multipart_upload("uploads", &file_name, &multipart_upload_id, &parts)
+        .map_err(storage_error)?;
+
+    upload::update_multipart_upload_id(conn, upload.id, None)?;
+
+    Ok(upload)
+}
+
+/// Gives up on a multipart upload, discarding the parts received and failing the pending upload.
+pub(crate) fn abort(conn: &PgConnection, user: &User, file_id: &str) -> Result<(), MultipartError> {
+    let (upload, multipart_upload_id) = pending_multipart(conn, user, file_id)?;
+
+    if let Err(err) = s3_client::abort_multipart_upload(
+        "uploads",
+        &storage_file_name(&upload),
+        &multipart_upload_id,
+    ) {
+        // Storage cleans up abandoned multipart uploads eventually, so this isn't fatal.
+        warn!(
+            "[multipart_upload] could not abort {}: {}",
+            upload.file_id, err
+        );
+    }
+
+    upload::update_multipart_upload_id(conn, upload.id, None)?;
+    upload::update_status(conn, upload.id, UploadStatus::Failed)?;
+
+    Ok(())
+}
@@ -1,7 +1,12 @@
 use serde_json::json;
 
 use crate::config;
-use crate::models::{upload::Upload, upload_comment::UploadComment, user::User};
+use crate::models::{
+    saved_search::SavedSearch,
+    upload::{FullUpload, Upload},
+    upload_comment::UploadComment,
+    user::User,
+};
 
 /// Notify Contributor Discord that a new pending upload has been submitted for approval.
 pub fn notify_pending_upload(upload: &Upload, user: &User) {
@@ -109,3 +114,51 @@ pub fn notify_new_comment(comment: &UploadComment, upload: &Upload, user: &User)
 
     let _ = client.post(&webhook_url).json(&json).send();
 }
+
+/// Notify a saved search's webhook about uploads that newly match it.
+pub fn notify_saved_search_matches(
+    saved_search: &SavedSearch,
+    uploads: &[FullUpload],
+    total_count: i64,
+) {
+    let webhook_url = match &saved_search.webhook_url {
+        Some(webhook_url) => webhook_url,
+        None => return,
+    };
+
+    let search_url = format!(
+        "https://spin-archive.org/?{}",
+        url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("q", &saved_search.query)
+            .finish()
+    );
+
+    let embeds = uploads
+        .iter()
+        .map(|upload| {
+            json!({
+              "title": upload.file_name.clone().unwrap_or(upload.file_id.clone()),
+              "thumbnail": {
+                "url": upload.get_thumbnail_url(),
+              },
+              "footer": {
+                "text": upload.tag_string,
+              },
+              "url": format!("https://spin-archive.org/u/{}", upload.file_id),
+              "color": 7506394,
+              "author": {
+                "name": upload.uploader_username,
+              }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let json = json!({
+      "content": format!("{} new upload(s) matching saved search [{}]({})", total_count, saved_search.name, search_url),
+      "embeds": embeds,
+    });
+
+    let client = reqwest::blocking::Client::new();
+
+    let _ = client.post(webhook_url).json(&json).send();
+}
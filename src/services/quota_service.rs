@@ -0,0 +1,75 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::PgConnection;
+use serde::Serialize;
+
+use crate::config;
+use crate::models::upload;
+use crate::models::user::{User, UserRole};
+
+/// How far back uploads count towards the daily upload limit, in hours.
+const QUOTA_WINDOW_HOURS: i64 = 24;
+
+/// How many uploads a user can still start, over a rolling 24 hour window.
+#[derive(Debug, Clone, Serialize)]
+pub struct Quota {
+    pub limit: i64,
+    pub used: i64,
+    pub remaining: i64,
+
+    /// When the oldest upload in the window stops counting, freeing up room for another.
+    pub resets_at: Option<NaiveDateTime>,
+}
+
+impl Quota {
+    pub fn is_exceeded(&self) -> bool {
+        self.remaining <= 0
+    }
+
+    /// Explains why no more uploads can be started, for error messages.
+    pub fn exceeded_reason(&self) -> String {
+        match self.resets_at {
+            Some(resets_at) => format!(
+                "Daily upload limit of {} reached, try again after {} UTC",
+                self.limit,
+                resets_at.format("%Y-%m-%d %H:%M")
+            ),
+            None => "You can't upload right now".to_owned(),
+        }
+    }
+}
+
+/// The default daily upload limit of a role, which can be changed through `config`.
+pub fn role_limit(role: UserRole) -> i64 {
+    let default = match role {
+        UserRole::Limited => 0,
+        UserRole::Registered => 20,
+        UserRole::Contributor => 200,
+        UserRole::Moderator => 500,
+        UserRole::Admin => 1000,
+    };
+
+    config::get_daily_upload_limit(&role.to_string(), default)
+}
+
+/// The user's daily upload limit, their override if an admin set one or else their role's.
+pub fn daily_limit(user: &User) -> i64 {
+    user.daily_upload_limit
+        .map(i64::from)
+        .unwrap_or_else(|| role_limit(user.role))
+}
+
+/// Works out how much of their quota a user has used. Every upload started counts, whether or
+/// not it was finished.
+pub fn quota(conn: &PgConnection, user: &User) -> Quota {
+    let now = Utc::now().naive_utc();
+    let window = Duration::hours(QUOTA_WINDOW_HOURS);
+    let (used, oldest) = upload::started_since(conn, user.id, now - window);
+    let limit = daily_limit(user);
+
+    Quota {
+        limit,
+        used,
+        remaining: (limit - used).max(0),
+        resets_at: oldest.map(|oldest| oldest + window),
+    }
+}
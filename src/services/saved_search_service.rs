@@ -0,0 +1,194 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::PgConnection;
+use log::{debug, warn};
+use thiserror::Error;
+
+use crate::config;
+use crate::models::saved_search::{self, NewSavedSearch, SavedSearch};
+use crate::models::upload_query::{Filter, UploadQuery};
+use crate::models::user::User;
+use crate::search::{self, SearchError};
+use crate::services::notification_service;
+
+pub use crate::models::saved_search::{by_id, by_user, delete};
+
+/// How many of the new uploads are included in a single notification.
+const NOTIFICATION_UPLOAD_LIMIT: i64 = 5;
+
+#[derive(Error, Debug)]
+pub(crate) enum SavedSearchError {
+    #[error("Saved search needs a name")]
+    MissingName,
+
+    #[error("{0}")]
+    InvalidQuery(#[from] SearchError),
+
+    #[error(
+        "Webhook URL must be an https:// URL on {}",
+        config::get_saved_search_webhook_hosts().join(" or ")
+    )]
+    InvalidWebhookUrl,
+
+    #[error("Error occured in database")]
+    DatabaseError,
+}
+
+/// Saves a search query for this user, checking that the query is valid first.
+pub(crate) fn new(
+    conn: &PgConnection,
+    user: &User,
+    name: &str,
+    query: &str,
+    webhook_url: Option<&str>,
+) -> Result<SavedSearch, SavedSearchError> {
+    let name = name.trim();
+    let query = query.trim();
+    let webhook_url = webhook_url
+        .map(|webhook_url| webhook_url.trim())
+        .filter(|webhook_url| !webhook_url.is_empty());
+
+    if name.is_empty() {
+        return Err(SavedSearchError::MissingName);
+    }
+
+    search::parse(conn, query, None, Some(user))?;
+
+    if let Some(webhook_url) = webhook_url {
+        if !is_allowed_webhook_url(webhook_url) {
+            return Err(SavedSearchError::InvalidWebhookUrl);
+        }
+    }
+
+    let new_saved_search = NewSavedSearch {
+        user_id: user.id,
+        name: name.to_owned(),
+        query: query.to_owned(),
+        webhook_url: webhook_url.map(|webhook_url| webhook_url.to_owned()),
+    };
+
+    saved_search::insert(conn, &new_saved_search).map_err(|_| SavedSearchError::DatabaseError)
+}
+
+/// Whether a webhook URL is an https:// URL on one of the allowed hosts, on the default port.
+fn is_allowed_webhook_url(webhook_url: &str) -> bool {
+    let url = match url::Url::parse(webhook_url) {
+        Ok(url) => url,
+        Err(_) => return false,
+    };
+
+    let host = match url.host() {
+        Some(url::Host::Domain(host)) => host.to_lowercase(),
+        _ => return false,
+    };
+
+    url.scheme() == "https"
+        && url.port().is_none()
+        && url.username().is_empty()
+        && url.password().is_none()
+        && config::get_saved_search_webhook_hosts().contains(&host)
+}
+
+/// Builds the upload query for uploads matching the saved search that were completed after
+/// `since`. Uploads are only visible once completed, which can be long after they were created.
+///
+/// Runs as the user that saved the search, so it can't see more than they could on the index.
+fn matches_since(
+    conn: &PgConnection,
+    user: &User,
+    saved_search: &SavedSearch,
+    since: NaiveDateTime,
+) -> Result<UploadQuery, SearchError> {
+    let search_query = search::parse(conn, &saved_search.query, Some("newest"), Some(user))?;
+
+    Ok(search::upload_query(conn, &search_query).filter(Filter::CompletedAfter(since)))
+}
+
+/// Counts the uploads matching a saved search since the user last looked at its results.
+fn count_new_matches(conn: &PgConnection, user: &User, saved_search: &SavedSearch) -> i64 {
+    matches_since(conn, user, saved_search, saved_search.last_seen_at)
+        .ok()
+        .and_then(|upload_query| upload_query.count(conn).ok())
+        .unwrap_or(0)
+}
+
+/// Marks all current results of the saved search as seen.
+pub fn mark_seen(conn: &PgConnection, saved_search: &SavedSearch) {
+    let _ = saved_search::update_last_seen(conn, saved_search.id, Utc::now().naive_utc());
+}
+
+/// Checks every saved search for new matches. Stores how many uploads matched since the results
+/// were last seen, and sends a webhook notification for saved searches that had new matches since
+/// the last run.
+pub fn check_new_matches(conn: &PgConnection) {
+    let saved_searches = saved_search::all_with_users(conn);
+
+    debug!(
+        "[saved_search_service] checking {} saved searches",
+        saved_searches.len()
+    );
+
+    for (saved_search, user) in saved_searches.iter() {
+        let new_match_count = count_new_matches(conn, user, saved_search);
+
+        let _ = saved_search::update_new_match_count(
+            conn,
+            saved_search.id,
+            saved_search.last_seen_at,
+            new_match_count,
+        );
+
+        if saved_search.webhook_url.is_some() {
+            notify_new_matches(conn, user, saved_search);
+        }
+    }
+}
+
+/// Sends a webhook notification if the saved search had new matches since the last run.
+fn notify_new_matches(conn: &PgConnection, user: &User, saved_search: &SavedSearch) {
+    let checked_at = Utc::now().naive_utc();
+
+    // Webhooks saved before the allowed hosts were restricted, or since removed from them.
+    if !saved_search
+        .webhook_url
+        .as_deref()
+        .map_or(false, is_allowed_webhook_url)
+    {
+        warn!(
+            "[saved_search_service] skipping saved search {}: webhook host is not allowed",
+            saved_search.id
+        );
+        return;
+    }
+
+    let upload_query = match matches_since(conn, user, saved_search, saved_search.last_notified_at)
+    {
+        Ok(upload_query) => upload_query,
+        Err(err) => {
+            warn!(
+                "[saved_search_service] skipping saved search {}: {}",
+                saved_search.id, err
+            );
+            return;
+        }
+    };
+
+    let uploads = match upload_query
+        .paginate(1, NOTIFICATION_UPLOAD_LIMIT)
+        .load(conn)
+    {
+        Ok(uploads) => uploads,
+        Err(err) => {
+            warn!(
+                "[saved_search_service] could not load matches for saved search {}: {}",
+                saved_search.id, err
+            );
+            return;
+        }
+    };
+
+    if let Some(total_count) = uploads.first().map(|upload| upload.count) {
+        notification_service::notify_saved_search_matches(saved_search, &uploads, total_count);
+    }
+
+    let _ = saved_search::update_last_notified(conn, saved_search.id, checked_at);
+}
@@ -0,0 +1,155 @@
+use diesel::prelude::*;
+use diesel::PgConnection;
+use log::{debug, warn};
+use thiserror::Error;
+
+use crate::models::tag_alias::{self, NewTagAlias, TagAlias};
+use crate::models::upload::{self, Upload};
+use crate::models::user::User;
+use crate::services::{tag_service, upload_service};
+
+pub use crate::models::tag_alias::{all, delete};
+
+/// How many uploads are rewritten at a time when a new alias is added.
+const REWRITE_BATCH_SIZE: i64 = 250;
+
+#[derive(Error, Debug)]
+pub(crate) enum TagAliasError {
+    #[error("Tags must be a single word")]
+    InvalidTag,
+
+    #[error("A tag can't be aliased to itself")]
+    SameTag,
+
+    #[error("`{0}` is already aliased to `{1}`")]
+    AlreadyAliased(String, String),
+
+    #[error("`{0}` is itself aliased to `{1}`, alias to that instead")]
+    ConsequentAliased(String, String),
+
+    #[error("Error occured in database")]
+    DatabaseError,
+}
+
+fn sanitize_tag(tag: &str) -> Result<String, TagAliasError> {
+    let tag = tag.trim().to_lowercase();
+
+    if tag.is_empty() || tag.contains(char::is_whitespace) {
+        return Err(TagAliasError::InvalidTag);
+    }
+
+    Ok(tag)
+}
+
+/// Aliases `antecedent` to `consequent`, existing aliases to `antecedent` are pointed at
+/// `consequent` as well.
+///
+/// Uploads already tagged with `antecedent` aren't touched, see [`rewrite_uploads`].
+pub(crate) fn new(
+    conn: &PgConnection,
+    user: &User,
+    antecedent: &str,
+    consequent: &str,
+) -> Result<TagAlias, TagAliasError> {
+    let antecedent = sanitize_tag(antecedent)?;
+    let consequent = sanitize_tag(consequent)?;
+
+    if antecedent == consequent {
+        return Err(TagAliasError::SameTag);
+    }
+
+    if let Some(existing) = tag_alias::by_antecedent(conn, &antecedent) {
+        return Err(TagAliasError::AlreadyAliased(
+            existing.antecedent_name,
+            existing.consequent_name,
+        ));
+    }
+
+    if let Some(existing) = tag_alias::by_antecedent(conn, &consequent) {
+        return Err(TagAliasError::ConsequentAliased(
+            existing.antecedent_name,
+            existing.consequent_name,
+        ));
+    }
+
+    let new_tag_alias = NewTagAlias {
+        antecedent_name: antecedent.clone(),
+        consequent_name: consequent.clone(),
+        creator_id: user.id,
+    };
+
+    let tag_alias = conn
+        .transaction::<_, diesel::result::Error, _>(|| {
+            tag_alias::retarget(conn, &antecedent, &consequent)?;
+            tag_alias::insert(conn, &new_tag_alias)
+        })
+        .map_err(|_| TagAliasError::DatabaseError)?;
+
+    tag_service::create_from_tag_string(conn, &consequent);
+
+    Ok(tag_alias)
+}
+
+/// Replaces aliased tags with the tags they're aliased to, dropping any duplicates.
+pub fn resolve(conn: &PgConnection, tags: Vec<String>) -> Vec<String> {
+    if tags.is_empty() {
+        return tags;
+    }
+
+    let tag_aliases = tag_alias::by_antecedents(conn, &tags);
+    let mut resolved: Vec<String> = Vec::with_capacity(tags.len());
+
+    for tag in tags {
+        let tag = tag_aliases
+            .iter()
+            .find(|tag_alias| tag_alias.antecedent_name == tag)
+            .map(|tag_alias| tag_alias.consequent_name.clone())
+            .unwrap_or(tag);
+
+        if !resolved.contains(&tag) {
+            resolved.push(tag);
+        }
+    }
+
+    resolved
+}
+
+/// Rewrites the `tag_string` of every upload tagged with the alias' antecedent, adding any tags
+/// the consequent implies. Each change is recorded in the audit log as made by the alias'
+/// creator, and each batch of uploads is rewritten in its own transaction. Returns how many
+/// uploads changed.
+pub fn rewrite_uploads(conn: &PgConnection, tag_alias: &TagAlias) -> usize {
+    let mut rewritten = 0;
+    let mut last_id = 0;
+
+    loop {
+        let uploads = upload::get_with_tag(
+            conn,
+            &tag_alias.antecedent_name,
+            last_id,
+            REWRITE_BATCH_SIZE,
+        );
+
+        let batch = uploads.iter().collect::<Vec<&Upload>>();
+
+        match upload_service::reapply_tag_rules(conn, tag_alias.creator_id, &batch) {
+            Ok(changed) => rewritten += changed,
+            Err(err) => warn!(
+                "[tag_alias_service] could not rewrite tags of uploads after {}: {}",
+                last_id, err
+            ),
+        }
+
+        debug!(
+            "[tag_alias_service] rewrite {} -> {} rewritten={}",
+            tag_alias.antecedent_name, tag_alias.consequent_name, rewritten
+        );
+
+        match uploads.last() {
+            Some(upload) if uploads.len() as i64 == REWRITE_BATCH_SIZE => last_id = upload.id,
+            _ => break,
+        }
+    }
+
+    rewritten
+}
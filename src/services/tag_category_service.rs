@@ -0,0 +1,111 @@
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::PgConnection;
+use thiserror::Error;
+
+use crate::models::tag_category::{self, NewTagCategory, TagCategory};
+
+pub use crate::models::tag_category::all;
+
+#[derive(Error, Debug)]
+pub(crate) enum TagCategoryError {
+    #[error("Tag category needs a name")]
+    MissingName,
+
+    #[error("Tag category prefix must be a single word")]
+    InvalidPrefix,
+
+    #[error("Colour must be a hex colour like #3a86ff")]
+    InvalidColour,
+
+    #[error("A tag category with that name or prefix already exists")]
+    AlreadyExists,
+
+    #[error("Error occured in database")]
+    DatabaseError,
+}
+
+impl From<DieselError> for TagCategoryError {
+    fn from(err: DieselError) -> TagCategoryError {
+        match err {
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                TagCategoryError::AlreadyExists
+            }
+            _ => TagCategoryError::DatabaseError,
+        }
+    }
+}
+
+fn is_hex_colour(colour: &str) -> bool {
+    match colour.strip_prefix('#') {
+        Some(hex) => {
+            (hex.len() == 3 || hex.len() == 6) && hex.chars().all(|c| c.is_ascii_hexdigit())
+        }
+        None => false,
+    }
+}
+
+fn validate(
+    name: &str,
+    prefix: &str,
+    display_order: i32,
+    colour: &str,
+) -> Result<NewTagCategory, TagCategoryError> {
+    let name = name.trim();
+    let prefix = prefix.trim().to_lowercase();
+    let colour = colour.trim().to_lowercase();
+
+    if name.is_empty() {
+        return Err(TagCategoryError::MissingName);
+    }
+
+    if prefix.is_empty() || prefix.contains(char::is_whitespace) {
+        return Err(TagCategoryError::InvalidPrefix);
+    }
+
+    if !is_hex_colour(&colour) {
+        return Err(TagCategoryError::InvalidColour);
+    }
+
+    Ok(NewTagCategory {
+        name: name.to_owned(),
+        prefix,
+        display_order,
+        colour,
+    })
+}
+
+/// Creates a tag category and moves every tag matching it into it.
+pub(crate) fn new(
+    conn: &PgConnection,
+    name: &str,
+    prefix: &str,
+    display_order: i32,
+    colour: &str,
+) -> Result<TagCategory, TagCategoryError> {
+    let new_tag_category = validate(name, prefix, display_order, colour)?;
+    let tag_category = tag_category::insert(conn, &new_tag_category)?;
+
+    Ok(tag_category)
+}
+
+/// Updates a tag category, tags are moved if its prefix changed.
+pub(crate) fn update(
+    conn: &PgConnection,
+    tag_category_id: i64,
+    name: &str,
+    prefix: &str,
+    display_order: i32,
+    colour: &str,
+) -> Result<TagCategory, TagCategoryError> {
+    let new_tag_category = validate(name, prefix, display_order, colour)?;
+    let tag_category = tag_category::update(conn, tag_category_id, &new_tag_category)?;
+
+    Ok(tag_category)
+}
+
+/// Deletes a tag category, its tags fall back to the next best matching category if any.
+pub(crate) fn delete(conn: &PgConnection, tag_category_id: i64) -> Result<(), TagCategoryError> {
+    tag_category::delete(conn, tag_category_id)?;
+
+    Ok(())
+}
@@ -0,0 +1,259 @@
+use diesel::PgConnection;
+use log::{debug, warn};
+use thiserror::Error;
+
+use crate::models::tag_implication::{self, NewTagImplication, TagImplication};
+use crate::models::upload::{self, Upload};
+use crate::models::user::User;
+use crate::services::{tag_alias_service, tag_service, upload_service};
+
+pub use crate::models::tag_implication::{all, delete};
+
+/// How many uploads are loaded at a time when previewing or applying an implication.
+const REWRITE_BATCH_SIZE: i64 = 250;
+
+/// How many of the affected uploads a preview lists.
+const PREVIEW_UPLOAD_LIMIT: usize = 50;
+
+#[derive(Error, Debug)]
+pub(crate) enum TagImplicationError {
+    #[error("Tags must be a single word")]
+    InvalidTag,
+
+    #[error("A tag can't imply itself")]
+    SameTag,
+
+    #[error("`{0}` already implies `{1}`")]
+    AlreadyImplied(String, String),
+
+    #[error("`{1}` already implies `{0}`, so this would create a cycle")]
+    Cycle(String, String),
+
+    #[error("Error occured in database")]
+    DatabaseError,
+}
+
+/// What committing a new implication would change.
+pub struct ImplicationPreview {
+    pub antecedent: String,
+    pub consequent: String,
+
+    /// Tags that get added to uploads tagged with the antecedent, the consequent and everything
+    /// it implies in turn.
+    pub added_tags: Vec<String>,
+
+    /// How many existing uploads would get new tags.
+    pub upload_count: usize,
+
+    /// The first few of those uploads, along with the tags they'd get.
+    pub uploads: Vec<(Upload, Vec<String>)>,
+}
+
+/// Lowercases a tag and resolves its alias, since implications only apply to resolved tags.
+fn sanitize_tag(conn: &PgConnection, tag: &str) -> Result<String, TagImplicationError> {
+    let tag = tag.trim().to_lowercase();
+
+    if tag.is_empty() || tag.contains(char::is_whitespace) {
+        return Err(TagImplicationError::InvalidTag);
+    }
+
+    tag_alias_service::resolve(conn, vec![tag])
+        .pop()
+        .ok_or(TagImplicationError::InvalidTag)
+}
+
+/// Checks that `antecedent` can imply `consequent`, returning both sanitized.
+fn validate(
+    conn: &PgConnection,
+    antecedent: &str,
+    consequent: &str,
+) -> Result<(String, String), TagImplicationError> {
+    let antecedent = sanitize_tag(conn, antecedent)?;
+    let consequent = sanitize_tag(conn, consequent)?;
+
+    if antecedent == consequent {
+        return Err(TagImplicationError::SameTag);
+    }
+
+    if tag_implication::by_names(conn, &antecedent, &consequent).is_some() {
+        return Err(TagImplicationError::AlreadyImplied(antecedent, consequent));
+    }
+
+    if implied_by(conn, &[consequent.clone()]).contains(&antecedent) {
+        return Err(TagImplicationError::Cycle(antecedent, consequent));
+    }
+
+    Ok((antecedent, consequent))
+}
+
+/// Gets every tag implied by the given tags, directly or through other implications.
+///
+/// This can include some of the given tags, if they're implied by the others.
+pub fn implied_by(conn: &PgConnection, tags: &[String]) -> Vec<String> {
+    let mut implied: Vec<String> = Vec::new();
+    let mut pending: Vec<String> = tags.to_vec();
+
+    // Every tag is only looked up once, so this ends even if the rules somehow have a cycle.
+    while !pending.is_empty() {
+        let tag_implications = tag_implication::by_antecedents(conn, &pending);
+
+        pending = Vec::new();
+
+        for tag_implication in tag_implications {
+            if !implied.contains(&tag_implication.consequent_name) {
+                implied.push(tag_implication.consequent_name.clone());
+                pending.push(tag_implication.consequent_name);
+            }
+        }
+    }
+
+    implied
+}
+
+/// Adds every tag implied by the given tags.
+pub fn apply(conn: &PgConnection, mut tags: Vec<String>) -> Vec<String> {
+    for tag in implied_by(conn, &tags) {
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+
+    tags
+}
+
+/// The tags an upload is missing out of `added_tags`.
+fn missing_tags(upload: &Upload, added_tags: &[String]) -> Vec<String> {
+    let tags = upload.tag_string.split_whitespace().collect::<Vec<&str>>();
+
+    added_tags
+        .iter()
+        .filter(|tag| !tags.contains(&tag.as_str()))
+        .cloned()
+        .collect()
+}
+
+/// Calls `f` with each batch of uploads tagged with `antecedent` that are missing some of
+/// `added_tags`, along with the tags each one is missing.
+fn for_each_affected_batch<F>(
+    conn: &PgConnection,
+    antecedent: &str,
+    added_tags: &[String],
+    mut f: F,
+) where
+    F: FnMut(Vec<(&Upload, Vec<String>)>),
+{
+    let mut last_id = 0;
+
+    loop {
+        let uploads = upload::get_with_tag(conn, antecedent, last_id, REWRITE_BATCH_SIZE);
+
+        let batch = uploads
+            .iter()
+            .map(|upload| (upload, missing_tags(upload, added_tags)))
+            .filter(|(_, missing)| !missing.is_empty())
+            .collect::<Vec<_>>();
+
+        if !batch.is_empty() {
+            f(batch);
+        }
+
+        match uploads.last() {
+            Some(upload) if uploads.len() as i64 == REWRITE_BATCH_SIZE => last_id = upload.id,
+            _ => break,
+        }
+    }
+}
+
+/// Works out which existing uploads a new implication would change, without saving anything.
+pub(crate) fn preview(
+    conn: &PgConnection,
+    antecedent: &str,
+    consequent: &str,
+) -> Result<ImplicationPreview, TagImplicationError> {
+    let (antecedent, consequent) = validate(conn, antecedent, consequent)?;
+    let added_tags = apply(conn, vec![consequent.clone()]);
+
+    let mut upload_count = 0;
+    let mut uploads = Vec::new();
+
+    for_each_affected_batch(conn, &antecedent, &added_tags, |batch| {
+        for (upload, missing) in batch {
+            upload_count += 1;
+
+            if uploads.len() < PREVIEW_UPLOAD_LIMIT {
+                uploads.push((upload.clone(), missing));
+            }
+        }
+    });
+
+    Ok(ImplicationPreview {
+        antecedent,
+        consequent,
+        added_tags,
+        upload_count,
+        uploads,
+    })
+}
+
+/// Makes `antecedent` imply `consequent` from now on.
+///
+/// Uploads already tagged with `antecedent` aren't touched, see [`rewrite_uploads`].
+pub(crate) fn new(
+    conn: &PgConnection,
+    user: &User,
+    antecedent: &str,
+    consequent: &str,
+) -> Result<TagImplication, TagImplicationError> {
+    let (antecedent, consequent) = validate(conn, antecedent, consequent)?;
+
+    let new_tag_implication = NewTagImplication {
+        antecedent_name: antecedent,
+        consequent_name: consequent,
+        creator_id: user.id,
+    };
+
+    let tag_implication = tag_implication::insert(conn, &new_tag_implication)
+        .map_err(|_| TagImplicationError::DatabaseError)?;
+
+    tag_service::create_from_tag_string(conn, &tag_implication.consequent_name);
+
+    Ok(tag_implication)
+}
+
+/// Adds the implied tags to every upload tagged with the implication's antecedent, recording each
+/// change in the audit log as made by the implication's creator. Each batch of uploads is changed
+/// in its own transaction. Returns how many uploads changed.
+pub fn rewrite_uploads(conn: &PgConnection, tag_implication: &TagImplication) -> usize {
+    let added_tags = apply(conn, vec![tag_implication.consequent_name.clone()]);
+    let mut rewritten = 0;
+
+    for_each_affected_batch(
+        conn,
+        &tag_implication.antecedent_name,
+        &added_tags,
+        |batch| {
+            let uploads = batch
+                .iter()
+                .map(|(upload, _)| *upload)
+                .collect::<Vec<&Upload>>();
+
+            match upload_service::reapply_tag_rules(conn, tag_implication.creator_id, &uploads) {
+                Ok(changed) => rewritten += changed,
+                Err(err) => warn!(
+                    "[tag_implication_service] could not add tags to {} uploads: {}",
+                    uploads.len(),
+                    err
+                ),
+            }
+        },
+    );
+
+    debug!(
+        "[tag_implication_service] rewrite {} -> {} rewritten={}",
+        tag_implication.antecedent_name, tag_implication.consequent_name, rewritten
+    );
+
+    tag_service::create_from_tag_string(conn, &added_tags.join(" "));
+
+    rewritten
+}
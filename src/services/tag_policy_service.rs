@@ -0,0 +1,155 @@
+// Rules for which tags uploads may use. The rules come from the environment, see `config`, except
+// for the known namespaces which are the prefixes of tag categories.
+
+use diesel::PgConnection;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use thiserror::Error;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::config;
+use crate::models::tag_category;
+
+/// Longest tag that can be used, in characters.
+const MAX_TAG_LENGTH: usize = 60;
+
+struct TagPolicy {
+    allowed_punctuation: String,
+    extra_namespaces: Vec<String>,
+    required_namespaces: Vec<String>,
+    banned: Vec<String>,
+}
+
+lazy_static! {
+    static ref POLICY: TagPolicy = TagPolicy {
+        allowed_punctuation: config::get_tag_allowed_punctuation(),
+        extra_namespaces: config::get_tag_extra_namespaces(),
+        required_namespaces: config::get_tag_required_namespaces(),
+        banned: config::get_tag_banned(),
+    };
+}
+
+/// A way a tag string breaks the tag policy.
+#[derive(Error, Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TagViolation {
+    #[error("`{tag}` is longer than {max_length} characters")]
+    TooLong { tag: String, max_length: usize },
+
+    #[error("`{tag}` contains `{character}`, which isn't allowed in tags")]
+    InvalidCharacter { tag: String, character: char },
+
+    #[error("`{tag}` has an empty namespace or name")]
+    EmptySegment { tag: String },
+
+    #[error("`{tag}` uses the unknown namespace `{namespace}`")]
+    UnknownNamespace { tag: String, namespace: String },
+
+    #[error("`{tag}` is a banned tag")]
+    Banned { tag: String },
+
+    #[error("Uploads need at least one `{namespace}/` tag")]
+    MissingNamespace { namespace: String },
+}
+
+impl TagViolation {
+    /// The tag that breaks the policy, if the violation is about a single tag.
+    pub fn tag(&self) -> Option<&str> {
+        match self {
+            TagViolation::TooLong { tag, .. }
+            | TagViolation::InvalidCharacter { tag, .. }
+            | TagViolation::EmptySegment { tag }
+            | TagViolation::UnknownNamespace { tag, .. }
+            | TagViolation::Banned { tag } => Some(tag),
+            TagViolation::MissingNamespace { .. } => None,
+        }
+    }
+}
+
+/// Joins violations into one message, for flash messages and logs.
+pub fn describe(violations: &[TagViolation]) -> String {
+    violations
+        .iter()
+        .map(|violation| violation.to_string())
+        .collect::<Vec<String>>()
+        .join("; ")
+}
+
+/// Normalizes a tag the same way everywhere: Unicode compatibility composition, then lowercase.
+pub fn normalize(tag: &str) -> String {
+    tag.nfkc().collect::<String>().to_lowercase()
+}
+
+/// The namespace of a tag, which is everything before its first `/`.
+pub fn namespace(tag: &str) -> Option<&str> {
+    tag.find('/').map(|index| &tag[..index])
+}
+
+/// Every namespace tags may use.
+fn known_namespaces(conn: &PgConnection) -> Vec<String> {
+    let mut namespaces = tag_category::all(conn)
+        .into_iter()
+        .map(|tag_category| tag_category.prefix.trim_end_matches('/').to_owned())
+        .collect::<Vec<String>>();
+
+    namespaces.extend(POLICY.extra_namespaces.iter().cloned());
+    namespaces
+}
+
+/// Checks normalized tags against the policy, returning every violation found.
+pub fn check_tags(conn: &PgConnection, tags: &[String]) -> Vec<TagViolation> {
+    let namespaces = known_namespaces(conn);
+    let mut violations = Vec::new();
+
+    for tag in tags {
+        if tag.chars().count() > MAX_TAG_LENGTH {
+            violations.push(TagViolation::TooLong {
+                tag: tag.clone(),
+                max_length: MAX_TAG_LENGTH,
+            });
+        }
+
+        if let Some(character) = tag
+            .chars()
+            .find(|c| !c.is_alphanumeric() && !POLICY.allowed_punctuation.contains(*c))
+        {
+            violations.push(TagViolation::InvalidCharacter {
+                tag: tag.clone(),
+                character,
+            });
+        }
+
+        if tag.split('/').any(str::is_empty) {
+            violations.push(TagViolation::EmptySegment { tag: tag.clone() });
+        } else if let Some(namespace) = namespace(tag) {
+            if !namespaces.iter().any(|known| known == namespace) {
+                violations.push(TagViolation::UnknownNamespace {
+                    tag: tag.clone(),
+                    namespace: namespace.to_owned(),
+                });
+            }
+        }
+
+        if POLICY.banned.contains(tag) {
+            violations.push(TagViolation::Banned { tag: tag.clone() });
+        }
+    }
+
+    violations
+}
+
+/// Checks that the final tags of a new upload include every required namespace.
+pub fn check_required_namespaces(tags: &[String]) -> Vec<TagViolation> {
+    POLICY
+        .required_namespaces
+        .iter()
+        .filter(|required| {
+            !tags
+                .iter()
+                .any(|tag| namespace(tag) == Some(required.as_str()))
+        })
+        .map(|required| TagViolation::MissingNamespace {
+            namespace: required.clone(),
+        })
+        .collect()
+}
@@ -0,0 +1,230 @@
+use diesel::prelude::*;
+use diesel::PgConnection;
+use log::debug;
+use thiserror::Error;
+
+use crate::models::tag::{self, NewTag, Tag};
+use crate::models::tag_alias::{self, NewTagAlias};
+use crate::models::tag_implication;
+use crate::models::upload::{self, Upload};
+use crate::search;
+use crate::services::{audit_service, tag_implication_service};
+
+/// How many uploads are loaded at a time while renaming a tag.
+const REWRITE_BATCH_SIZE: i64 = 250;
+
+#[derive(Error, Debug)]
+pub(crate) enum TagRenameError {
+    #[error("Tags must be a single word")]
+    InvalidTag,
+
+    #[error("A tag can't be renamed to itself")]
+    SameTag,
+
+    #[error("Tag `{0}` does not exist")]
+    TagNotFound(String),
+
+    #[error("`{0}` is aliased to `{1}`, rename to that instead")]
+    TargetAliased(String, String),
+
+    #[error("`{0}` is already aliased to `{1}`")]
+    AlreadyAliased(String, String),
+
+    #[error("Error occured in database")]
+    DatabaseError,
+}
+
+/// What renaming `from` to `to` would do, shown to moderators before it's done.
+pub struct RenamePreview {
+    pub from: String,
+    pub to: String,
+
+    /// Whether `to` already exists, in which case `from` is merged into it.
+    pub is_merge: bool,
+
+    /// How many uploads, in any status, would be retagged.
+    pub upload_count: i64,
+}
+
+fn sanitize_tag(tag: &str) -> Result<String, TagRenameError> {
+    let tag = tag.trim().to_lowercase();
+
+    if tag.is_empty() || tag.contains(char::is_whitespace) {
+        return Err(TagRenameError::InvalidTag);
+    }
+
+    Ok(tag)
+}
+
+/// Checks that `from` can be renamed to `to`, returning the tag being renamed, the tag it's being
+/// merged into if that already exists, and the sanitized new name.
+fn validate(
+    conn: &PgConnection,
+    from: &str,
+    to: &str,
+    leave_alias: bool,
+) -> Result<(Tag, Option<Tag>, String), TagRenameError> {
+    let from = sanitize_tag(from)?;
+    let to = sanitize_tag(to)?;
+
+    if from == to {
+        return Err(TagRenameError::SameTag);
+    }
+
+    let from_tag =
+        tag::by_name(conn, &from).ok_or_else(|| TagRenameError::TagNotFound(from.clone()))?;
+
+    if let Some(existing) = tag_alias::by_antecedent(conn, &to) {
+        return Err(TagRenameError::TargetAliased(
+            existing.antecedent_name,
+            existing.consequent_name,
+        ));
+    }
+
+    if leave_alias {
+        if let Some(existing) = tag_alias::by_antecedent(conn, &from) {
+            return Err(TagRenameError::AlreadyAliased(
+                existing.antecedent_name,
+                existing.consequent_name,
+            ));
+        }
+    }
+
+    Ok((from_tag, tag::by_name(conn, &to), to))
+}
+
+/// Works out what renaming `from` to `to` would do, without changing anything.
+pub(crate) fn preview(
+    conn: &PgConnection,
+    from: &str,
+    to: &str,
+    leave_alias: bool,
+) -> Result<RenamePreview, TagRenameError> {
+    let (from_tag, to_tag, to) = validate(conn, from, to, leave_alias)?;
+
+    Ok(RenamePreview {
+        upload_count: upload::count_with_tag(conn, &from_tag.name),
+        from: from_tag.name,
+        to,
+        is_merge: to_tag.is_some(),
+    })
+}
+
+/// Replaces `from` with `to` in a tag string, adding whatever `to` implies.
+fn rename_in_tag_string(conn: &PgConnection, upload: &Upload, from: &str, to: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+
+    for tag in upload.tag_string.split_whitespace() {
+        let tag = if tag == from { to } else { tag };
+
+        if !tags.iter().any(|existing| existing == tag) {
+            tags.push(tag.to_owned());
+        }
+    }
+
+    tag_implication_service::apply(conn, tags)
+}
+
+/// Renames `from` to `to`, or merges it into `to` if that tag already exists.
+///
+/// Every upload tagged with `from` is retagged in a single transaction, with one audit log entry
+/// per upload attributed to `changed_by`. Aliases to `from` and implications of it are moved over to
+/// `to`, and `from` is optionally aliased to `to` so it keeps working. When merging, the old tag
+/// is kept without any uploads so its wiki page isn't lost. Returns how many uploads changed.
+pub(crate) fn rename(
+    conn: &PgConnection,
+    changed_by: i32,
+    from: &str,
+    to: &str,
+    leave_alias: bool,
+) -> Result<usize, TagRenameError> {
+    let (from_tag, to_tag, to) = validate(conn, from, to, leave_alias)?;
+    let from = from_tag.name.clone();
+
+    let (upload_ids, recounted_tags) = conn
+        .transaction::<_, diesel::result::Error, _>(|| {
+            tag_alias::retarget(conn, &from, &to)?;
+            tag_implication::rename_tag(conn, &from, &to)?;
+
+            if to_tag.is_none() {
+                tag::rename(conn, from_tag.id, &to)?;
+            }
+
+            if leave_alias {
+                tag_alias::insert(
+                    conn,
+                    &NewTagAlias {
+                        antecedent_name: from.clone(),
+                        consequent_name: to.clone(),
+                        creator_id: changed_by,
+                    },
+                )?;
+            }
+
+            let mut upload_ids = Vec::new();
+            let mut recounted_tags = vec![from.clone(), to.clone()];
+            let mut last_id = 0;
+
+            loop {
+                let uploads = upload::get_with_tag(conn, &from, last_id, REWRITE_BATCH_SIZE);
+
+                for upload in uploads.iter() {
+                    let tags = rename_in_tag_string(conn, upload, &from, &to);
+                    let new_tag_string = tags.join(" ");
+
+                    upload::update_tag_string(conn, upload.id, &new_tag_string)?;
+
+                    // The tag string always changes here, so no entry means the insert failed.
+                    audit_service::create_audit_log(
+                        conn,
+                        "uploads",
+                        "tag_string",
+                        upload.id,
+                        changed_by,
+                        &upload.tag_string,
+                        &new_tag_string,
+                    )
+                    .ok_or(diesel::result::Error::RollbackTransaction)?;
+
+                    // Besides `from` and `to`, only newly implied tags change counts. They may
+                    // not have been used before, so they're created first.
+                    for tag in tags {
+                        if !upload.tag_string.split_whitespace().any(|old| old == tag)
+                            && !recounted_tags.contains(&tag)
+                        {
+                            tag::insert(conn, &NewTag { name: tag.clone() })?;
+                            recounted_tags.push(tag);
+                        }
+                    }
+
+                    upload_ids.push(upload.id);
+                }
+
+                match uploads.last() {
+                    Some(upload) if uploads.len() as i64 == REWRITE_BATCH_SIZE => {
+                        last_id = upload.id
+                    }
+                    _ => break,
+                }
+            }
+
+            for tag_name in recounted_tags.iter() {
+                tag::recount(conn, tag_name)?;
+            }
+
+            Ok((upload_ids, recounted_tags))
+        })
+        .map_err(|_| TagRenameError::DatabaseError)?;
+
+    search::index_uploads(conn, &upload_ids);
+
+    debug!(
+        "[tag_rename_service] rename {} -> {} retagged={} recounted={}",
+        from,
+        to,
+        upload_ids.len(),
+        recounted_tags.len()
+    );
+
+    Ok(upload_ids.len())
+}
@@ -1,17 +1,25 @@
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
 
-use diesel::PgConnection;
-use log::debug;
+use diesel::{PgConnection, QueryResult};
+use log::{debug, error};
 use serde::{Deserialize, Serialize};
 
 use crate::models::tag::{self, NewTag, Tag};
-use crate::models::upload::UploadStatus;
+use crate::models::tag_category::{self, TagCategory};
+use crate::models::tag_cooccurrence::{self, RelatedTag};
+use crate::models::upload::{Upload, UploadStatus};
+use crate::models::upload_query::TagFacet;
 use crate::schema::uploads;
+use crate::services::tag_alias_service;
 
-pub use crate::models::tag::{all, by_names};
+pub use crate::models::tag::{
+    all, by_id, by_name, by_names, count_drift, suggest, TagCountDrift, TagSuggestion,
+};
 
+/// Creates any tags in the tag string that don't exist yet, aliased tags are created as the tag
+/// they're aliased to.
 pub fn create_from_tag_string(conn: &PgConnection, tag_string: &str) {
-    let tags = sanitize_tags(tag_string);
+    let tags = tag_alias_service::resolve(conn, sanitize_tags(tag_string));
 
     for tag_name in tags.iter() {
         let new_tag = NewTag {
@@ -22,6 +30,30 @@ pub fn create_from_tag_string(conn: &PgConnection, tag_string: &str) {
     }
 }
 
+/// How long description snippets in tag suggestions can get, in characters.
+const SNIPPET_LENGTH: usize = 100;
+
+/// The first paragraph of a tag's wiki page, shortened to fit in a tag suggestion.
+pub fn description_snippet(description: &str) -> Option<String> {
+    let paragraph = description
+        .split("\n\n")
+        .map(|paragraph| paragraph.trim().trim_start_matches('#').trim())
+        .find(|paragraph| !paragraph.is_empty())?;
+
+    let paragraph = paragraph
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ");
+
+    if paragraph.chars().count() <= SNIPPET_LENGTH {
+        Some(paragraph)
+    } else {
+        let snippet = paragraph.chars().take(SNIPPET_LENGTH).collect::<String>();
+
+        Some(format!("{}…", snippet.trim_end()))
+    }
+}
+
 pub fn sanitize_tags<'a>(tags: &'a str) -> Vec<String> {
     tags.split_whitespace()
         .map(|str| str.to_lowercase())
@@ -67,8 +99,92 @@ pub fn rebuild(conn: &PgConnection) {
             break;
         }
     }
+
+    refresh_cooccurrences(conn);
+}
+
+/// Recomputes how often tags appear together. Done by the rebuild jobs instead of on every edit,
+/// since it scans every completed upload.
+pub fn refresh_cooccurrences(conn: &PgConnection) {
+    match tag_cooccurrence::refresh(conn) {
+        Ok(_) => debug!("[tag_service] refreshed tag co-occurrences"),
+        Err(error) => error!(
+            "[tag_service] couldn't refresh tag co-occurrences: {}",
+            error
+        ),
+    }
+}
+
+/// Tags that most often appear on the same uploads as `tag_name`, optionally only from one
+/// namespace, as of the last refresh.
+pub fn related_tags(
+    conn: &PgConnection,
+    tag_name: &str,
+    namespace: Option<&str>,
+    limit: i64,
+) -> Vec<RelatedTag> {
+    tag_cooccurrence::related(conn, tag_name, namespace, limit)
+}
+
+/// The tags an upload contributes to tag counts, only completed uploads count. A tag listed twice
+/// still only counts once.
+fn counted_tags(upload: &Upload) -> BTreeSet<&str> {
+    if upload.status == UploadStatus::Completed {
+        upload.tag_string.split_whitespace().collect()
+    } else {
+        BTreeSet::new()
+    }
+}
+
+/// How the count of each tag changes going from `old_tags` to `new_tags`.
+fn count_changes<'a>(
+    old_tags: &BTreeSet<&'a str>,
+    new_tags: &BTreeSet<&'a str>,
+) -> Vec<(&'a str, i32)> {
+    old_tags
+        .difference(new_tags)
+        .map(|tag| (*tag, -1))
+        .chain(new_tags.difference(old_tags).map(|tag| (*tag, 1)))
+        .collect()
+}
+
+/// Updates tag counts for a single upload changing from `old_upload` to `new_upload`, going by
+/// the difference in their tags and whether each is completed. `old_upload` is `None` for new
+/// uploads.
+pub fn update_upload_counts(
+    conn: &PgConnection,
+    old_upload: Option<&Upload>,
+    new_upload: &Upload,
+) -> QueryResult<usize> {
+    let old_tags = old_upload.map(counted_tags).unwrap_or_default();
+    let new_tags = counted_tags(new_upload);
+
+    let mut names: Vec<String> = Vec::new();
+    let mut changes: Vec<i32> = Vec::new();
+
+    for (tag, change) in count_changes(&old_tags, &new_tags) {
+        if change > 0 {
+            let _ = tag::insert(
+                conn,
+                &NewTag {
+                    name: tag.to_owned(),
+                },
+            );
+        }
+
+        names.push(tag.to_owned());
+        changes.push(change);
+    }
+
+    if names.is_empty() {
+        return Ok(0);
+    }
+
+    tag::adjust_counts(conn, &names, &changes)
 }
 
+/// Recounts every tag from scratch. Slow on a large archive, so it's only used to repair counts
+/// that have drifted, see [`count_drift`].
 pub fn rebuild_tag_counts(conn: &PgConnection) -> Vec<Tag> {
     use diesel::prelude::*;
 
@@ -76,7 +192,7 @@ pub fn rebuild_tag_counts(conn: &PgConnection) -> Vec<Tag> {
 
     let mut updated_tags = diesel::sql_query(
         "UPDATE tags SET upload_count = true_count FROM (
-        SELECT tag, COUNT(*) AS true_count
+        SELECT tag, COUNT(DISTINCT uploads.id) AS true_count
         FROM uploads,
         unnest(string_to_array(tag_string, ' ')) AS tag
         WHERE uploads.status = 2
@@ -90,6 +206,7 @@ pub fn rebuild_tag_counts(conn: &PgConnection) -> Vec<Tag> {
         "UPDATE tags SET upload_count = 0 WHERE upload_count != 0 AND name NOT IN (
         SELECT DISTINCT tag
         FROM uploads, unnest(string_to_array(tag_string, ' ')) AS tag
+        WHERE uploads.status = 2
         GROUP BY tag
       ) RETURNING tags.*",
     )
@@ -98,6 +215,30 @@ pub fn rebuild_tag_counts(conn: &PgConnection) -> Vec<Tag> {
 
     tags.append(&mut updated_tags);
     tags.append(&mut removed_tags);
+
+    refresh_cooccurrences(conn);
+
+    tags
+}
+
+/// Loads the tags of the given facets, with `upload_count` set to the number of matching uploads
+/// instead of the archive-wide count, in the same order as the facets.
+pub fn facet_tags(conn: &PgConnection, facets: &[TagFacet]) -> Vec<Tag> {
+    let names = facets
+        .iter()
+        .map(|facet| facet.tag.as_str())
+        .collect::<Vec<&str>>();
+
+    let mut tags = by_names(conn, &names);
+
+    tags.sort_by_key(|tag| facets.iter().position(|facet| facet.tag == tag.name));
+
+    for tag in tags.iter_mut() {
+        if let Some(facet) = facets.iter().find(|facet| facet.tag == tag.name) {
+            tag.upload_count = facet.count as i32;
+        }
+    }
+
     tags
 }
 
@@ -113,92 +254,128 @@ fn dedupe_tags<'a>(tag_strings: &Vec<String>, buffer: &'a mut HashSet<String>) {
 
 #[derive(Serialize, Deserialize)]
 pub struct TagGroup {
-    pub name: &'static str,
+    pub name: String,
+    pub colour: String,
     pub tags: Vec<Tag>,
 }
 
-impl TagGroup {
-    pub fn new(name: &'static str) -> TagGroup {
+impl From<TagCategory> for TagGroup {
+    fn from(tag_category: TagCategory) -> TagGroup {
         TagGroup {
-            name,
+            name: tag_category.name,
+            colour: tag_category.colour,
             tags: Vec::new(),
         }
     }
 }
 
-/// Groups tags into defined, common tag groups.
-pub fn group_tags(tags: Vec<Tag>) -> (Vec<TagGroup>, Vec<Tag>) {
-    let mut groups = vec![
-        TagGroup::new("Communities"),
-        TagGroup::new("Spinners"),
-        TagGroup::new("Collaboration Video"),
-        TagGroup::new("Promo Video"),
-        TagGroup::new("Solo Video"),
-        TagGroup::new("Editors"),
-        TagGroup::new("Events"),
-        TagGroup::new("Video Type"),
-        TagGroup::new("Organizers"),
-        TagGroup::new("Teams"),
-    ];
-
-    let remaining_tags = tags
+/// Groups tags by their category, in the categories' display order. Tags without a category are
+/// returned separately.
+pub fn group_tags(conn: &PgConnection, tags: Vec<Tag>) -> (Vec<TagGroup>, Vec<Tag>) {
+    let mut groups = tag_category::all(conn)
         .into_iter()
-        .filter(|tag| {
-            let mut any_matches = false;
-
-            if tag.name.starts_with("community") {
-                groups[0].tags.push(tag.clone());
-                any_matches = true;
-            }
-
-            if tag.name.starts_with("spinner") {
-                groups[1].tags.push(tag.clone());
-                any_matches = true;
-            }
-
-            if tag.name.starts_with("cv") {
-                groups[2].tags.push(tag.clone());
-                any_matches = true;
-            }
-
-            if tag.name.starts_with("pv") {
-                groups[3].tags.push(tag.clone());
-                any_matches = true;
-            }
-
-            if tag.name.starts_with("sv") {
-                groups[4].tags.push(tag.clone());
-                any_matches = true;
-            }
-
-            if tag.name.starts_with("editor") {
-                groups[5].tags.push(tag.clone());
-                any_matches = true;
-            }
-
-            if tag.name.starts_with("event") {
-                groups[6].tags.push(tag.clone());
-                any_matches = true;
-            }
-
-            if tag.name.starts_with("type") {
-                groups[7].tags.push(tag.clone());
-                any_matches = true;
-            }
-
-            if tag.name.starts_with("organizer") {
-                groups[8].tags.push(tag.clone());
-                any_matches = true;
-            }
-
-            if tag.name.starts_with("team") {
-                groups[9].tags.push(tag.clone());
-                any_matches = true;
-            }
-
-            return !any_matches;
-        })
-        .collect();
+        .map(|tag_category| (tag_category.id, TagGroup::from(tag_category)))
+        .collect::<Vec<(i64, TagGroup)>>();
+
+    let mut remaining_tags = Vec::new();
+
+    for tag in tags {
+        let group = tag.category_id.and_then(|category_id| {
+            groups
+                .iter_mut()
+                .find(|(id, _group)| *id == category_id)
+                .map(|(_id, group)| group)
+        });
+
+        match group {
+            Some(group) => group.tags.push(tag),
+            None => remaining_tags.push(tag),
+        }
+    }
+
+    let groups = groups.into_iter().map(|(_id, group)| group).collect();
 
     (groups, remaining_tags)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn upload(status: UploadStatus, tag_string: &str) -> Upload {
+        let created_at = NaiveDate::from_ymd(2020, 8, 1).and_hms(12, 0, 0);
+
+        Upload {
+            id: 1,
+            status,
+            file_id: "abc".to_owned(),
+            file_size: None,
+            file_name: None,
+            md5_hash: None,
+            uploader_user_id: None,
+            source: None,
+            created_at,
+            updated_at: created_at,
+            file_ext: "mp4".to_owned(),
+            tag_string: tag_string.to_owned(),
+            video_encoding_key: String::new(),
+            thumbnail_url: None,
+            video_url: None,
+            description: String::new(),
+            original_upload_date: None,
+        }
+    }
+
+    fn changes(old_upload: Option<&Upload>, new_upload: &Upload) -> Vec<(String, i32)> {
+        let old_tags = old_upload.map(counted_tags).unwrap_or_default();
+        let new_tags = counted_tags(new_upload);
+
+        count_changes(&old_tags, &new_tags)
+            .into_iter()
+            .map(|(tag, change)| (tag.to_owned(), change))
+            .collect()
+    }
+
+    #[test]
+    fn counts_only_completed_uploads() {
+        let pending = upload(UploadStatus::Pending, "a b");
+        let completed = upload(UploadStatus::Completed, "a b");
+
+        assert!(changes(None, &pending).is_empty());
+        assert_eq!(
+            changes(Some(&pending), &completed),
+            vec![("a".to_owned(), 1), ("b".to_owned(), 1)]
+        );
+        assert_eq!(
+            changes(Some(&completed), &upload(UploadStatus::Deleted, "a b")),
+            vec![("a".to_owned(), -1), ("b".to_owned(), -1)]
+        );
+    }
+
+    #[test]
+    fn counts_the_difference_in_tags() {
+        assert_eq!(
+            changes(
+                Some(&upload(UploadStatus::Completed, "a b c")),
+                &upload(UploadStatus::Completed, "b c d"),
+            ),
+            vec![("a".to_owned(), -1), ("d".to_owned(), 1)]
+        );
+    }
+
+    #[test]
+    fn counts_duplicate_tags_once() {
+        assert_eq!(
+            changes(None, &upload(UploadStatus::Completed, "a b a")),
+            vec![("a".to_owned(), 1), ("b".to_owned(), 1)]
+        );
+        assert_eq!(
+            changes(
+                Some(&upload(UploadStatus::Completed, "a a b")),
+                &upload(UploadStatus::Completed, "b b"),
+            ),
+            vec![("a".to_owned(), -1)]
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use diesel::prelude::*;
+use diesel::PgConnection;
+use thiserror::Error;
+
+use crate::models::tag::{self, Tag};
+use crate::models::tag_revision::{self, NewTagRevision, TagRevision};
+use crate::models::upload::{self, FullUpload};
+use crate::models::upload_query::Filter;
+use crate::models::user::User;
+use crate::search::SearchQuery;
+use crate::services::tag_service;
+
+pub use crate::models::tag_revision::{by_id, by_tag as history};
+
+/// Longest wiki page that can be saved, in bytes.
+const MAX_BODY_LENGTH: usize = 20_000;
+
+/// How many related tags are shown on a wiki page.
+const RELATED_TAG_LIMIT: i64 = 30;
+
+#[derive(Error, Debug)]
+pub(crate) enum TagWikiError {
+    #[error("Tag does not exist")]
+    TagNotFound,
+
+    #[error("Revision does not exist")]
+    RevisionNotFound,
+
+    #[error("Wiki page is too long")]
+    TooLong,
+
+    #[error("Nothing was changed")]
+    Unchanged,
+
+    #[error("Error occured in database")]
+    DatabaseError,
+}
+
+/// A line of a diff between two revisions.
+pub enum DiffLine {
+    Added(String),
+    Removed(String),
+    Unchanged(String),
+}
+
+/// Saves a new revision of a tag's wiki page and makes it the current one.
+pub(crate) fn edit(
+    conn: &PgConnection,
+    user: &User,
+    tag: &Tag,
+    body: &str,
+    summary: &str,
+) -> Result<TagRevision, TagWikiError> {
+    let body = body.trim().replace("\r\n", "\n");
+
+    if body.len() > MAX_BODY_LENGTH {
+        return Err(TagWikiError::TooLong);
+    }
+
+    if body == tag.description {
+        return Err(TagWikiError::Unchanged);
+    }
+
+    let new_tag_revision = NewTagRevision {
+        tag_id: tag.id,
+        body,
+        summary: summary.trim().to_owned(),
+        editor_id: Some(user.id),
+    };
+
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        let tag_revision = tag_revision::insert(conn, &new_tag_revision)?;
+        tag::update_description(conn, tag.id, &tag_revision.body)?;
+
+        Ok(tag_revision)
+    })
+    .map_err(|_| TagWikiError::DatabaseError)
+}
+
+/// Restores an old revision by saving its body as a new revision, so the history is kept.
+pub(crate) fn revert(
+    conn: &PgConnection,
+    user: &User,
+    tag_revision_id: i64,
+) -> Result<(Tag, TagRevision), TagWikiError> {
+    let old_revision = by_id(conn, tag_revision_id).ok_or(TagWikiError::RevisionNotFound)?;
+    let tag = tag::by_id(conn, old_revision.tag_id).ok_or(TagWikiError::TagNotFound)?;
+
+    let tag_revision = edit(
+        conn,
+        user,
+        &tag,
+        &old_revision.body,
+        &format!("Reverted to revision #{}", old_revision.id),
+    )?;
+
+    Ok((tag, tag_revision))
+}
+
+/// Diffs a revision against the one before it, line by line.
+pub fn changes(conn: &PgConnection, tag_revision: &TagRevision) -> Vec<DiffLine> {
+    let previous_body = tag_revision::previous(conn, tag_revision)
+        .map(|previous| previous.body)
+        .unwrap_or_default();
+
+    diff(&previous_body, &tag_revision.body)
+}
+
+/// Line diff of `old` to `new`, from the longest common subsequence of their lines.
+pub fn diff(old: &str, new: &str) -> Vec<DiffLine> {
+    let old = old.lines().collect::<Vec<&str>>();
+    let new = new.lines().collect::<Vec<&str>>();
+
+    // lengths[i][j] is the length of the longest common subsequence of old[i..] and new[j..].
+    let mut lengths = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if old[i] == new[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::with_capacity(old.len().max(new.len()));
+    let (mut i, mut j) = (0, 0);
+
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            lines.push(DiffLine::Unchanged(old[i].to_owned()));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            lines.push(DiffLine::Removed(old[i].to_owned()));
+            i += 1;
+        } else {
+            lines.push(DiffLine::Added(new[j].to_owned()));
+            j += 1;
+        }
+    }
+
+    lines.extend(
+        old[i..]
+            .iter()
+            .map(|line| DiffLine::Removed((*line).to_owned())),
+    );
+    lines.extend(
+        new[j..]
+            .iter()
+            .map(|line| DiffLine::Added((*line).to_owned())),
+    );
+
+    lines
+}
+
+/// The most recent uploads tagged with the tag.
+pub fn recent_uploads(conn: &PgConnection, tag: &Tag, limit: i64) -> Vec<FullUpload> {
+    let (uploads, _page_count, _total_count) =
+        upload::index(conn, 1, limit, &tag_search_query(tag));
+
+    uploads
+}
+
+/// Tags that most often appear together with the tag, with `upload_count` set to how many uploads
+/// they share with it.
+pub fn related_tags(conn: &PgConnection, tag: &Tag) -> Vec<Tag> {
+    tag_service::related_tags(conn, &tag.name, None, RELATED_TAG_LIMIT)
+        .into_iter()
+        .map(|related| Tag {
+            upload_count: related.count as i32,
+            ..related.tag
+        })
+        .collect()
+}
+
+fn tag_search_query(tag: &Tag) -> SearchQuery {
+    SearchQuery {
+        filters: vec![Filter::AnyTag(vec![tag.name.clone()])],
+        ..SearchQuery::default()
+    }
+}
@@ -0,0 +1,124 @@
+// Removes uploads that were started but never finalized. Every started upload gets a pending row,
+// and often a file in storage, whether or not the user goes on to finish it.
+//
+// Pending uploads are abandoned once they're older than `PENDING_UPLOAD_MAX_AGE_HOURS`, except
+// for multipart uploads that are still receiving parts, whose age counts from their last part.
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::PgConnection;
+use log::{info, warn};
+
+use crate::config;
+use crate::models::upload::{self, Upload};
+use crate::s3_client;
+
+/// How many pending uploads are loaded at a time.
+const CLEANUP_BATCH_SIZE: i64 = 100;
+
+/// What a cleanup did.
+#[derive(Debug, Default)]
+pub struct CleanupSummary {
+    pub removed: usize,
+
+    /// Multipart uploads that received a part recently, so they're still in progress.
+    pub in_progress: usize,
+
+    /// Uploads whose files couldn't be deleted from storage, they're kept to retry next time.
+    pub failed: usize,
+}
+
+enum Outcome {
+    Removed,
+    InProgress,
+
+    /// The upload was finalized while it was being looked at.
+    Finalized,
+}
+
+/// Removes pending uploads older than the configured age, along with their files in storage.
+pub fn remove_abandoned_uploads(conn: &PgConnection) -> CleanupSummary {
+    let max_age = Duration::hours(config::get_pending_upload_max_age());
+    let before = Utc::now().naive_utc() - max_age;
+    let mut summary = CleanupSummary::default();
+    let mut last_id = 0;
+
+    loop {
+        let uploads = upload::pending_before(conn, before, last_id, CLEANUP_BATCH_SIZE);
+
+        for pending_upload in uploads.iter() {
+            match remove(conn, pending_upload, before) {
+                Ok(Outcome::Removed) => summary.removed += 1,
+                Ok(Outcome::InProgress) => summary.in_progress += 1,
+                Ok(Outcome::Finalized) => {}
+                Err(err) => {
+                    warn!(
+                        "[upload_cleanup] could not remove {}: {}",
+                        pending_upload.file_id, err
+                    );
+                    summary.failed += 1;
+                }
+            }
+        }
+
+        match uploads.last() {
+            Some(pending_upload) if uploads.len() as i64 == CLEANUP_BATCH_SIZE => {
+                last_id = pending_upload.id
+            }
+            _ => break,
+        }
+    }
+
+    info!(
+        "[upload_cleanup] removed {} abandoned uploads older than {} hours, {} in progress, {} failed",
+        summary.removed,
+        max_age.num_hours(),
+        summary.in_progress,
+        summary.failed
+    );
+
+    summary
+}
+
+/// Deletes the upload and its file, or the parts of its multipart upload. The upload is deleted
+/// first and kept if the file can't be, so an upload finalized in the meantime is left alone.
+/// Multipart uploads that received a part since `before` are kept too.
+fn remove(
+    conn: &PgConnection,
+    pending_upload: &Upload,
+    before: NaiveDateTime,
+) -> anyhow::Result<Outcome> {
+    let file_name = format!("{}.{}", pending_upload.file_id, pending_upload.file_ext);
+    let multipart_upload_id = upload::multipart_upload_id(conn, pending_upload.id);
+
+    if let Some(multipart_upload_id) = &multipart_upload_id {
+        let last_part_uploaded_at =
+            s3_client::last_part_uploaded_at("uploads", &file_name, multipart_upload_id)?;
+
+        if last_part_uploaded_at.map_or(false, |uploaded_at| uploaded_at >= before) {
+            return Ok(Outcome::InProgress);
+        }
+    }
+
+    conn.transaction::<_, anyhow::Error, _>(|| {
+        if upload::delete_pending(conn, pending_upload.id)? == 0 {
+            return Ok(Outcome::Finalized);
+        }
+
+        if let Some(multipart_upload_id) = multipart_upload_id {
+            // Storage expires abandoned multipart uploads by itself too, so this isn't fatal.
+            if let Err(err) =
+                s3_client::abort_multipart_upload("uploads", &file_name, &multipart_upload_id)
+            {
+                warn!(
+                    "[upload_cleanup] could not abort multipart upload of {}: {}",
+                    pending_upload.file_id, err
+                );
+            }
+        }
+
+        s3_client::delete_object("uploads", &file_name)?;
+
+        Ok(Outcome::Removed)
+    })
+}
@@ -10,14 +10,19 @@ use crate::models::tag::Tag;
 use crate::models::upload::{
     self, FullUpload, NewImmediateUpload, PendingUpload, UpdateUpload, Upload, UploadStatus,
 };
-use crate::models::user::User;
+use crate::models::user::{self, User};
+use crate::s3_client;
 use crate::schema::upload_views;
-use crate::services::{audit_service, encoder_service, tag_service};
+use crate::search;
+use crate::services::quota_service::{self, Quota};
+use crate::services::tag_policy_service::{self, TagViolation};
+use crate::services::{
+    audit_service, encoder_service, tag_alias_service, tag_implication_service, tag_service,
+};
 
 pub use crate::models::upload::{
     get_by_file_id, get_by_md5, get_by_original_file, get_by_source, get_pending_approval_uploads,
-    get_upload_count_by_user_id, insert_immediate_upload, random, update_md5, update_status,
-    where_md5,
+    get_upload_count_by_user_id, insert_immediate_upload, random, update_md5, where_md5,
 };
 
 #[derive(Insertable)]
@@ -36,6 +41,47 @@ pub(crate) enum UploadError {
 
     #[error("Upload was not found")]
     NotFound,
+
+    #[error("{}", tag_policy_service::describe(.0))]
+    InvalidTags(Vec<TagViolation>),
+
+    #[error("{}", .0.exceeded_reason())]
+    QuotaExceeded(Quota),
+
+    #[error("Multipart upload has not been completed")]
+    MultipartIncomplete,
+
+    #[error("Uploaded file was not found, it needs to be uploaded again")]
+    ObjectMissing,
+
+    #[error("Uploaded file is {actual} bytes, expected {expected} bytes")]
+    SizeMismatch { expected: i64, actual: i64 },
+
+    #[error("Uploaded file doesn't match its MD5 hash, it needs to be uploaded again")]
+    ChecksumMismatch,
+
+    #[error("Could not check the uploaded file")]
+    StorageError,
+}
+
+impl From<diesel::result::Error> for UploadError {
+    fn from(_err: diesel::result::Error) -> Self {
+        UploadError::DatabaseError
+    }
+}
+
+/// Checks that the user can start another upload. Locks the user until the end of the
+/// transaction, so concurrent uploads can't both take the last spot.
+fn check_quota(conn: &PgConnection, user: &User) -> Result<(), UploadError> {
+    user::lock(conn, user.id)?;
+
+    let quota = quota_service::quota(conn, user);
+
+    if quota.is_exceeded() {
+        return Err(UploadError::QuotaExceeded(quota));
+    }
+
+    Ok(())
 }
 
 pub(crate) fn immediate_upload(
@@ -51,7 +97,7 @@ pub(crate) fn immediate_upload(
     description: &str,
     original_upload_date: NaiveDate,
 ) -> Result<Upload, UploadError> {
-    let new_tag_string = sanitize_tags(tag_string);
+    let new_tag_string = sanitize_tags(conn, tag_string);
 
     let immediate_upload = NewImmediateUpload {
         status: UploadStatus::Completed,
@@ -68,18 +114,18 @@ pub(crate) fn immediate_upload(
         file_size,
     };
 
-    match upload::insert_immediate_upload(&conn, &immediate_upload)
-        .map_err(|_| UploadError::DatabaseError)
-    {
-        Ok(upload) => {
-            after_edit_hooks(&conn, &upload);
-            Ok(upload)
-        }
-        err => err,
-    }
+    let upload = conn.transaction::<_, UploadError, _>(|| {
+        check_quota(conn, user)?;
+
+        Ok(upload::insert_immediate_upload(&conn, &immediate_upload)?)
+    })?;
+
+    after_edit_hooks(&conn, None, &upload);
+
+    Ok(upload)
 }
 
-/// Creates a new pending upload.
+/// Creates a new pending upload, if the user hasn't used up their daily upload limit.
 pub(crate) fn new_pending_upload(
     conn: &PgConnection,
     user: &User,
@@ -99,7 +145,11 @@ pub(crate) fn new_pending_upload(
         md5_hash,
     };
 
-    upload::insert_pending_upload(&conn, &pending_upload).map_err(|_| UploadError::DatabaseError)
+    conn.transaction::<_, UploadError, _>(|| {
+        check_quota(conn, user)?;
+
+        Ok(upload::insert_pending_upload(&conn, &pending_upload)?)
+    })
 }
 
 /// Finalizes a pending upload, which means the user has finished uploading the file and
@@ -122,32 +172,41 @@ pub(crate) fn finalize_upload(
                 ..
             },
         ) => {
+            if upload::multipart_upload_id(conn, upload.id).is_some() {
+                return Err(UploadError::MultipartIncomplete);
+            }
+
+            verify_object(&upload)?;
+
             let update_upload = UpdateUpload {
                 id: upload.id,
                 status: UploadStatus::Processing,
-                tag_string: sanitize_tags(tags),
+                tag_string: validate_tags(conn, tags, None)?,
                 source: Some(source.to_owned()),
                 description: description.to_string(),
                 original_upload_date,
             };
 
             match upload::update(&conn, &update_upload) {
-                Ok(upload) => {
-                    after_edit_hooks(&conn, &upload);
+                Ok(new_upload) => {
+                    after_edit_hooks(&conn, Some(&upload), &new_upload);
 
-                    match encoder_service::enqueue_upload(&upload) {
+                    match encoder_service::enqueue_upload(&new_upload) {
                         Ok(_job) => {
-                            debug!("[encoding] Started job id {}", upload.video_encoding_key);
+                            debug!(
+                                "[encoding] Started job id {}",
+                                new_upload.video_encoding_key
+                            );
                         }
                         Err(e) => {
                             warn!(
                                 "[encoding] Job error: {:?} for job id {}",
-                                e, upload.video_encoding_key
+                                e, new_upload.video_encoding_key
                             );
                         }
                     }
 
-                    Ok(upload)
+                    Ok(new_upload)
                 }
                 Err(_err) => Err(UploadError::DatabaseError),
             }
@@ -157,6 +216,39 @@ pub(crate) fn finalize_upload(
     }
 }
 
+/// Checks that the file of a pending upload is in storage, with the size and MD5 hash the client
+/// gave when it started the upload.
+fn verify_object(upload: &Upload) -> Result<(), UploadError> {
+    let file_name = format!("{}.{}", upload.file_id, upload.file_ext);
+
+    let object = match s3_client::head_object("uploads", &file_name) {
+        Ok(Some(object)) => object,
+        Ok(None) => return Err(UploadError::ObjectMissing),
+        Err(err) => {
+            warn!("[upload] could not check {}: {}", file_name, err);
+            return Err(UploadError::StorageError);
+        }
+    };
+
+    if let Some(expected) = upload.file_size {
+        if object.size != expected {
+            return Err(UploadError::SizeMismatch {
+                expected,
+                actual: object.size,
+            });
+        }
+    }
+
+    // The ETag of a multipart upload isn't an MD5 of the file, those are only checked by size.
+    if let Some(ref md5_hash) = upload.md5_hash {
+        if !object.e_tag.contains('-') && !object.e_tag.eq_ignore_ascii_case(md5_hash) {
+            return Err(UploadError::ChecksumMismatch);
+        }
+    }
+
+    Ok(())
+}
+
 /// Updates an already published upload.
 pub(crate) fn update_upload(
     conn: &PgConnection,
@@ -169,7 +261,7 @@ pub(crate) fn update_upload(
 ) -> Result<Upload, UploadError> {
     match upload::get_by_file_id(&conn, &file_id) {
         Some(upload) => {
-            let new_tag_string = sanitize_tags(tags);
+            let new_tag_string = validate_tags(conn, tags, Some(&upload.tag_string))?;
 
             let update_upload = UpdateUpload {
                 id: upload.id,
@@ -211,9 +303,9 @@ pub(crate) fn update_upload(
             );
 
             match upload::update(&conn, &update_upload) {
-                Ok(upload) => {
-                    after_edit_hooks(&conn, &upload);
-                    Ok(upload)
+                Ok(new_upload) => {
+                    after_edit_hooks(&conn, Some(&upload), &new_upload);
+                    Ok(new_upload)
                 }
                 Err(_err) => Err(UploadError::DatabaseError),
             }
@@ -222,8 +314,77 @@ pub(crate) fn update_upload(
     }
 }
 
+/// Replaces the tags of an already published upload the same way [`update_upload`] does, leaving
+/// everything else as it is. The upload is returned as it was if its tags don't change.
+///
+/// Meant to run in a transaction, any part of the change that fails is returned as an error so
+/// the caller can roll back. The upload isn't reindexed for search, callers do that with
+/// [`search::index_uploads`] once their transaction commits.
+pub(crate) fn update_tags(
+    conn: &PgConnection,
+    user_id: i32,
+    upload: &Upload,
+    tags: &str,
+) -> Result<Upload, UploadError> {
+    let new_tag_string = validate_tags(conn, tags, Some(&upload.tag_string))?;
+
+    // The audit log doesn't record changes in case alone.
+    if new_tag_string.eq_ignore_ascii_case(&upload.tag_string) {
+        return Ok(upload.clone());
+    }
+
+    let new_upload = upload::update_tag_string(conn, upload.id, &new_tag_string)?;
+
+    audit_service::create_audit_log(
+        conn,
+        "uploads",
+        "tag_string",
+        upload.id,
+        user_id,
+        &upload.tag_string,
+        &new_tag_string,
+    )
+    .ok_or(UploadError::DatabaseError)?;
+
+    tag_service::create_from_tag_string(conn, &new_upload.tag_string);
+    tag_service::update_upload_counts(conn, Some(upload), &new_upload)?;
+
+    Ok(new_upload)
+}
+
+/// Runs the tags of every upload through [`update_tags`] again on behalf of `changed_by`, so
+/// aliases and implications added since they were tagged apply to them too.
+///
+/// The uploads are changed in a single transaction that's rolled back if any of them fails, and
+/// the ones that changed are reindexed once it commits. Returns how many uploads changed.
+pub(crate) fn reapply_tag_rules(
+    conn: &PgConnection,
+    changed_by: i32,
+    uploads: &[&Upload],
+) -> Result<usize, UploadError> {
+    let changed_ids = conn.transaction::<_, UploadError, _>(|| {
+        let mut changed_ids = Vec::new();
+
+        for upload in uploads {
+            // Only the tags an edit adds are checked against the tag policy, so passing the
+            // upload's own tags leaves aliases and implications as the only changes.
+            let new_upload = update_tags(conn, changed_by, upload, &upload.tag_string)?;
+
+            if new_upload.tag_string != upload.tag_string {
+                changed_ids.push(upload.id);
+            }
+        }
+
+        Ok(changed_ids)
+    })?;
+
+    search::index_uploads(conn, &changed_ids);
+
+    Ok(changed_ids.len())
+}
+
 pub fn delete(conn: &PgConnection, upload: &Upload, user: &User) -> QueryResult<Upload> {
-    upload::update_status(&conn, upload.id, UploadStatus::Deleted).and_then(|new_upload| {
+    update_status(&conn, upload, UploadStatus::Deleted).and_then(|new_upload| {
         audit_service::create_audit_log(
             &conn,
             "uploads",
@@ -238,17 +399,94 @@ pub fn delete(conn: &PgConnection, upload: &Upload, user: &User) -> QueryResult<
     })
 }
 
-pub fn after_edit_hooks(conn: &PgConnection, upload: &Upload) {
+/// Changes the status of an upload, keeping tag counts in step when it moves into or out of
+/// [`UploadStatus::Completed`].
+pub fn update_status(
+    conn: &PgConnection,
+    upload: &Upload,
+    status: UploadStatus,
+) -> QueryResult<Upload> {
+    let new_upload = upload::update_status(conn, upload.id, status)?;
+
+    let _ = tag_service::update_upload_counts(conn, Some(upload), &new_upload);
+
+    Ok(new_upload)
+}
+
+/// Runs after an upload is created or edited. `old_upload` is the upload before the edit, or
+/// `None` if it was just created.
+pub fn after_edit_hooks(conn: &PgConnection, old_upload: Option<&Upload>, upload: &Upload) {
     let _ = tag_service::create_from_tag_string(&conn, &upload.tag_string);
-    let _ = tag_service::rebuild_tag_counts(&conn);
+    let _ = tag_service::update_upload_counts(&conn, old_upload, upload);
+    search::index_upload(&conn, upload.id);
+}
+
+/// Normalizes a tag string and checks it against the tag policy, then resolves aliases and adds
+/// implied tags.
+///
+/// New uploads, without an `existing_tag_string`, also need a tag from every required namespace.
+/// Edits only check the tags they add, so uploads tagged before the policy can still be edited.
+pub(crate) fn validate_tags(
+    conn: &PgConnection,
+    tags: &str,
+    existing_tag_string: Option<&str>,
+) -> Result<String, UploadError> {
+    let tags = tags
+        .split_whitespace()
+        .map(tag_policy_service::normalize)
+        .collect::<Vec<_>>();
+
+    let mut violations = match existing_tag_string {
+        Some(existing_tag_string) => {
+            let existing_tags = existing_tag_string
+                .split_whitespace()
+                .map(tag_policy_service::normalize)
+                .collect::<Vec<_>>();
+
+            let added_tags = tags
+                .iter()
+                .filter(|tag| !existing_tags.contains(tag))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            tag_policy_service::check_tags(conn, &added_tags)
+        }
+        None => tag_policy_service::check_tags(conn, &tags),
+    };
+
+    let tags = tag_implication_service::apply(conn, tag_alias_service::resolve(conn, tags));
+
+    if existing_tag_string.is_none() {
+        violations.extend(tag_policy_service::check_required_namespaces(&tags));
+    }
+
+    if violations.is_empty() {
+        Ok(tags.join(" "))
+    } else {
+        Err(UploadError::InvalidTags(violations))
+    }
 }
 
-pub fn sanitize_tags<'a>(tags: &'a str) -> String {
-    tags.split_whitespace()
-        .map(|str| str.to_lowercase())
-        .filter(|str| str.len() <= 60)
-        .collect::<Vec<_>>()
-        .join(" ")
+/// Normalizes a tag string, replacing aliased tags with the tags they're aliased to and adding
+/// implied tags. Tags breaking the tag policy are dropped rather than rejected, for ingestors that
+/// can't ask anyone to fix them.
+pub fn sanitize_tags(conn: &PgConnection, tags: &str) -> String {
+    let mut tags = tags
+        .split_whitespace()
+        .map(tag_policy_service::normalize)
+        .collect::<Vec<_>>();
+
+    let violations = tag_policy_service::check_tags(conn, &tags);
+
+    tags.retain(|tag| {
+        !violations
+            .iter()
+            .any(|violation| violation.tag() == Some(tag.as_str()))
+    });
+
+    let tags = tag_alias_service::resolve(conn, tags);
+
+    tag_implication_service::apply(conn, tags).join(" ")
 }
 
 /// Increments the view count for an upload.
@@ -271,8 +509,6 @@ pub fn get_view_count(conn: &PgConnection, upload_id: i32) -> i64 {
 
 /// Gets the associated uploader user.
 pub fn get_uploader_user(conn: &PgConnection, upload: &Upload) -> User {
-    use crate::models::user;
-
     user::get_user_by_id(&conn, upload.uploader_user_id.expect("No uploader user")).unwrap()
 }
 
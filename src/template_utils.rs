@@ -2,13 +2,15 @@ use std::collections::hash_map::DefaultHasher;
 use std::env;
 use std::hash::Hasher;
 
-use chrono::{NaiveDateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use lazy_static::lazy_static;
 use rocket::http::hyper::header::{ETag, EntityTag};
-use rocket::http::{Method, Status};
+use rocket::http::uri::Uri;
+use rocket::http::{ContentType, Method, Status};
 use rocket::request::FlashMessage;
 use rocket::request::Request;
-use rocket::response::{self, content::Html as HtmlCt, Responder, Response};
+use rocket::response::content::{Content, Html as HtmlCt};
+use rocket::response::{self, Responder, Response};
 
 use crate::models::user::User;
 use crate::templates::Html;
@@ -34,6 +36,81 @@ pub fn to_tag_url(name: &str) -> String {
     format!("/?q={}", name)
 }
 
+/// Percent-encodes a tag name for use in a path, namespaced tags keep their `/` as path
+/// separators.
+pub fn encode_tag_path(name: &str) -> String {
+    name.split('/')
+        .map(|segment| Uri::percent_encode(segment).into_owned())
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+/// Link to a tag's wiki page.
+pub fn to_tag_wiki_url(name: &str) -> String {
+    format!("/tags/{}", encode_tag_path(name))
+}
+
+/// Sort orders offered on the index page, as (`?sort=` value, label).
+pub const SORT_OPTIONS: [(&str, &str); 9] = [
+    ("newest", "Newest"),
+    ("oldest", "Oldest"),
+    ("original", "Original date"),
+    ("original_desc", "Original date (newest)"),
+    ("views", "Most viewed"),
+    ("comments", "Most commented"),
+    ("size", "Largest"),
+    ("random", "Random"),
+    ("relevance", "Relevance"),
+];
+
+/// Checks if `sort` is the given sort option, ignoring any random seed.
+pub fn is_current_sort(sort: &str, option: &str) -> bool {
+    sort.split(':').next() == Some(option)
+}
+
+/// Builds an index page URL for a search query, sort order and page number.
+pub fn index_url(query: &str, sort: &str, page: i64) -> String {
+    let mut params = url::form_urlencoded::Serializer::new(String::new());
+
+    if !query.is_empty() {
+        params.append_pair("q", query);
+    }
+
+    if !sort.is_empty() && sort != "newest" {
+        params.append_pair("sort", sort);
+    }
+
+    if page > 1 {
+        params.append_pair("page", &page.to_string());
+    }
+
+    let params = params.finish();
+
+    if params.is_empty() {
+        "/".to_string()
+    } else {
+        format!("/?{}", params)
+    }
+}
+
+/// Builds the Atom feed URL for a search query.
+pub fn feed_url(query: &str) -> String {
+    if query.is_empty() {
+        return "/feed.atom".to_string();
+    }
+
+    let params = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("q", query)
+        .finish();
+
+    format!("/feed.atom?{}", params)
+}
+
+/// Formats a UTC timestamp as RFC 3339, as used by Atom feeds.
+pub fn to_rfc3339(date: NaiveDateTime) -> String {
+    DateTime::<Utc>::from_utc(date, Utc).to_rfc3339()
+}
+
 pub fn static_file(content: String) -> Html<String> {
     Html(format!("{}?v={}", content, &**APP_VERSION))
 }
@@ -97,6 +174,32 @@ pub struct Pagination {
     pub total_count: i64,
 }
 
+/// Responds with `body`, or with `304 Not Modified` if the client already has the same body
+/// according to its `If-None-Match` header.
+fn respond_with_etag<'r>(
+    r: &Request<'_>,
+    content_type: ContentType,
+    body: Vec<u8>,
+) -> response::Result<'r> {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(&body);
+    let etag = format!("{:x}", hasher.finish());
+    if r.headers()
+        .get("If-None-Match")
+        .any(|s| s[1..s.len() - 1] == etag)
+    {
+        Response::build()
+            .status(Status::NotModified)
+            .header(ETag(EntityTag::strong(etag)))
+            .ok()
+    } else {
+        Response::build()
+            .merge(Content(content_type, body).respond_to(r)?)
+            .header(ETag(EntityTag::strong(etag)))
+            .ok()
+    }
+}
+
 #[derive(Debug)]
 pub struct Ructe(pub Vec<u8>);
 
@@ -106,29 +209,31 @@ impl<'r> Responder<'r> for Ructe {
         if r.method() != Method::Get || self.0.windows(6).any(|w| w == b"<form ") {
             return HtmlCt(self.0).respond_to(r);
         }
-        let mut hasher = DefaultHasher::new();
-        hasher.write(&self.0);
-        let etag = format!("{:x}", hasher.finish());
-        if r.headers()
-            .get("If-None-Match")
-            .any(|s| s[1..s.len() - 1] == etag)
-        {
-            Response::build()
-                .status(Status::NotModified)
-                .header(ETag(EntityTag::strong(etag)))
-                .ok()
-        } else {
-            Response::build()
-                .merge(HtmlCt(self.0).respond_to(r)?)
-                .header(ETag(EntityTag::strong(etag)))
-                .ok()
+        respond_with_etag(r, ContentType::HTML, self.0)
+    }
+}
+
+/// A rendered Atom feed, cached the same way as [`Ructe`] pages.
+#[derive(Debug)]
+pub struct Atom(pub Vec<u8>);
+
+impl<'r> Responder<'r> for Atom {
+    fn respond_to(self, r: &Request<'_>) -> response::Result<'r> {
+        let content_type = ContentType::new("application", "atom+xml");
+
+        if r.method() != Method::Get {
+            return Content(content_type, self.0).respond_to(r);
         }
+        respond_with_etag(r, content_type, self.0)
     }
 }
 
 #[macro_export]
 macro_rules! render {
     ($group:tt :: $page:tt ( $( $param:expr ),* ) ) => {
+        render!(Ructe, $group::$page($( $param ),*))
+    };
+    ($responder:ident, $group:tt :: $page:tt ( $( $param:expr ),* ) ) => {
         {
             use crate::templates;
 
@@ -139,7 +244,7 @@ macro_rules! render {
                     $param
                 ),*
             ).unwrap();
-            Ructe(res)
+            $responder(res)
         }
-    }
+    };
 }